@@ -91,6 +91,12 @@ mod other {
 
         #[link_name = "LINK_ADDR_RW"]
         static LINK_ADDR_RW: [u64; 0];
+
+        #[link_name = "COUNT_PAGES_RX"]
+        static COUNT_PAGES_RX: [u64; 0];
+
+        #[link_name = "COUNT_PAGES_RO"]
+        static COUNT_PAGES_RO: [u64; 0];
     }
 
     pub fn link_addr_boot() -> *const u8 {
@@ -112,4 +118,14 @@ mod other {
     pub fn link_addr_rw() -> *const u8 {
         (unsafe { LINK_ADDR_RW.as_ptr() }).cast()
     }
+
+    /// Returns the number of 4 KiB pages of the RX segment.
+    pub fn count_pages_rx() -> usize {
+        (unsafe { COUNT_PAGES_RX.as_ptr() }) as usize
+    }
+
+    /// Returns the number of 4 KiB pages of the RO segment.
+    pub fn count_pages_ro() -> usize {
+        (unsafe { COUNT_PAGES_RO.as_ptr() }) as usize
+    }
 }
@@ -0,0 +1,99 @@
+//! Global Descriptor Table (GDT) and Task State Segment (TSS) for ring 3.
+//!
+//! The boot code only provides a ring 0 code segment. Tasks loaded from
+//! modules run in ring 3, which additionally requires user segments, a TSS
+//! with the stack for exceptions from ring 3, and `syscall`/`sysret`.
+
+use core::cell::OnceCell;
+use lib::mem::stack::Stack;
+use lib::safe::Safe;
+use x86_64::instructions::segmentation::{Segment, CS, DS, ES, SS};
+use x86_64::instructions::tables::load_tss;
+use x86_64::registers::control::{Cr4, Cr4Flags};
+use x86_64::registers::model_specific::{Efer, EferFlags, LStar, SFMask, Star};
+use x86_64::registers::rflags::RFlags;
+use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector};
+use x86_64::structures::tss::TaskStateSegment;
+use x86_64::VirtAddr;
+
+/// Index in the interrupt stack table of the stack for NMIs and double faults.
+pub const IST_INDEX: u16 = 0;
+
+const STACK_SIZE: usize = 0x4000 /* 16 KiB */;
+
+/// Stack the CPU switches to on exceptions from ring 3.
+static mut PRIVILEGE_STACK: Stack<STACK_SIZE> = Stack::new();
+/// Stack for exceptions that may hit at any time, even on a broken stack.
+static mut IST_STACK: Stack<STACK_SIZE> = Stack::new();
+
+static TSS: Safe<OnceCell<TaskStateSegment>> = Safe::new(OnceCell::new());
+static GDT: Safe<OnceCell<(GlobalDescriptorTable, Selectors)>> = Safe::new(OnceCell::new());
+
+/// Segment selectors of the GDT. The order of the user segments is dictated
+/// by `sysret`.
+#[derive(Debug, Copy, Clone)]
+pub struct Selectors {
+    pub kernel_code: SegmentSelector,
+    pub kernel_data: SegmentSelector,
+    pub user_data: SegmentSelector,
+    pub user_code: SegmentSelector,
+    pub tss: SegmentSelector,
+}
+
+extern "C" {
+    /// Entry point of `syscall`, see [`crate::sandbox`].
+    fn sandbox_syscall_entry();
+}
+
+/// Replaces the GDT of the boot code and enables `syscall`. Must run before
+/// [`crate::idt::init`], which refers to the interrupt stack table.
+pub fn init() {
+    let tss = TSS.get_or_init(|| {
+        let mut tss = TaskStateSegment::new();
+        tss.privilege_stack_table[0] = VirtAddr::from_ptr(unsafe { PRIVILEGE_STACK.adjusted_top() });
+        tss.interrupt_stack_table[IST_INDEX as usize] = VirtAddr::from_ptr(unsafe { IST_STACK.adjusted_top() });
+        tss
+    });
+    let (gdt, selectors) = GDT.get_or_init(|| {
+        let mut gdt = GlobalDescriptorTable::new();
+        let selectors = Selectors {
+            kernel_code: gdt.add_entry(Descriptor::kernel_code_segment()),
+            kernel_data: gdt.add_entry(Descriptor::kernel_data_segment()),
+            user_data: gdt.add_entry(Descriptor::user_data_segment()),
+            user_code: gdt.add_entry(Descriptor::user_code_segment()),
+            tss: gdt.add_entry(Descriptor::tss_segment(tss)),
+        };
+        (gdt, selectors)
+    });
+
+    gdt.load();
+    unsafe {
+        CS::set_reg(selectors.kernel_code);
+        SS::set_reg(selectors.kernel_data);
+        DS::set_reg(selectors.kernel_data);
+        ES::set_reg(selectors.kernel_data);
+        load_tss(selectors.tss);
+    }
+
+    Star::write(
+        selectors.user_code,
+        selectors.user_data,
+        selectors.kernel_code,
+        selectors.kernel_data,
+    )
+    .unwrap();
+    LStar::write(VirtAddr::new(sandbox_syscall_entry as *const () as u64));
+    // Enter the kernel with interrupts disabled and a sane flags register
+    SFMask::write(
+        RFlags::INTERRUPT_FLAG
+            | RFlags::TRAP_FLAG
+            | RFlags::DIRECTION_FLAG
+            | RFlags::ALIGNMENT_CHECK,
+    );
+    unsafe { Efer::update(|flags| flags.insert(EferFlags::SYSTEM_CALL_EXTENSIONS)) };
+
+    // SMEP: The kernel never executes user pages
+    if 0 != unsafe { core::arch::x86_64::__cpuid_count(7, 0) }.ebx & (1 << 7) {
+        unsafe { Cr4::update(|flags| flags.insert(Cr4Flags::SUPERVISOR_MODE_EXECUTION_PROTECTION)) };
+    }
+}
@@ -1,3 +1,4 @@
+use crate::gdt;
 use core::cell::RefCell;
 use lib::safe::Safe;
 use x86_64::structures::idt::*;
@@ -11,8 +12,11 @@ pub fn init() {
     let mut idt = IDT.borrow_mut();
     idt.divide_error.set_handler_fn(exception_handlers::divide);
     idt.debug.set_handler_fn(exception_handlers::debug);
-    idt.non_maskable_interrupt
-        .set_handler_fn(exception_handlers::nmi);
+    unsafe {
+        idt.non_maskable_interrupt
+            .set_handler_fn(exception_handlers::nmi)
+            .set_stack_index(gdt::IST_INDEX);
+    }
     idt.breakpoint
         .set_handler_fn(exception_handlers::breakpoint);
     idt.overflow.set_handler_fn(exception_handlers::overflow);
@@ -22,8 +26,11 @@ pub fn init() {
         .set_handler_fn(exception_handlers::invalid_opcode);
     idt.device_not_available
         .set_handler_fn(exception_handlers::device_not_available);
-    idt.double_fault
-        .set_handler_fn(exception_handlers::double_fault);
+    unsafe {
+        idt.double_fault
+            .set_handler_fn(exception_handlers::double_fault)
+            .set_stack_index(gdt::IST_INDEX);
+    }
     idt.invalid_tss
         .set_handler_fn(exception_handlers::invalid_tss);
     idt.segment_not_present
@@ -67,8 +74,10 @@ pub fn init() {
 mod exception_handlers {
     use x86_64::structures::idt::{InterruptStackFrame, PageFaultErrorCode};
     use core::arch::x86_64 as core_x86;
+    use crate::sandbox::abort_if_user;
 
     pub extern "x86-interrupt" fn divide(stack_frame: InterruptStackFrame) {
+        abort_if_user(&stack_frame, "division error");
         log::error!("exception: 0x0 division error, stack_frame={stack_frame:#?}");
         loop {}
     }
@@ -78,34 +87,45 @@ mod exception_handlers {
         loop {}
     }
 
-    pub extern "x86-interrupt" fn nmi(stack_frame: InterruptStackFrame) {
+    pub extern "x86-interrupt" fn nmi(mut stack_frame: InterruptStackFrame) {
+        if crate::sandbox::abort_on_budget(&mut stack_frame) {
+            return;
+        }
+        // Raised by the tamper-detection PMCs, the TEE halts without secrets
+        lib::secret::wipe_all();
         let tsc = unsafe{ core_x86::_rdtsc() };
         log::error!("tsc={tsc:#?}");
+        crate::pmc::log_overflows();
         log::error!("exception: 0x2 debug, stack_frame={stack_frame:#?}");
         loop {}
     }
 
     pub extern "x86-interrupt" fn breakpoint(stack_frame: InterruptStackFrame) {
+        abort_if_user(&stack_frame, "breakpoint");
         log::error!("exception: 0x3 breakpoint, stack_frame={stack_frame:#?}");
         loop {}
     }
 
     pub extern "x86-interrupt" fn overflow(stack_frame: InterruptStackFrame) {
+        abort_if_user(&stack_frame, "overflow");
         log::error!("exception: 0x4 overflow, stack_frame={stack_frame:#?}");
         loop {}
     }
 
     pub extern "x86-interrupt" fn bound_range_exceeded(stack_frame: InterruptStackFrame) {
+        abort_if_user(&stack_frame, "bound_range_exceeded");
         log::error!("exception: 0x5 bound_range_exceeded, stack_frame={stack_frame:#?}");
         loop {}
     }
 
     pub extern "x86-interrupt" fn invalid_opcode(stack_frame: InterruptStackFrame) {
+        abort_if_user(&stack_frame, "invalid_opcode");
         log::error!("exception: 0x6 invalid_opcode, stack_frame={stack_frame:#?}");
         loop {}
     }
 
     pub extern "x86-interrupt" fn device_not_available(stack_frame: InterruptStackFrame) {
+        abort_if_user(&stack_frame, "device_not_available");
         log::error!("exception: 0x7 device_not_available, stack_frame={stack_frame:#?}");
         loop {}
     }
@@ -140,6 +160,7 @@ mod exception_handlers {
         stack_frame: InterruptStackFrame,
         error_code: u64,
     ) {
+        abort_if_user(&stack_frame, "stack_segment_fault");
         log::error!("exception: 0xc stack_segment_fault, error_code={error_code:?}, stack_frame={stack_frame:#?}");
         loop {}
     }
@@ -148,6 +169,7 @@ mod exception_handlers {
         stack_frame: InterruptStackFrame,
         error_code: u64,
     ) {
+        abort_if_user(&stack_frame, "general_protection_fault");
         log::error!("exception: 0xd general_protection_fault, error_code={error_code:?}, stack_frame={stack_frame:#?}");
         loop {}
     }
@@ -156,6 +178,7 @@ mod exception_handlers {
         stack_frame: InterruptStackFrame,
         error_code: PageFaultErrorCode,
     ) {
+        abort_if_user(&stack_frame, "page_fault");
         log::error!(
             "exception: 0xe page_fault, error_code={error_code:?}, stack_frame={stack_frame:#?}"
         );
@@ -163,6 +186,7 @@ mod exception_handlers {
     }
 
     pub extern "x86-interrupt" fn x87_floating_point(stack_frame: InterruptStackFrame) {
+        abort_if_user(&stack_frame, "x87_floating_point");
         log::error!("exception: 0x10 x87_floating_point, stack_frame={stack_frame:#?}");
         loop {}
     }
@@ -171,6 +195,7 @@ mod exception_handlers {
         stack_frame: InterruptStackFrame,
         error_code: u64,
     ) {
+        abort_if_user(&stack_frame, "alignment_check");
         log::error!("exception: 0x11 alignment_check, error_code={error_code:?}, stack_frame={stack_frame:#?}");
         loop {}
     }
@@ -180,6 +205,7 @@ mod exception_handlers {
     }
 
     pub extern "x86-interrupt" fn simd_floating_point(stack_frame: InterruptStackFrame) {
+        abort_if_user(&stack_frame, "simd_floating_point");
         log::error!("exception: 0x13 simd_floating_point, stack_frame={stack_frame:#?}");
         loop {}
     }
@@ -10,23 +10,29 @@ mod asm;
 mod driver;
 mod env;
 mod extern_symbols;
+mod gdt;
 mod idt;
 mod mem;
+mod modules;
+mod platform;
+mod pmc;
+mod sandbox;
 mod xen_pvh;
-mod shared_mem_com;
-mod state_machine;
 
 use crate::mem::stack;
 use core::fmt::Write;
+use core::str::FromStr;
 use core::hint::black_box;
 use core::panic::PanicInfo;
+use lib::bench;
+use lib::cli::CliArgs;
 use lib::logger;
 use lib::mem::paging;
 use lib::mem::paging::{PhysAddr, VirtAddr};
 use x86::msr;
 use multiboot2::{BootInformation, BootInformationHeader, MemoryAreaTypeId};
-use crate::state_machine::task::init_task_map;
-use crate::state_machine::pmc;
+use lib::shared_mem_com;
+use lib::state_machine;
 
 /// Entry into the high-level code of the loader.
 ///
@@ -46,6 +52,7 @@ extern "C" fn rust_entry64(
     load_addr_offset: i64,
 ) -> ! {
     // The order of the init functions mostly reflect actual dependencies!
+    gdt::init();
     idt::init();
     // x86_64::instructions::interrupts::enable();
     mem::init(load_addr_offset);
@@ -77,7 +84,9 @@ extern "C" fn rust_entry64(
     unsafe { log::info!("Hash of memory: {:#016x?}", paging::touch_all_present_pages() )};
     unsafe { log::info!("Hash of memory: {:#016x?}", paging::touch_all_present_pages() )};
 
-    pmc::setup_pmcs();
+    if false == pmc::setup_pmcs() {
+        log::error!("Tamper detection is not armed, all requests will be refused");
+    }
 
     log::info!("{:?} {:?}", PhysAddr::from(apic_page), VirtAddr::from(crate::extern_symbols::link_addr_high_base() as u64));
     let virt_lapic = unsafe {
@@ -103,6 +112,7 @@ extern "C" fn rust_entry64(
             let lvt_pcr_new : u32 = 0x0_u32 | 0b0 << 16 | 0b100 << 8 | 0x0;
             core::ptr::write_volatile(lvt_pcr_ptr, lvt_pcr_new);
             log::info!("Updated LVT PCR to: {:#010x}", core::ptr::read_volatile(lvt_pcr_ptr));
+            pmc::set_lvt_pcr(lvt_pcr_ptr);
         }
     }
 
@@ -150,10 +160,28 @@ extern "C" fn rust_entry64(
             mmap_shared_entry.size() as usize,
         )
     };
-    log::info!("Init taskmap...");
-    init_task_map();
+    let boot_secret = modules::load_boot_secret(&binding, VirtAddr::from(l1_addr));
+    let mut state_machine = state_machine::StateMachine::<state_machine::StateInitialized, _>::new(
+        shared_mem_communicator,
+        platform::HwPlatform::new(boot_secret),
+    );
+    for descriptor in modules::load(&binding, VirtAddr::from(l1_addr)) {
+        if let Err(e) = state_machine.register_task(descriptor) {
+            log::info!("Could not register task from module: {:?}", e);
+        }
+    }
 
-    let mut state_machine = state_machine::StateMachine::<state_machine::StateInitialized>::new(shared_mem_communicator);
+    let args = binding
+        .command_line_tag()
+        .and_then(|tag| tag.cmdline().ok())
+        .and_then(|cmdline| CliArgs::from_str(cmdline).ok())
+        .unwrap_or_default();
+    if let Some(benchmark) = args.bench() {
+        // Runs before the state machine, i.e., with the code of the TEE not yet cached
+        let result = benchmark.run(state_machine.platform(), bench::DEFAULT_BENCH_SIZE, bench::DEFAULT_PASSES);
+        log::info!("Benchmark {:?}: {:?}", benchmark, result.counts);
+        bench::record(result);
+    }
 
     loop {
         state_machine = state_machine::run_state_machine(state_machine);
@@ -184,6 +212,9 @@ fn create_pagefault() {
 
 #[panic_handler]
 fn panic_handler(info: &PanicInfo) -> ! {
+    // Secrets must not survive in memory the host may get hold of
+    lib::secret::wipe_all();
+
     // If a panic happens, we are screwed anyways. We do some additional
     // emergency logging without the whole log-stack
     let _ = writeln!(&mut driver::DebugconLogger, "PANIC: {info:#?}");
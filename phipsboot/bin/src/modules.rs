@@ -0,0 +1,104 @@
+//! Loading of tasks that are passed as Multiboot2 modules.
+
+use alloc::vec::Vec;
+use lib::mem::paging;
+use lib::mem::paging::{PhysAddr, VirtAddr};
+use lib::state_machine::session::SessionBuffer;
+use lib::state_machine::task::TaskDescriptor;
+use lib::tasks::module::{ModuleError, ModuleImage};
+use multiboot2::BootInformation;
+
+/// Present, read-only and not executable. Tasks only execute their code in
+/// their own address space, see [`crate::sandbox`].
+const MODULE_PAGE_FLAGS: u64 = 0x1 | 1 << 63;
+
+/// Command line of the module that contains the boot secret.
+pub const BOOT_SECRET_CMDLINE: &str = "boot-secret";
+/// Minimum size of the boot secret in bytes.
+const BOOT_SECRET_MIN_LEN: usize = 32;
+
+/// Maps all modules that contain a task into the high half and returns their
+/// descriptors. Other modules are skipped.
+pub fn load(boot_info: &BootInformation, l1_addr: VirtAddr) -> Vec<TaskDescriptor> {
+    let mut tasks = Vec::new();
+    for module in boot_info.module_tags() {
+        let cmdline = module.cmdline().unwrap_or("");
+        if BOOT_SECRET_CMDLINE == cmdline {
+            continue;
+        }
+        let start = module.start_address() as u64;
+        let end = module.end_address() as u64;
+        let page_offset = start & 0xFFF;
+        let pages = ((end - (start & !0xFFF)) as usize + 4095) / 4096;
+        if pages > paging::free_l1_entries() {
+            log::info!("Skipping module {:?}: {} pages do not fit", cmdline, pages);
+            continue;
+        }
+
+        let virt = unsafe {
+            paging::map_phys_rel_base_addr(
+                PhysAddr::from(start),
+                pages,
+                l1_addr,
+                MODULE_PAGE_FLAGS,
+            )
+        };
+        if 0 == virt.val() {
+            log::info!("Skipping module {:?}: could not be mapped", cmdline);
+            continue;
+        }
+        // The mapping is never removed
+        let bytes: &'static [u8] = unsafe {
+            core::slice::from_raw_parts((virt.val() + page_offset) as *const u8, (end - start) as usize)
+        };
+        match ModuleImage::parse(bytes) {
+            Ok(image) => {
+                log::info!(
+                    "Loaded task {:?} ({:?}) from module {:?} at {:#016x?}",
+                    image.name(),
+                    image.id(),
+                    cmdline,
+                    virt.val() + page_offset
+                );
+                tasks.push(image.into_descriptor());
+            }
+            Err(ModuleError::BadMagic) => log::info!("Skipping module {:?}: not a task", cmdline),
+            Err(e) => log::info!("Skipping module {:?}: {:?}", cmdline, e),
+        }
+    }
+    tasks
+}
+
+/// Copies the boot secret into the heap and wipes the module, so that it is
+/// not left in memory outside of the TEE. The module must have the command
+/// line [`BOOT_SECRET_CMDLINE`] and contain at least 32 bytes.
+pub fn load_boot_secret(boot_info: &BootInformation, l1_addr: VirtAddr) -> Option<SessionBuffer> {
+    let module = boot_info
+        .module_tags()
+        .find(|module| Ok(BOOT_SECRET_CMDLINE) == module.cmdline())?;
+    let start = module.start_address() as u64;
+    let len = (module.end_address() - module.start_address()) as usize;
+    if len < BOOT_SECRET_MIN_LEN {
+        log::info!("Ignoring boot secret: {} bytes are too short", len);
+        return None;
+    }
+    let pages = ((start & 0xFFF) as usize + len + 4095) / 4096;
+    if pages > paging::free_l1_entries() {
+        log::info!("Ignoring boot secret: {} pages do not fit", pages);
+        return None;
+    }
+    // Present and writable
+    let virt = unsafe { paging::map_phys_rel_base_addr(PhysAddr::from(start), pages, l1_addr, 0x3 | 1 << 63) };
+    if 0 == virt.val() {
+        log::info!("Ignoring boot secret: could not be mapped");
+        return None;
+    }
+    let bytes = unsafe { core::slice::from_raw_parts_mut((virt.val() + (start & 0xFFF)) as *mut u8, len) };
+    let mut secret = SessionBuffer::new(len, 1);
+    secret.as_mut_slice().copy_from_slice(bytes);
+    for byte in bytes.iter_mut() {
+        unsafe { core::ptr::write_volatile(byte, 0) };
+    }
+    log::info!("Loaded boot secret of {} bytes", len);
+    Some(secret)
+}
@@ -0,0 +1,139 @@
+//! Implementation of [`Platform`] for the real hardware.
+
+use core::arch::x86_64::__cpuid;
+use core::cell::RefCell;
+
+use lib::attestation::CpuInfo;
+use lib::entropy::{Entropy, EntropyError};
+use lib::measurement::{self, Measurement};
+use lib::mem::paging;
+use lib::platform::{Platform, COUNTER_NUM};
+use lib::pmc_utils::events::Event;
+use lib::pmc_utils::global::OverflowStatus;
+use lib::pmc_utils::pmu::PmuInfo;
+use lib::secret;
+use lib::state_machine::session::SessionBuffer;
+use lib::state_machine::task::TaskError;
+use lib::tasks::module::ModuleImage;
+
+use crate::extern_symbols;
+use crate::{pmc, sandbox};
+
+/// [`Platform`] that uses the page tables of the loader and the PMCs of the
+/// CPU the TEE runs on.
+#[derive(Debug)]
+pub struct HwPlatform {
+    measurement: Measurement,
+    boot_secret: Option<SessionBuffer>,
+    entropy: RefCell<Entropy>,
+}
+
+impl HwPlatform {
+    /// Measures the RX and RO segments of the loader. `boot_secret` is the
+    /// secret passed by the bootloader, see [`crate::modules::load_boot_secret`].
+    pub fn new(boot_secret: Option<SessionBuffer>) -> Self {
+        let (rx, ro) = unsafe {
+            (
+                core::slice::from_raw_parts(extern_symbols::link_addr_rx(), extern_symbols::count_pages_rx() * 4096),
+                core::slice::from_raw_parts(extern_symbols::link_addr_ro(), extern_symbols::count_pages_ro() * 4096),
+            )
+        };
+        let measurement = measurement::measure(&[rx, ro]);
+        log::info!("Measurement: {:02x?}", measurement);
+        let entropy = Entropy::detect();
+        log::info!("Entropy source: {:?}", entropy.source());
+        Self { measurement, boot_secret, entropy: RefCell::new(entropy) }
+    }
+}
+
+impl Platform for HwPlatform {
+    fn get_physical_address(&self, virt_addr: u64) -> u64 {
+        unsafe { paging::get_physical_address(virt_addr) }
+    }
+
+    fn get_virtual_address(&self, phys_addr: u64) -> u64 {
+        unsafe { paging::get_virtual_address(phys_addr) }
+    }
+
+    fn setup_pmcs(&self) -> bool {
+        pmc::setup_pmcs()
+    }
+
+    fn pmcs_armed(&self) -> bool {
+        pmc::is_armed()
+    }
+
+    fn read_pmcs(&self) -> [u64; COUNTER_NUM] {
+        pmc::read_pmcs()
+    }
+
+    fn pmu(&self) -> PmuInfo {
+        pmc::pmu().1
+    }
+
+    fn pmc_overflows(&self) -> OverflowStatus {
+        pmc::overflow_status()
+    }
+
+    fn rearm_pmcs(&self, status: OverflowStatus) {
+        pmc::rearm_pmcs(status);
+    }
+
+    fn read_tsc(&self) -> u64 {
+        unsafe { core::arch::x86_64::_rdtsc() }
+    }
+
+    fn pmc_config(&self) -> [u64; COUNTER_NUM] {
+        pmc::read_config()
+    }
+
+    fn count_event(&self, event: Event, f: &mut dyn FnMut()) -> u64 {
+        pmc::count_event(event, f)
+    }
+
+    fn run_module(&self, image: &ModuleImage, input: &[u8], output: &mut [u8]) -> Result<usize, TaskError> {
+        sandbox::run(self, image, input, output)
+    }
+
+    fn measurement(&self) -> Measurement {
+        self.measurement
+    }
+
+    fn cpu_info(&self) -> CpuInfo {
+        CpuInfo {
+            signature: unsafe { __cpuid(1) }.eax,
+            microcode: microcode_revision(),
+        }
+    }
+
+    fn boot_secret(&self) -> Option<&[u8]> {
+        // Tampering zeroes the secret, keys derived from zeroes are public
+        if secret::is_wiped() {
+            return None;
+        }
+        self.boot_secret.as_ref().map(SessionBuffer::as_slice)
+    }
+
+    fn fill_entropy(&self, buf: &mut [u8]) -> Result<(), EntropyError> {
+        self.entropy.borrow_mut().fill(buf)
+    }
+}
+
+/// Returns the revision of the loaded microcode, or 0 on CPUs of unknown
+/// vendors.
+fn microcode_revision() -> u32 {
+    use lib::pmc_utils::vendor::{get_cpu_vendor, CpuVendor};
+    use x86::msr::{rdmsr, wrmsr, IA32_BIOS_SIGN_ID};
+    match get_cpu_vendor() {
+        // Intel only updates the MSR on CPUID leaf 1 and reports the revision in
+        // the upper half
+        CpuVendor::Intel => unsafe {
+            wrmsr(IA32_BIOS_SIGN_ID, 0);
+            __cpuid(1);
+            (rdmsr(IA32_BIOS_SIGN_ID) >> 32) as u32
+        },
+        // PATCH_LEVEL
+        CpuVendor::Amd => unsafe { rdmsr(IA32_BIOS_SIGN_ID) as u32 },
+        CpuVendor::Unknown => 0,
+    }
+}
@@ -0,0 +1,394 @@
+
+use core::cell::Cell;
+use log::info;
+use lib::pmc_utils::vendor;
+use lib::pmc_utils::amd;
+use lib::pmc_utils::intel;
+use lib::pmc_utils::architectural;
+use lib::pmc_utils::counter::{self, EventCounter, GpCounter};
+use lib::pmc_utils::events::{self, Event};
+use lib::pmc_utils::fixed;
+use lib::pmc_utils::global::{self, GlobalControl, OverflowStatus};
+use lib::pmc_utils::hybrid::CoreType;
+use lib::pmc_utils::msr::HardwareMsr;
+use lib::pmc_utils::pmu::PmuInfo;
+use lib::platform::COUNTER_NUM;
+use lib::safe::Safe;
+
+/// Value the GP PMCs are armed with, so that they overflow on the first event.
+const ARMED: u64 = u64::MAX;
+
+/// GP PMC that counts the instructions of module tasks on AMD, which has no
+/// fixed counters.
+const AMD_BUDGET_PMC: u8 = COUNTER_NUM as u8;
+/// Retired instructions, the encoding is the same on Intel and AMD.
+const EVENT_INSTRUCTIONS_RETIRED: u64 = 0xc0;
+
+/// Returns the MSRs of the current core. The firmware runs in ring 0 and only
+/// accesses the MSRs of the PMU.
+fn msr() -> HardwareMsr {
+    unsafe { HardwareMsr::new() }
+}
+
+/// LVT performance counter register of the local APIC. `None` if the PMCs
+/// cannot raise the PMI.
+static LVT_PCR: Safe<Cell<Option<*mut u32>>> = Safe::new(Cell::new(None));
+
+/// Whether [`setup_pmcs`] armed all PMCs that raise the PMI.
+static PMCS_ARMED: Safe<Cell<bool>> = Safe::new(Cell::new(false));
+
+/// Vendor and PMU of the CPU. `None` until they were queried.
+static PMU: Safe<Cell<Option<(vendor::CpuVendor, PmuInfo)>>> = Safe::new(Cell::new(None));
+
+/// Returns the vendor and the PMU of the CPU. CPUID is only executed on the
+/// first call, which is [`setup_pmcs`] unless a benchmark runs before.
+pub fn pmu() -> (vendor::CpuVendor, PmuInfo) {
+    if let Some(pmu) = PMU.get() {
+        return pmu;
+    }
+    let pmu = (vendor::get_cpu_vendor(), counter::query_features());
+    PMU.set(Some(pmu));
+    pmu
+}
+
+/// Returns the GP PMC `index`. `None` on CPUs that are neither from Intel nor
+/// from AMD.
+fn gp_counter(index: u8) -> Option<GpCounter> {
+    let (vendor, pmu) = pmu();
+    GpCounter::with_msr(vendor, pmu, index, msr())
+}
+
+/// Returns IA32_PERF_GLOBAL_CTRL and its siblings.
+fn global_control() -> GlobalControl {
+    GlobalControl::with_msr(pmu().1, msr())
+}
+
+/// Programs the GP PMCs for the tamper detection and, on Intel CPUs, the fixed
+/// counters. Returns `false` if a PMC that raises the PMI could not be armed,
+/// e.g., on CPUs that are neither from Intel nor from AMD or that cannot count
+/// its event. The TEE refuses all requests then, see [`is_armed`].
+pub fn setup_pmcs() -> bool {
+    use vendor::CpuVendor;
+
+    let (vendor, pmu) = pmu();
+    if CpuVendor::Unknown == vendor {
+        log::error!("Tamper detection needs a CPU from Intel or AMD");
+        return false;
+    }
+    if usize::from(pmu.gp_counters) < COUNTER_NUM {
+        log::error!("Tamper detection needs {} GP PMCs, the CPU has {}", COUNTER_NUM, pmu.gp_counters);
+        return false;
+    }
+    // The E-cores cannot count all tamper-detection events
+    if Some(CoreType::Atom) == CoreType::detect() {
+        log::error!("Tamper detection needs a P-core, pin the TEE to one");
+        return false;
+    }
+    let armed = setup_architecturial(vendor, pmu);
+    // AMD has neither fixed counters nor IA32_PERF_GLOBAL_CTRL, both are
+    // no-ops there
+    setup_fixed(pmu);
+    // setup_offcore();
+
+    let mut enabled = (0..COUNTER_NUM as u8).fold(0, |mask, x| mask | global::gp_counter_bit(x));
+    for x in 0..pmu.fixed_counters {
+        enabled |= global::fixed_counter_bit(x);
+    }
+    GlobalControl::with_msr(pmu, msr()).enable(enabled);
+    PMCS_ARMED.set(armed);
+    armed
+}
+
+/// Returns `true` if [`setup_pmcs`] armed all PMCs that raise the PMI.
+pub fn is_armed() -> bool {
+    PMCS_ARMED.get()
+}
+
+/// Lets the fixed counters count instructions, core cycles and reference
+/// cycles in all privilege levels. The GP PMCs stay free for cache events.
+fn setup_fixed(pmu: PmuInfo) {
+    use fixed::{FixedEventCounter, IA32_FIXED_CTR_CTRL_OS, IA32_FIXED_CTR_CTRL_USR};
+
+    for x in 0..pmu.fixed_counters {
+        let mut counter = FixedEventCounter::with_msr(pmu, x, msr());
+        counter.set_configuration(IA32_FIXED_CTR_CTRL_OS | IA32_FIXED_CTR_CTRL_USR);
+        if let Err(e) = counter.activate_counter(0) {
+            log::error!("Could not activate fixed PMC {}: {:?}", x, e);
+        }
+    }
+}
+
+#[allow(dead_code)]
+fn setup_offcore() {
+    use intel::MsrOffcoreRspEventCounter;
+
+    let mut counter = unsafe { MsrOffcoreRspEventCounter::new(0, 3) };
+    counter.set_offcore_configuration(
+        0x184000001
+    );
+    if let Err(e) = counter.activate_counter(0x0_u64) {
+        log::error!("Could not activate offcore counter: {:?}", e);
+    }
+}
+
+/// Programs the GP PMCs for the tamper detection. Returns `false` if a PMC
+/// that raises the PMI could not be armed.
+fn setup_architecturial(vendor: vendor::CpuVendor, pmu: PmuInfo) -> bool {
+    use architectural::{
+        IA32_PERFEVTSEL_USR,
+        IA32_PERFEVTSEL_OS,
+        IA32_PERFEVTSEL_INT,
+    };
+
+    // Event of each PMC and whether its overflow raises the PMI
+    let setup: [(Event, u64); COUNTER_NUM] = [
+        (Event::L1DReplacement, 0),
+        (Event::L2Miss, IA32_PERFEVTSEL_INT),
+        (Event::L3Hit, IA32_PERFEVTSEL_INT),
+        (Event::L3Miss, IA32_PERFEVTSEL_INT),
+    ];
+
+    let mut armed = true;
+    for (x, (event, flags)) in setup.into_iter().enumerate() {
+        let Some(mut counter) = GpCounter::with_msr(vendor, pmu, x as u8, msr()) else {
+            return false;
+        };
+        // Without its event, a PMC that raises the PMI leaves a gap in the
+        // tamper detection
+        let encoding = match events::resolve(event) {
+            Ok(encoding) => encoding,
+            Err(e) => {
+                log::error!("Could not resolve the event {:?} of PMC {}: {:?}", event, x, e);
+                armed &= 0 == flags & IA32_PERFEVTSEL_INT;
+                continue;
+            }
+        };
+        counter.set_configuration(encoding | IA32_PERFEVTSEL_OS | IA32_PERFEVTSEL_USR | flags);
+        if let Err(e) = counter.activate_counter(ARMED) {
+            log::error!("Could not activate PMC {}: {:?}", x, e);
+            armed &= 0 == flags & IA32_PERFEVTSEL_INT;
+        }
+    }
+    armed
+}
+
+
+/// Returns the values of the PMCs programmed by [`setup_pmcs`]. On CPUs that
+/// are neither from Intel nor from AMD and for counters the CPU lacks, the
+/// values are zero.
+pub fn read_pmcs() -> [u64; COUNTER_NUM] {
+    let mut values = [0x0_u64; COUNTER_NUM];
+    for x in 0..COUNTER_NUM {
+        if let Some(counter) = gp_counter(x as u8) {
+            values[x] = counter.read_pcm_val().unwrap_or(0);
+        }
+    }
+    values
+}
+
+/// Returns the PMCs that overflowed and were not re-armed yet. AMD has no
+/// global overflow status, so on non-Intel CPUs no PMC overflows. The tamper
+/// detection still notices counters that moved.
+pub fn overflow_status() -> OverflowStatus {
+    use vendor::CpuVendor;
+
+    if CpuVendor::Intel != pmu().0 {
+        return OverflowStatus::default();
+    }
+    global_control().overflow_status()
+}
+
+/// Re-arms the PMCs programmed by [`setup_pmcs`] that are flagged in `status`
+/// and acknowledges their overflow. The overflow of the other PMCs is only
+/// acknowledged.
+pub fn rearm_pmcs(status: OverflowStatus) {
+    use vendor::CpuVendor;
+
+    for x in 0..COUNTER_NUM as u8 {
+        if status.counter(x) {
+            let Some(counter) = gp_counter(x) else {
+                return;
+            };
+            if let Err(e) = counter.reset_pcm_val(ARMED) {
+                log::error!("Could not re-arm PMC {}: {:?}", x, e);
+            }
+        }
+    }
+    if CpuVendor::Intel == pmu().0 {
+        global_control().acknowledge(status);
+    }
+}
+
+/// Logs which PMCs overflowed and the events they count. Used by the PMI
+/// handler to tell which event fired.
+pub fn log_overflows() {
+    let status = overflow_status();
+    if status.is_empty() {
+        log::error!("No PMC overflowed, the NMI was not raised by a PMC");
+        return;
+    }
+    let config = read_config();
+    for x in 0..COUNTER_NUM {
+        if status.counter(x as u8) {
+            log::error!("PMC {} overflowed, event select {:#x}", x, config[x]);
+        }
+    }
+    for x in 0..pmu().1.fixed_counters {
+        if status.fixed_counter(x) {
+            log::error!("Fixed PMC {} overflowed", x);
+        }
+    }
+}
+
+/// Returns the event select registers of the PMCs programmed by
+/// [`setup_pmcs`]. On CPUs that are neither from Intel nor from AMD and for
+/// counters the CPU lacks, the values are zero.
+pub fn read_config() -> [u64; COUNTER_NUM] {
+    let mut config = [0x0_u64; COUNTER_NUM];
+    for x in 0..COUNTER_NUM {
+        if let Some(counter) = gp_counter(x as u8) {
+            config[x] = counter.read_configuration().unwrap_or(0);
+        }
+    }
+    config
+}
+
+/// Counts `event` on PMC 0 while `f` runs and returns the count. PMC 0 is
+/// restored afterwards. On Intel CPUs, all other PMCs are stopped meanwhile;
+/// AMD CPUs have no IA32_PERF_GLOBAL_CTRL, so they keep counting. For events
+/// the CPU cannot count, 0 is returned. Only used by benchmarks at boot,
+/// before the tamper detection is armed.
+pub fn count_event(event: Event, f: &mut dyn FnMut()) -> u64 {
+    let (vendor, pmu) = pmu();
+    let Some(mut counter) = GpCounter::with_msr(vendor, pmu, 0, msr()) else {
+        f();
+        return 0;
+    };
+    let encoding = match events::resolve(event) {
+        Ok(encoding) => encoding,
+        Err(e) => {
+            log::error!("Could not resolve the event {:?}: {:?}", event, e);
+            f();
+            return 0;
+        }
+    };
+
+    counter.count_event(&global_control(), encoding, f).unwrap_or_else(|e| {
+        log::error!("Could not count the event {:?}: {:?}", event, e);
+        0
+    })
+}
+
+pub fn read_and_print_pmcs() {
+    use vendor::CpuVendor;
+
+    let (vendor, pmu) = pmu();
+    if CpuVendor::Unknown == vendor {
+        info!("Function not supported on this machine!");
+        return;
+    }
+
+    let values = read_pmcs();
+    info!("IA_PMC1 (Replacement) = {:#018x?}", values[0]);
+    info!("IA_PMC0 (L2 Misses)   = {:#018x?}", values[1]);
+    info!("IA_PMC2 (L3 Hits)     = {:#018x?}", values[2]);
+    info!("IA_PMC3 (L3 Misses)   = {:#018x?}", values[3]);
+
+    // AMD has no fixed counters
+    if CpuVendor::Intel != vendor {
+        return;
+    }
+    let names = ["Instructions", "Core cycles", "Ref. cycles"];
+    for (x, name) in names.iter().enumerate() {
+        let counter = fixed::FixedEventCounter::with_msr(pmu, x as u8, msr());
+        if let Ok(value) = counter.read_pcm_val() {
+            info!("IA32_FIXED_CTR{} ({}) = {:#018x?}", x, name, value);
+        }
+    }
+}
+
+/// Records the LVT performance counter register, which delivers the PMI as NMI.
+pub fn set_lvt_pcr(lvt_pcr: *mut u32) {
+    LVT_PCR.set(Some(lvt_pcr));
+}
+
+/// Clears the mask bit of the LVT performance counter register. Intel CPUs set
+/// it whenever they deliver the PMI.
+pub fn unmask_pmi() {
+    if let Some(lvt_pcr) = LVT_PCR.get() {
+        unsafe { lvt_pcr.write_volatile(lvt_pcr.read_volatile() & !(0x1 << 16)) };
+    }
+}
+
+/// Arms a PMC that raises the PMI once ring 3 retired `budget` instructions.
+/// Intel CPUs use fixed counter 0, AMD CPUs the GP PMC after the tamper
+/// detection. Returns `false` if there is no such PMC or no PMI.
+pub fn arm_budget(budget: u64) -> bool {
+    use architectural::{IA32_PERFEVTSEL_INT, IA32_PERFEVTSEL_USR};
+    use fixed::{FixedEventCounter, IA32_FIXED_CTR_CTRL_PMI, IA32_FIXED_CTR_CTRL_USR};
+    use vendor::CpuVendor;
+
+    if LVT_PCR.get().is_none() {
+        return false;
+    }
+    let (vendor, pmu) = pmu();
+    match vendor {
+        CpuVendor::Intel => {
+            let mut counter = FixedEventCounter::with_msr(pmu, fixed::FIXED_INSTRUCTIONS_RETIRED, msr());
+            counter.set_configuration(IA32_FIXED_CTR_CTRL_USR | IA32_FIXED_CTR_CTRL_PMI);
+            let mask = (1_u64 << pmu.fixed_width.min(63)) - 1;
+            if counter.activate_counter(budget.wrapping_neg() & mask).is_err() {
+                return false;
+            }
+            GlobalControl::with_msr(pmu, msr()).enable(global::fixed_counter_bit(fixed::FIXED_INSTRUCTIONS_RETIRED));
+            true
+        }
+        CpuVendor::Amd => {
+            let Some(mut counter) = gp_counter(AMD_BUDGET_PMC) else {
+                return false;
+            };
+            counter.set_configuration(EVENT_INSTRUCTIONS_RETIRED | IA32_PERFEVTSEL_USR | IA32_PERFEVTSEL_INT);
+            counter.activate_counter(budget.wrapping_neg()).is_ok()
+        }
+        CpuVendor::Unknown => false,
+    }
+}
+
+/// Returns `true` if the PMC armed by [`arm_budget`] overflowed.
+pub fn budget_exhausted() -> bool {
+    use vendor::CpuVendor;
+
+    match pmu().0 {
+        CpuVendor::Intel => overflow_status().fixed_counter(fixed::FIXED_INSTRUCTIONS_RETIRED),
+        // The counter is armed close to the top, so it lost its top bit
+        CpuVendor::Amd => match gp_counter(AMD_BUDGET_PMC).map(|counter| counter.read_pcm_val()) {
+            Some(Ok(value)) => 0 == value & (0x1 << (amd::AMD_COUNTER_WIDTH - 1)),
+            _ => false,
+        },
+        CpuVendor::Unknown => false,
+    }
+}
+
+/// Stops the PMC armed by [`arm_budget`] and acknowledges its overflow. On
+/// Intel CPUs, fixed counter 0 counts instructions in all rings again.
+pub fn disarm_budget() {
+    use fixed::{FixedEventCounter, IA32_FIXED_CTR_CTRL_OS, IA32_FIXED_CTR_CTRL_USR};
+    use vendor::CpuVendor;
+
+    let (vendor, pmu) = pmu();
+    match vendor {
+        CpuVendor::Intel => {
+            let mut counter = FixedEventCounter::with_msr(pmu, fixed::FIXED_INSTRUCTIONS_RETIRED, msr());
+            counter.set_configuration(IA32_FIXED_CTR_CTRL_OS | IA32_FIXED_CTR_CTRL_USR);
+            let _ = counter.activate_counter(0);
+            let status = OverflowStatus(global::fixed_counter_bit(fixed::FIXED_INSTRUCTIONS_RETIRED));
+            GlobalControl::with_msr(pmu, msr()).acknowledge(status);
+        }
+        CpuVendor::Amd => {
+            if let Some(counter) = gp_counter(AMD_BUDGET_PMC) {
+                let _ = counter.deactivate_counter();
+            }
+        }
+        CpuVendor::Unknown => {}
+    }
+}
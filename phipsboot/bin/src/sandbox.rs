@@ -0,0 +1,244 @@
+//! Execution of module tasks in ring 3.
+//!
+//! [`run`] builds a [`Sandbox`] for the module, switches to its address space
+//! and enters ring 3 via `sysret` at the trampoline, which calls the entry
+//! point and passes its return value to `exit`. Syscalls land in
+//! `sandbox_syscall_entry`, which switches to the kernel stack and dispatches
+//! them to the active [`Sandbox`]. `exit` and exceptions in ring 3 unwind to
+//! the caller of `sandbox_enter` via `sandbox_leave`.
+//!
+//! Tasks run with interrupts disabled, so a PMC enforces the
+//! [`INSTRUCTION_BUDGET`]. Its overflow raises an NMI, whose handler returns to
+//! `sandbox_abort` instead of the task.
+
+use core::cell::Cell;
+use lib::platform::Platform;
+use lib::safe::Safe;
+use lib::sandbox::{Sandbox, SyscallResult, INSTRUCTION_BUDGET, USER_STACK_TOP};
+use lib::state_machine::task::TaskError;
+use lib::tasks::module::ModuleImage;
+use x86::controlregs;
+use x86_64::instructions::segmentation::{Segment, CS, SS};
+use x86_64::structures::idt::InterruptStackFrame;
+use x86_64::VirtAddr;
+
+use crate::extern_symbols;
+use crate::pmc;
+
+core::arch::global_asm!(
+    r#"
+.section .text
+
+# Enters ring 3. Returns the exit status of the task.
+# rdi: user address of the entry point, passed to the trampoline
+# rsi: user stack pointer
+# rdx: user address of the trampoline
+.global sandbox_enter
+sandbox_enter:
+    push  %rbx
+    push  %rbp
+    push  %r12
+    push  %r13
+    push  %r14
+    push  %r15
+    mov  %rsp, SANDBOX_KERNEL_RSP(%rip)
+
+    mov  %rdx, %rcx
+    mov  $0x2, %r11
+    mov  %rsi, %rsp
+    xor  %eax, %eax
+    xor  %ebx, %ebx
+    xor  %edx, %edx
+    xor  %esi, %esi
+    xor  %ebp, %ebp
+    xor  %r8d, %r8d
+    xor  %r9d, %r9d
+    xor  %r10d, %r10d
+    xor  %r12d, %r12d
+    xor  %r13d, %r13d
+    xor  %r14d, %r14d
+    xor  %r15d, %r15d
+    sysretq
+
+# Returns from `sandbox_enter` with the status in rax. Expects rsp to be
+# SANDBOX_KERNEL_RSP.
+.global sandbox_leave
+sandbox_leave:
+    pop  %r15
+    pop  %r14
+    pop  %r13
+    pop  %r12
+    pop  %rbp
+    pop  %rbx
+    ret
+
+# Fails the task. The NMI handler returns here with rsp set to
+# SANDBOX_KERNEL_RSP.
+.global sandbox_abort
+sandbox_abort:
+    mov  $-1, %rax
+    jmp  sandbox_leave
+
+# Target of `syscall`. The number is in rax, the arguments in rdi, rsi, rdx.
+.global sandbox_syscall_entry
+sandbox_syscall_entry:
+    mov  %rsp, SANDBOX_USER_RSP(%rip)
+    mov  SANDBOX_KERNEL_RSP(%rip), %rsp
+    push  %rcx
+    push  %r11
+    sub  $8, %rsp
+    mov  %rdx, %rcx
+    mov  %rsi, %rdx
+    mov  %rdi, %rsi
+    mov  %rax, %rdi
+    call  sandbox_syscall
+    add  $8, %rsp
+    test  %rdx, %rdx
+    jnz  1f
+
+    pop  %r11
+    pop  %rcx
+    mov  SANDBOX_USER_RSP(%rip), %rsp
+    # Do not leak kernel data in scratch registers
+    xor  %edx, %edx
+    xor  %esi, %esi
+    xor  %edi, %edi
+    xor  %r8d, %r8d
+    xor  %r9d, %r9d
+    xor  %r10d, %r10d
+    sysretq
+1:
+    mov  SANDBOX_KERNEL_RSP(%rip), %rsp
+    jmp  sandbox_leave
+
+# The only kernel code that is mapped into the task. It gets a page on its own.
+.pushsection .text.sandbox_user, "ax"
+.balign 4096
+.global sandbox_trampoline
+sandbox_trampoline:
+    call  *%rdi
+    mov  %rax, %rdi
+    xor  %eax, %eax
+    syscall
+    ud2
+.balign 4096
+.popsection
+"#,
+    options(att_syntax)
+);
+
+extern "C" {
+    fn sandbox_enter(entry: u64, user_rsp: u64, trampoline: u64) -> i64;
+    fn sandbox_abort() -> !;
+    static sandbox_trampoline: [u8; 0];
+}
+
+/// Kernel stack pointer while a task runs.
+#[no_mangle]
+static mut SANDBOX_KERNEL_RSP: u64 = 0;
+/// User stack pointer during a syscall.
+#[no_mangle]
+static mut SANDBOX_USER_RSP: u64 = 0;
+
+/// The sandbox of the task that currently runs.
+static ACTIVE: Safe<Cell<Option<*mut Sandbox<'static>>>> = Safe::new(Cell::new(None));
+
+/// Return value of [`sandbox_syscall`] in rax and rdx.
+#[repr(C)]
+struct SyscallReturn {
+    value: u64,
+    exit: u64,
+}
+
+#[no_mangle]
+extern "sysv64" fn sandbox_syscall(nr: u64, arg0: u64, arg1: u64, arg2: u64) -> SyscallReturn {
+    let sandbox = ACTIVE.get().expect("syscall without a task");
+    match unsafe { &mut *sandbox }.syscall(nr, arg0, arg1, arg2) {
+        SyscallResult::Return(value) => SyscallReturn { value, exit: 0 },
+        SyscallResult::Exit(status) => SyscallReturn { value: status as u64, exit: 1 },
+    }
+}
+
+/// Runs the module task `image` in ring 3. Returns the number of bytes
+/// written to `output`.
+pub fn run(platform: &dyn Platform, image: &ModuleImage, input: &[u8], output: &mut [u8]) -> Result<usize, TaskError> {
+    let kernel_l4 = unsafe {
+        &*(extern_symbols::boot_symbol_to_high_address(extern_symbols::boot_mem_pt_l4()) as *const [u64; 512])
+    };
+    let trampoline = unsafe { sandbox_trampoline.as_ptr() };
+    let mut sandbox = Sandbox::new(platform, kernel_l4, image, trampoline, input, output.len())?;
+    let root = sandbox.address_space().root(platform)?;
+    let (entry, trampoline) = (sandbox.entry(), sandbox.trampoline());
+
+    if false == pmc::arm_budget(INSTRUCTION_BUDGET) {
+        log::error!("Cannot run task {:?}: no PMC can limit its instructions", image.name());
+        return Err(TaskError::ExecutionFailed);
+    }
+    ACTIVE.set(Some((&mut sandbox as *mut Sandbox).cast()));
+    let status = unsafe {
+        let kernel_root = controlregs::cr3();
+        controlregs::cr3_write(root);
+        let status = sandbox_enter(entry, USER_STACK_TOP, trampoline);
+        controlregs::cr3_write(kernel_root);
+        status
+    };
+    ACTIVE.set(None);
+    pmc::disarm_budget();
+
+    let written = sandbox.into_output();
+    if status < 0 {
+        log::info!("Task {:?} failed with status {}", image.name(), status);
+        return Err(TaskError::ExecutionFailed);
+    }
+    output[..written.len()].copy_from_slice(&written);
+    Ok(written.len())
+}
+
+/// Aborts the running task if the exception described by `stack_frame` was
+/// raised in ring 3. The task then fails.
+pub fn abort_if_user(stack_frame: &InterruptStackFrame, exception: &str) {
+    if 3 != stack_frame.code_segment & 3 || ACTIVE.get().is_none() {
+        return;
+    }
+    log::info!("Aborting task: {} at {:#016x?}", exception, stack_frame.instruction_pointer.as_u64());
+    unsafe {
+        core::arch::asm!(
+            "mov SANDBOX_KERNEL_RSP(%rip), %rsp",
+            "mov $-1, %rax",
+            "jmp sandbox_leave",
+            options(att_syntax, noreturn)
+        )
+    }
+}
+
+/// Handles the NMI of the PMC armed with the instruction budget of the running
+/// task. Returns `false` if the NMI has another cause. In ring 3, the handler
+/// then returns to `sandbox_abort`. In the kernel, i.e., during a syscall, the
+/// budget is re-armed to overflow on the next instruction of the task.
+pub fn abort_on_budget(stack_frame: &mut InterruptStackFrame) -> bool {
+    if ACTIVE.get().is_none() || false == pmc::budget_exhausted() {
+        return false;
+    }
+    // Overflows of the tamper-detection PMCs take precedence
+    let status = pmc::overflow_status();
+    if (0..lib::platform::COUNTER_NUM as u8).any(|x| status.counter(x)) {
+        return false;
+    }
+    pmc::disarm_budget();
+    pmc::unmask_pmi();
+    if 3 != stack_frame.code_segment & 3 {
+        pmc::arm_budget(1);
+        return true;
+    }
+    log::info!("Aborting task: instruction budget exhausted at {:#016x?}", stack_frame.instruction_pointer.as_u64());
+    unsafe {
+        stack_frame.as_mut().update(|frame| {
+            frame.instruction_pointer = VirtAddr::new(sandbox_abort as *const () as u64);
+            frame.code_segment = u64::from(CS::get_reg().0);
+            frame.cpu_flags = 0x2;
+            frame.stack_pointer = VirtAddr::new(SANDBOX_KERNEL_RSP);
+            frame.stack_segment = u64::from(SS::get_reg().0);
+        });
+    }
+    true
+}
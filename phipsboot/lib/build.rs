@@ -0,0 +1,100 @@
+//! Generates the PMC event definitions from the Intel perfmon JSON files in
+//! `perfmon/`, see `src/pmc_utils/perfmon.rs`.
+
+use std::fmt::Write as _;
+use std::path::Path;
+
+use serde_json::Value;
+
+/// Module name of the generated definitions and the JSON file they are
+/// generated from.
+const CORES: &[(&str, &str)] = &[
+    ("skylake", "skylake_core_v42.json"),
+    ("icelake", "icelake_core_v1.00.json"),
+    ("raptor_cove", "alderlake_goldencove_core.json"),
+    ("gracemont", "alderlake_gracemont_core.json"),
+];
+
+fn main() {
+    println!("cargo:rerun-if-changed=perfmon");
+    let mut out = String::new();
+    for (module, file) in CORES {
+        let path = Path::new("perfmon").join(file);
+        let json = std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("cannot read {}: {}", path.display(), e));
+        // Older releases of perfmon store the bare event list, newer ones put
+        // it next to a header
+        let events = match serde_json::from_str(&json) {
+            Ok(Value::Array(events)) => events,
+            Ok(Value::Object(mut root)) => match root.remove("Events") {
+                Some(Value::Array(events)) => events,
+                _ => panic!("{} has no event list", path.display()),
+            },
+            Ok(_) => panic!("{} is neither an object nor an array", path.display()),
+            Err(e) => panic!("cannot parse {}: {}", path.display(), e),
+        };
+        generate(&mut out, module, file, &events);
+    }
+    let out_path = Path::new(&std::env::var("OUT_DIR").unwrap()).join("perfmon.rs");
+    std::fs::write(out_path, out).unwrap();
+}
+
+/// Appends the module `module` with a constant per event and the list of all
+/// events.
+fn generate(out: &mut String, module: &str, file: &str, events: &[Value]) {
+    let mut names = Vec::new();
+    writeln!(out, "/// Events of `{}`.", file).unwrap();
+    writeln!(out, "pub mod {} {{", module).unwrap();
+    writeln!(out, "    use super::PerfmonEvent;").unwrap();
+    for event in events {
+        let fields = event.as_object().unwrap_or_else(|| panic!("event in {} is not an object", file));
+        let field = |key: &str| fields.get(key).and_then(Value::as_str).unwrap_or("");
+        if "1" == field("Deprecated") {
+            continue;
+        }
+        // Offcore-response events can use either MSR_OFFCORE_RSP register,
+        // the first event code selects MSR_OFFCORE_RSP_0
+        let event_select = parse_int(field("EventCode").split(',').next().unwrap());
+        // Event code 0 is only counted by the fixed counters
+        if 0 == event_select {
+            continue;
+        }
+        let name = field("EventName");
+        let constant: String = name
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+            .collect();
+        let offcore_rsp = match parse_int(field("MSRIndex").split(',').next().unwrap()) {
+            0x1a6 => format!("Some({:#x})", parse_int(field("MSRValue"))),
+            _ => "None".into(),
+        };
+        writeln!(out, "    #[doc = {:?}]", field("BriefDescription")).unwrap();
+        writeln!(
+            out,
+            "    pub const {}: PerfmonEvent = PerfmonEvent {{ name: {:?}, event_select: {:#04x}, umask: {:#04x}, cmask: {}, invert: {}, edge: {}, offcore_rsp: {} }};",
+            constant,
+            name,
+            event_select,
+            parse_int(field("UMask")),
+            parse_int(field("CounterMask")),
+            0 != parse_int(field("Invert")),
+            0 != parse_int(field("EdgeDetect")),
+            offcore_rsp,
+        )
+        .unwrap();
+        names.push(constant);
+    }
+    writeln!(out, "    /// All events of this microarchitecture.").unwrap();
+    writeln!(out, "    pub const EVENTS: &[PerfmonEvent] = &[{}];", names.join(", ")).unwrap();
+    writeln!(out, "}}").unwrap();
+}
+
+/// Parses a decimal or `0x`-prefixed hexadecimal number. Empty strings are 0.
+fn parse_int(value: &str) -> u64 {
+    let value = value.trim();
+    let parsed = match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None if value.is_empty() => Ok(0),
+        None => value.parse(),
+    };
+    parsed.unwrap_or_else(|_| panic!("invalid number {:?}", value))
+}
@@ -0,0 +1,23 @@
+use lib::sim::Simulator;
+use lib::state_machine::task_id::TaskId;
+
+fn main() {
+    let mut sim = Simulator::default();
+    println!("TEE status after init: {:?}", sim.status());
+
+    let mut value = 0;
+    for _ in 0..3 {
+        let status = sim.call(TaskId::PING, &[value]);
+        value = sim.output()[0];
+        println!(
+            "Ping -> status={:?}, task={:?}, output={}",
+            status,
+            sim.task(),
+            value
+        );
+    }
+
+    let status = sim.call(TaskId::ATTACK_WRITE_MEM, &[]);
+    let phys = u64::from_le_bytes(sim.output()[2..10].try_into().unwrap());
+    println!("AttackWriteMem -> status={status:?}, buffer at {phys:#016x} (phys)");
+}
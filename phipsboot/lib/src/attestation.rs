@@ -0,0 +1,136 @@
+//! Attestation reports that let the host verify which TEE it talks to.
+//!
+//! A [`Report`] binds the [measurement](crate::measurement) of the TEE, the CPU
+//! it runs on and the configuration of the tamper-detection PMCs to a nonce of
+//! the host. It is signed with the Ed25519 attestation key, which is derived
+//! with HKDF-SHA256 from the boot secret and the measurement, just like the
+//! [`SealingKey`](crate::seal::SealingKey). Whoever provisioned the boot secret
+//! can therefore compute the public key of every TEE binary they trust.
+//!
+//! ```text
+//! 0         1             33           65              69          73           105     137
+//! | version | measurement | public key | CPU signature | microcode | PMC config | nonce | signature |
+//! ```
+//!
+//! Integers are little-endian. The signature covers all preceding bytes.
+
+use alloc::vec::Vec;
+use ed25519_dalek::{Signer, SigningKey};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+use crate::measurement::Measurement;
+use crate::platform::{Platform, COUNTER_NUM};
+
+/// Version of the report format described in the [module docs](self).
+pub const REPORT_VERSION: u8 = 1;
+/// Length of the nonce the host supplies.
+pub const NONCE_LEN: usize = 32;
+/// Length of a report without its signature.
+pub const REPORT_LEN: usize = 1 + 32 + 32 + 4 + 4 + COUNTER_NUM * 8 + NONCE_LEN;
+/// Length of a report including its signature.
+pub const SIGNED_REPORT_LEN: usize = REPORT_LEN + ed25519_dalek::SIGNATURE_LENGTH;
+
+/// Identifies the CPU the TEE runs on.
+#[derive(Debug, Copy, Clone, Default, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct CpuInfo {
+    /// Family, model and stepping, i.e., `EAX` of CPUID leaf 1.
+    pub signature: u32,
+    /// Revision of the loaded microcode. 0 if unknown.
+    pub microcode: u32,
+}
+
+/// Contents of an attestation report.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Report {
+    pub measurement: Measurement,
+    pub cpu: CpuInfo,
+    /// Values of the event select registers of the tamper-detection PMCs.
+    pub pmc_config: [u64; COUNTER_NUM],
+    pub nonce: [u8; NONCE_LEN],
+}
+
+impl Report {
+    /// Collects a report for `nonce` from `platform`.
+    pub fn new(platform: &dyn Platform, nonce: [u8; NONCE_LEN]) -> Self {
+        Self {
+            measurement: platform.measurement(),
+            cpu: platform.cpu_info(),
+            pmc_config: platform.pmc_config(),
+            nonce,
+        }
+    }
+
+    /// Encodes the report and signs it with `key`.
+    pub fn sign(&self, key: &SigningKey) -> [u8; SIGNED_REPORT_LEN] {
+        let mut report = Vec::with_capacity(SIGNED_REPORT_LEN);
+        report.push(REPORT_VERSION);
+        report.extend_from_slice(&self.measurement);
+        report.extend_from_slice(key.verifying_key().as_bytes());
+        report.extend_from_slice(&self.cpu.signature.to_le_bytes());
+        report.extend_from_slice(&self.cpu.microcode.to_le_bytes());
+        for config in self.pmc_config {
+            report.extend_from_slice(&config.to_le_bytes());
+        }
+        report.extend_from_slice(&self.nonce);
+        let signature = key.sign(&report);
+        report.extend_from_slice(&signature.to_bytes());
+        report.try_into().unwrap()
+    }
+}
+
+/// Derives the attestation key of the TEE with the measurement `measurement`
+/// that received `boot_secret`.
+pub fn derive_key(measurement: &Measurement, boot_secret: &[u8]) -> SigningKey {
+    let hkdf = Hkdf::<Sha256>::new(Some(measurement), boot_secret);
+    let mut seed = Zeroizing::new([0; ed25519_dalek::SECRET_KEY_LENGTH]);
+    // Only fails for outputs longer than 255 hashes
+    hkdf.expand(b"phipsboot attestation key", &mut *seed).unwrap();
+    SigningKey::from_bytes(&seed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+
+    const MEASUREMENT: Measurement = [0x11; 32];
+    const BOOT_SECRET: [u8; 32] = [0x22; 32];
+
+    fn report() -> Report {
+        Report {
+            measurement: MEASUREMENT,
+            cpu: CpuInfo { signature: 0x000906ea, microcode: 0xf0 },
+            pmc_config: [0x530151, 0x5310d1, 0x5304d1, 0x5320d1],
+            nonce: [0x33; NONCE_LEN],
+        }
+    }
+
+    #[test]
+    fn test_sign() {
+        let key = derive_key(&MEASUREMENT, &BOOT_SECRET);
+        let signed = report().sign(&key);
+        assert_eq!(signed[0], REPORT_VERSION);
+        assert_eq!(signed[1..33], MEASUREMENT);
+        assert_eq!(signed[33..65], key.verifying_key().to_bytes());
+        assert_eq!(signed[65..69], 0x000906ea_u32.to_le_bytes());
+        assert_eq!(signed[73..81], 0x530151_u64.to_le_bytes());
+        assert_eq!(signed[REPORT_LEN - NONCE_LEN..REPORT_LEN], [0x33; NONCE_LEN]);
+
+        let public_key = VerifyingKey::from_bytes(signed[33..65].try_into().unwrap()).unwrap();
+        let signature = Signature::from_bytes(signed[REPORT_LEN..].try_into().unwrap());
+        assert!(public_key.verify(&signed[..REPORT_LEN], &signature).is_ok());
+        let mut tampered = signed;
+        tampered[REPORT_LEN - 1] ^= 1;
+        assert!(public_key.verify(&tampered[..REPORT_LEN], &signature).is_err());
+    }
+
+    #[test]
+    fn test_derive_key() {
+        let key = derive_key(&MEASUREMENT, &BOOT_SECRET);
+        assert_eq!(key.to_bytes(), derive_key(&MEASUREMENT, &BOOT_SECRET).to_bytes());
+        assert_ne!(key.to_bytes(), derive_key(&[0x12; 32], &BOOT_SECRET).to_bytes());
+        assert_ne!(key.to_bytes(), derive_key(&MEASUREMENT, &[0x23; 32]).to_bytes());
+    }
+}
@@ -0,0 +1,200 @@
+//! Benchmarks of the cache behavior of the TEE.
+//!
+//! Each [`Benchmark`] runs a workload for a number of passes and counts a
+//! cache event per pass via [`Platform::count_event`]. They are selected on
+//! the command line of the loader (`--bench=<name>`) and run at boot, before
+//! the tamper detection is armed, as counting stops its PMCs. The results are
+//! [recorded](record) and the host fetches them with the
+//! [`bench`](crate::tasks::bench::bench) task. A result is encoded as
+//!
+//! ```text
+//! 0           1        2
+//! | benchmark | passes | count of pass 0 | count of pass 1 | ...
+//! ```
+//!
+//! with little-endian `u64` counts.
+
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::ptr;
+use core::str::FromStr;
+use sha2::{Digest, Sha256};
+
+use crate::platform::Platform;
+use crate::pmc_utils::events::Event;
+use crate::state_machine::session::SessionBuffer;
+
+/// Size of the memory a benchmark works on if none is requested.
+pub const DEFAULT_BENCH_SIZE: usize = 0x10000;
+/// Number of passes if none is requested.
+pub const DEFAULT_PASSES: u8 = 8;
+/// Maximum size of the memory a benchmark works on.
+pub const MAX_BENCH_SIZE: usize = 0x20000;
+/// Maximum number of passes of a benchmark.
+pub const MAX_PASSES: u8 = 64;
+/// Size of a cache line.
+const CACHE_LINE: usize = 64;
+
+/// Available benchmarks. The discriminant identifies the benchmark in
+/// results.
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub enum Benchmark {
+    /// Reads a buffer until no load misses all caches anymore, i.e., until
+    /// the whole buffer is cached. Stops after the first pass without misses.
+    MemPasses = 0x01,
+    /// Counts L1I misses of the TEE code that hashes a buffer. The first pass
+    /// runs with a cold instruction cache.
+    L1iMisses = 0x02,
+    /// Allocates a buffer on the heap and counts the L2 misses of reading it.
+    /// Misses in later passes mean that the heap spills out of the L2 cache.
+    HeapFootprint = 0x03,
+}
+
+impl FromStr for Benchmark {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "mem_passes" => Ok(Self::MemPasses),
+            "l1i_misses" => Ok(Self::L1iMisses),
+            "heap_footprint" => Ok(Self::HeapFootprint),
+            _ => Err(()),
+        }
+    }
+}
+
+impl TryFrom<u8> for Benchmark {
+    type Error = ();
+
+    fn try_from(id: u8) -> Result<Self, Self::Error> {
+        match id {
+            0x01 => Ok(Self::MemPasses),
+            0x02 => Ok(Self::L1iMisses),
+            0x03 => Ok(Self::HeapFootprint),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Result of a benchmark run.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct BenchResult {
+    pub benchmark: Benchmark,
+    /// Number of events per pass.
+    pub counts: Vec<u64>,
+}
+
+impl BenchResult {
+    /// Encodes the result as described in the [module docs](self).
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(2 + self.counts.len() * 8);
+        out.push(self.benchmark as u8);
+        // There are at most MAX_PASSES passes
+        out.push(self.counts.len() as u8);
+        for count in &self.counts {
+            out.extend_from_slice(&count.to_le_bytes());
+        }
+        out
+    }
+}
+
+/// Results of the benchmarks that ran at boot.
+#[cfg(not(test))]
+static RESULTS: crate::safe::Safe<RefCell<Vec<BenchResult>>> = crate::safe::Safe::new(RefCell::new(Vec::new()));
+
+// Tests run in parallel threads, each of them gets its own results.
+#[cfg(test)]
+std::thread_local! {
+    static RESULTS: RefCell<Vec<BenchResult>> = const { RefCell::new(Vec::new()) };
+}
+
+fn with_results<R>(f: impl FnOnce(&mut Vec<BenchResult>) -> R) -> R {
+    #[cfg(not(test))]
+    return f(&mut RESULTS.borrow_mut());
+    #[cfg(test)]
+    return RESULTS.with(|results| f(&mut results.borrow_mut()));
+}
+
+/// Keeps `result` for the host. Replaces an earlier result of the same
+/// benchmark.
+pub fn record(result: BenchResult) {
+    with_results(|results| {
+        results.retain(|recorded| recorded.benchmark != result.benchmark);
+        results.push(result);
+    });
+}
+
+/// Returns the recorded result of `benchmark`, `None` if it did not run.
+pub fn recorded(benchmark: Benchmark) -> Option<BenchResult> {
+    with_results(|results| results.iter().find(|result| result.benchmark == benchmark).cloned())
+}
+
+/// Reads one byte of every cache line of `buffer`.
+fn touch(buffer: &SessionBuffer) {
+    for offset in (0..buffer.len()).step_by(CACHE_LINE) {
+        unsafe { ptr::read_volatile(buffer.as_ptr().add(offset)) };
+    }
+}
+
+impl Benchmark {
+    /// Returns the event the benchmark counts.
+    pub fn event(self) -> Event {
+        match self {
+            Benchmark::MemPasses => Event::L3Miss,
+            Benchmark::L1iMisses => Event::L1iMiss,
+            Benchmark::HeapFootprint => Event::L2Miss,
+        }
+    }
+
+    /// Runs the benchmark on `size` bytes for at most `passes` passes. Both are
+    /// clamped to [`MAX_BENCH_SIZE`] and [`MAX_PASSES`].
+    pub fn run(self, platform: &dyn Platform, size: usize, passes: u8) -> BenchResult {
+        let buffer = SessionBuffer::new(size.clamp(CACHE_LINE, MAX_BENCH_SIZE), CACHE_LINE);
+        let mut counts = Vec::new();
+        for _ in 0..passes.min(MAX_PASSES) {
+            let count = match self {
+                Benchmark::MemPasses | Benchmark::HeapFootprint => {
+                    platform.count_event(self.event(), &mut || touch(&buffer))
+                }
+                Benchmark::L1iMisses => platform.count_event(self.event(), &mut || {
+                    core::hint::black_box(Sha256::digest(buffer.as_slice()));
+                }),
+            };
+            counts.push(count);
+            if Benchmark::MemPasses == self && 0 == count {
+                break;
+            }
+        }
+        BenchResult { benchmark: self, counts }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::SimPlatform;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Benchmark::from_str("mem_passes"), Ok(Benchmark::MemPasses));
+        assert_eq!(Benchmark::from_str("heap"), Err(()));
+        for benchmark in [Benchmark::MemPasses, Benchmark::L1iMisses, Benchmark::HeapFootprint] {
+            assert_eq!(Benchmark::try_from(benchmark as u8), Ok(benchmark));
+        }
+        assert_eq!(Benchmark::try_from(0), Err(()));
+    }
+
+    #[test]
+    fn test_run() {
+        let platform = SimPlatform::default();
+        // The simulator counts no events, so the buffer is cached immediately
+        let result = Benchmark::MemPasses.run(&platform, 0x1000, 10);
+        assert_eq!(result.counts, [0]);
+        let result = Benchmark::HeapFootprint.run(&platform, usize::MAX, u8::MAX);
+        assert_eq!(result.counts.len(), usize::from(MAX_PASSES));
+
+        let result = BenchResult { benchmark: Benchmark::L1iMisses, counts: alloc::vec![1, 2] };
+        assert_eq!(result.encode(), [2, 2, 1, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0]);
+    }
+}
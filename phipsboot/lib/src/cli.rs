@@ -1,15 +1,20 @@
 //! CLI parsing of the loader. The CLI looks like this:
 //!
-//! `[--load=module-id-if-kernel] [--loggers=serial,debugcon]`
+//! `[--load=module-id-if-kernel] [--loggers=serial,debugcon] [--bench=benchmark]`
+//!
+//! See [`Benchmark`] for the names of the benchmarks.
 
 use ::regex::Regex;
 use alloc::string::{String, ToString};
 use alloc::vec::Vec;
 use core::str::FromStr;
 
+use crate::bench::Benchmark;
+
 mod regex {
     pub const LOAD: &str = "--load=(?P<load>[A-z0-9-_.]+)+";
     pub const LOGGERS: &str = "--loggers=(?P<loggers>[a-z]+(,[a-z]+)*)?";
+    pub const BENCH: &str = "--bench=(?P<bench>[a-z0-9_]+)";
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -34,6 +39,14 @@ impl FromStr for SupportedLogger {
 pub struct CliArgs {
     loggers: Vec<SupportedLogger>,
     load: String,
+    bench: Option<Benchmark>,
+}
+
+impl CliArgs {
+    /// Returns the benchmark to run at boot, if any.
+    pub fn bench(&self) -> Option<Benchmark> {
+        self.bench
+    }
 }
 
 impl FromStr for CliArgs {
@@ -44,6 +57,7 @@ impl FromStr for CliArgs {
 
         let regex_load = Regex::new(regex::LOAD).unwrap();
         let regex_loggers = Regex::new(regex::LOGGERS).unwrap();
+        let regex_bench = Regex::new(regex::BENCH).unwrap();
 
         if let Some(mtch) = regex_load.captures(cmdline) {
             args.load = mtch
@@ -63,12 +77,18 @@ impl FromStr for CliArgs {
             }
         }
 
+        if let Some(mtch) = regex_bench.captures(cmdline) {
+            let name = mtch.name("bench").map(|m| m.as_str()).unwrap_or("");
+            args.bench = Benchmark::from_str(name).ok();
+        }
+
         Ok(args)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::bench::Benchmark;
     use crate::cli::{CliArgs, SupportedLogger};
     use core::str::FromStr;
 
@@ -78,6 +98,7 @@ mod tests {
         let args = CliArgs::from_str(cmdline).unwrap();
         assert_eq!(args.load.as_str(), "");
         assert!(args.loggers.is_empty());
+        assert_eq!(args.bench(), None);
     }
 
     #[test]
@@ -95,4 +116,14 @@ mod tests {
             [SupportedLogger::Serial, SupportedLogger::Debugcon]
         );
     }
+
+    #[test]
+    fn test_cli_bench() {
+        let args = CliArgs::from_str("--loggers=serial --bench=heap_footprint").unwrap();
+        assert_eq!(args.bench(), Some(Benchmark::HeapFootprint));
+        assert_eq!(args.loggers, [SupportedLogger::Serial]);
+
+        let args = CliArgs::from_str("--bench=unknown").unwrap();
+        assert_eq!(args.bench(), None);
+    }
 }
@@ -0,0 +1,237 @@
+//! Entropy from the CPU.
+//!
+//! The preferred [`EntropySource`] is `RDSEED`, followed by `RDRAND`, as
+//! reported by CPUID. If neither is available, the timing jitter of the TSC is
+//! used. All raw samples pass the continuous health tests of NIST SP 800-90B in
+//! [`HealthTests`] and are conditioned with SHA-256. [`Entropy`] keeps the
+//! state of the tests across calls. Once they fail, the source is considered
+//! broken and no other source is used instead.
+//!
+//! The output seeds the [`Drbg`](crate::random::Drbg); it is too slow to be
+//! used directly.
+
+use core::arch::x86_64::{__cpuid, __cpuid_count, _rdrand64_step, _rdseed64_step, _rdtsc};
+use core::hint::black_box;
+use sha2::{Digest, Sha256};
+
+/// Hardware source of entropy.
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub enum EntropySource {
+    Rdseed,
+    Rdrand,
+    /// Jitter of the time stamp counter around a few memory accesses. It is
+    /// always available but yields less entropy per sample.
+    TscJitter,
+}
+
+/// Reason why no entropy could be gathered.
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub enum EntropyError {
+    /// The source did not deliver a sample.
+    SourceFailed,
+    /// The samples of the source failed the health tests.
+    HealthTestFailed,
+}
+
+/// Cutoff of the repetition count test: `1 + ceil(20 / H)` for a false
+/// positive probability of 2^-20 and an assumed min-entropy `H` of one bit per
+/// byte.
+const RCT_CUTOFF: u32 = 21;
+/// Window size of the adaptive proportion test.
+const APT_WINDOW: u32 = 512;
+/// Cutoff of the adaptive proportion test for the same `H` and window size.
+const APT_CUTOFF: u32 = 410;
+
+/// Continuous health tests of NIST SP 800-90B, section 4.4, on byte samples.
+///
+/// The parameters assume a min-entropy of only one bit per byte, so they only
+/// catch sources that are stuck or heavily biased.
+#[derive(Debug, Default)]
+pub struct HealthTests {
+    rct_sample: u8,
+    rct_count: u32,
+    apt_sample: u8,
+    apt_count: u32,
+    apt_index: u32,
+}
+
+impl HealthTests {
+    /// Feeds `sample` into the tests. Returns `false` if the source must be
+    /// considered broken.
+    pub fn feed(&mut self, sample: u8) -> bool {
+        // Repetition count test
+        if self.rct_count > 0 && sample == self.rct_sample {
+            self.rct_count += 1;
+        } else {
+            self.rct_sample = sample;
+            self.rct_count = 1;
+        }
+
+        // Adaptive proportion test
+        if 0 == self.apt_index {
+            self.apt_sample = sample;
+            self.apt_count = 1;
+        } else if sample == self.apt_sample {
+            self.apt_count += 1;
+        }
+        self.apt_index = (self.apt_index + 1) % APT_WINDOW;
+
+        self.rct_count < RCT_CUTOFF && self.apt_count < APT_CUTOFF
+    }
+}
+
+impl EntropySource {
+    /// Returns the best source the CPU reports.
+    pub fn detect() -> Self {
+        if 0 != unsafe { __cpuid_count(7, 0) }.ebx & (1 << 18) {
+            EntropySource::Rdseed
+        } else if 0 != unsafe { __cpuid(1) }.ecx & (1 << 30) {
+            EntropySource::Rdrand
+        } else {
+            EntropySource::TscJitter
+        }
+    }
+
+    /// Number of raw samples that are conditioned into 32 bytes of output.
+    /// This gives every output bit two bits of raw input for the hardware
+    /// generators, and two samples for the jitter, which is credited with one
+    /// bit per sample.
+    fn samples_per_block(self) -> usize {
+        match self {
+            EntropySource::Rdseed | EntropySource::Rdrand => 8,
+            EntropySource::TscJitter => 512,
+        }
+    }
+
+    /// Returns one raw sample.
+    fn sample(self) -> Result<u64, EntropyError> {
+        let mut value = 0;
+        let delivered = match self {
+            // RDSEED fails if its buffer is drained, so it needs more retries
+            EntropySource::Rdseed => (0..100).any(|_| {
+                let ok = 1 == unsafe { _rdseed64_step(&mut value) };
+                core::hint::spin_loop();
+                ok
+            }),
+            EntropySource::Rdrand => (0..10).any(|_| 1 == unsafe { _rdrand64_step(&mut value) }),
+            EntropySource::TscJitter => {
+                value = jitter_sample();
+                true
+            }
+        };
+        delivered.then_some(value).ok_or(EntropyError::SourceFailed)
+    }
+
+}
+
+/// An [`EntropySource`] together with the state of its [`HealthTests`].
+#[derive(Debug)]
+pub struct Entropy {
+    source: EntropySource,
+    tests: HealthTests,
+    broken: bool,
+}
+
+impl Entropy {
+    pub fn new(source: EntropySource) -> Self {
+        Self { source, tests: HealthTests::default(), broken: false }
+    }
+
+    /// Uses the best source the CPU reports.
+    pub fn detect() -> Self {
+        Self::new(EntropySource::detect())
+    }
+
+    pub fn source(&self) -> EntropySource {
+        self.source
+    }
+
+    /// Fills `buf` with conditioned entropy. Fails for good once the samples
+    /// failed the health tests.
+    pub fn fill(&mut self, buf: &mut [u8]) -> Result<(), EntropyError> {
+        if self.broken {
+            return Err(EntropyError::HealthTestFailed);
+        }
+        for chunk in buf.chunks_mut(32) {
+            let mut hash = Sha256::new();
+            for _ in 0..self.source.samples_per_block() {
+                let sample = self.source.sample()?;
+                // Only the low byte of the jitter varies
+                let bytes = match self.source {
+                    EntropySource::TscJitter => &sample.to_le_bytes()[..1],
+                    _ => &sample.to_le_bytes()[..],
+                };
+                if false == bytes.iter().all(|byte| self.tests.feed(*byte)) {
+                    log::error!("Entropy source {:?} failed the health tests", self.source);
+                    self.broken = true;
+                    return Err(EntropyError::HealthTestFailed);
+                }
+                hash.update(sample.to_le_bytes());
+            }
+            chunk.copy_from_slice(&hash.finalize()[..chunk.len()]);
+        }
+        Ok(())
+    }
+}
+
+/// Returns the number of cycles a few dependent memory accesses take.
+fn jitter_sample() -> u64 {
+    let mut memory = [0_u64; 64];
+    let start = unsafe { _rdtsc() };
+    let mut index = start as usize;
+    for _ in 0..16 {
+        index = (index.wrapping_mul(31) + 7) % memory.len();
+        memory[index] = black_box(memory[index].wrapping_add(index as u64));
+    }
+    black_box(&memory);
+    unsafe { _rdtsc() }.wrapping_sub(start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repetition_count() {
+        let mut tests = HealthTests::default();
+        assert!((1..RCT_CUTOFF).all(|_| tests.feed(7)));
+        assert!(!tests.feed(7));
+
+        let mut tests = HealthTests::default();
+        assert!((0..1000).all(|i| tests.feed((i % 2) as u8)));
+    }
+
+    #[test]
+    fn test_adaptive_proportion() {
+        // Passes the repetition count test, but is heavily biased
+        let mut tests = HealthTests::default();
+        let biased = (0..APT_WINDOW).map(|i| if 7 == i % 8 { 1 } else { 0 });
+        assert!(!biased.into_iter().all(|sample| tests.feed(sample)));
+
+        let mut tests = HealthTests::default();
+        assert!((0..4 * APT_WINDOW).all(|i| tests.feed((i * 97) as u8)));
+    }
+
+    #[test]
+    fn test_fill() {
+        let mut a = [0; 40];
+        let mut b = [0; 40];
+        let mut entropy = Entropy::new(EntropySource::TscJitter);
+        entropy.fill(&mut a).unwrap();
+        entropy.fill(&mut b).unwrap();
+        assert_ne!(a, [0; 40]);
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn test_tests_persist() {
+        // A stuck source is caught even if every call only feeds a few samples
+        let mut tests = HealthTests::default();
+        let mut fill = || (0..64).all(|i| tests.feed(if 7 == i % 8 { 1 } else { 0 }));
+        assert!((0..APT_WINDOW / 64).any(|_| !fill()));
+
+        let mut entropy = Entropy::new(EntropySource::TscJitter);
+        entropy.broken = true;
+        assert_eq!(entropy.fill(&mut [0; 8]), Err(EntropyError::HealthTestFailed));
+    }
+}
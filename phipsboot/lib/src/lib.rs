@@ -1,11 +1,27 @@
 #![no_std]
 extern crate alloc;
+// Allows `#[tee_task]` to refer to `::lib` from within this crate.
+extern crate self as lib;
 
 #[cfg(test)]
 extern crate std;
 
+pub mod attestation;
+pub mod bench;
 pub mod cli;
+pub mod entropy;
 pub mod logger;
+pub mod measurement;
 pub mod mem;
+pub mod platform;
+pub mod random;
 pub mod safe;
+pub mod sandbox;
+pub mod seal;
+pub mod secret;
+pub mod shared_mem_com;
+#[cfg(any(test, feature = "sim"))]
+pub mod sim;
+pub mod state_machine;
+pub mod tasks;
 pub mod pmc_utils;
@@ -0,0 +1,35 @@
+//! Measurement of the TEE.
+//!
+//! The measurement identifies the TEE binary. It is the SHA-256 hash over the
+//! segments that never change at runtime, i.e., the code and the read-only
+//! data, which the firmware passes to [`measure`].
+
+use sha2::{Digest, Sha256};
+
+/// SHA-256 hash that identifies the TEE binary.
+pub type Measurement = [u8; 32];
+
+/// Hashes `segments` in order. Each segment is prefixed with its length, so
+/// moving bytes between segments changes the measurement.
+pub fn measure(segments: &[&[u8]]) -> Measurement {
+    let mut hash = Sha256::new();
+    for segment in segments {
+        hash.update((segment.len() as u64).to_le_bytes());
+        hash.update(segment);
+    }
+    hash.finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_measure() {
+        let code = [0x90_u8; 16];
+        let rodata = [1_u8, 2, 3];
+        assert_eq!(measure(&[&code, &rodata]), measure(&[&code, &rodata]));
+        assert_ne!(measure(&[&code, &rodata]), measure(&[&code[..15], &[0x90, 1, 2, 3]]));
+        assert_ne!(measure(&[&code, &rodata]), measure(&[&rodata, &code]));
+    }
+}
@@ -17,6 +17,12 @@ static mut LAST_L1: u64 = 0;
 /// This uses the assumption that the last 128 entries are free
 static mut LAST_L1_INDEX: usize = 384-1;
 
+/// Returns an upper bound of the L1 entries that are left for
+/// [`map_phys_rel_base_addr`].
+pub fn free_l1_entries() -> usize {
+    512_usize.saturating_sub(unsafe { LAST_L1_INDEX })
+}
+
 #[derive(Copy, Clone, Debug, PartialOrd, PartialEq, Hash, Eq, Ord)]
 pub enum Level {
     One = 1,
@@ -194,9 +200,11 @@ pub unsafe fn map_phys_rel_base_addr(src: PhysAddr, size: usize, pml1: VirtAddr,
             // Create virtual address from start of the contiguous page block
             result = ((pml1_addr & (!0x1FFFFFu64)) + ((LAST_L1_INDEX as u64) << 12)) as u64;
             while (0 < pages_to_map) && (LAST_L1_INDEX < 512) {
+                // Consecutive pages map consecutive frames
+                let frame = (Into::<u64>::into(src) & (!0xFFFu64)) + (((size - pages_to_map) as u64) << 12);
                 ptr::write(
                     (pml1_addr as *mut u64).add(LAST_L1_INDEX) as *mut u64,
-                    (Into::<u64>::into(src) & ( & (!0xFFFu64))) | flags,
+                    frame | flags,
                 );
                 LAST_L1_INDEX += 1;
                 pages_to_map -= 1;
@@ -0,0 +1,88 @@
+//! Module for [`Platform`].
+//!
+//! The state machine and the tasks do not touch the hardware directly but go
+//! through a [`Platform`]. The firmware implements it with the real page tables
+//! and PMCs, while [`crate::sim`] provides mocked versions that run on a host.
+
+use crate::attestation::CpuInfo;
+use crate::entropy::EntropyError;
+use crate::measurement::Measurement;
+use crate::pmc_utils::events::Event;
+use crate::pmc_utils::global::OverflowStatus;
+use crate::pmc_utils::pmu::PmuInfo;
+use crate::state_machine::task::TaskError;
+use crate::tasks::module::ModuleImage;
+
+/// Number of general purpose PMCs that are programmed for tamper detection. The
+/// firmware only arms them if the [`PmuInfo`](crate::pmc_utils::pmu::PmuInfo)
+/// reports at least as many.
+pub const COUNTER_NUM: usize = 4;
+
+/// Hardware services the TEE depends on.
+pub trait Platform {
+    /// Returns the physical address the given virtual address is mapped to. If
+    /// the address is not mapped, 0 is returned.
+    fn get_physical_address(&self, virt_addr: u64) -> u64;
+
+    /// Returns the virtual address the given physical address is mapped to. If
+    /// no mapping exists, 0 is returned.
+    fn get_virtual_address(&self, phys_addr: u64) -> u64;
+
+    /// Programs and arms the PMCs used for tamper detection. Returns `false`
+    /// if a PMC that raises an interrupt could not be armed.
+    fn setup_pmcs(&self) -> bool;
+
+    /// Returns `true` if [`Platform::setup_pmcs`] armed all PMCs that raise
+    /// an interrupt. The TEE refuses all requests otherwise.
+    fn pmcs_armed(&self) -> bool;
+
+    /// Returns the current values of the PMCs configured by
+    /// [`Platform::setup_pmcs`].
+    fn read_pmcs(&self) -> [u64; COUNTER_NUM];
+
+    /// Returns the capabilities of the PMU these PMCs belong to.
+    fn pmu(&self) -> PmuInfo;
+
+    /// Returns the PMCs that overflowed since they were last re-armed. Their
+    /// overflow raises the PMI if they were configured to do so.
+    fn pmc_overflows(&self) -> OverflowStatus;
+
+    /// Acknowledges the overflow of the PMCs in `status` and resets the ones
+    /// configured by [`Platform::setup_pmcs`], so that they overflow on the
+    /// next event again.
+    fn rearm_pmcs(&self, status: OverflowStatus);
+
+    /// Returns the current value of the time-stamp counter.
+    fn read_tsc(&self) -> u64;
+
+    /// Returns the values of the event select registers of the PMCs configured
+    /// by [`Platform::setup_pmcs`].
+    fn pmc_config(&self) -> [u64; COUNTER_NUM];
+
+    /// Runs `f` and returns how often `event` occurred meanwhile, 0 if the CPU
+    /// cannot count it. It reprograms a PMC configured by
+    /// [`Platform::setup_pmcs`], so it is only used by benchmarks at boot.
+    fn count_event(&self, event: Event, f: &mut dyn FnMut()) -> u64;
+
+    /// Executes a task that was loaded from a module. Returns the number of
+    /// bytes written to `output`.
+    fn run_module(&self, image: &ModuleImage, input: &[u8], output: &mut [u8]) -> Result<usize, TaskError>;
+
+    /// Returns the measurement of the TEE binary.
+    fn measurement(&self) -> Measurement;
+
+    /// Returns the model and microcode revision of the CPU.
+    fn cpu_info(&self) -> CpuInfo;
+
+    /// Returns the secret the TEE received at boot. It stays the same across
+    /// reboots and is the root of the [`SealingKey`](crate::seal::SealingKey).
+    /// `None` if no secret was provided or it was wiped, see
+    /// [`secret::is_wiped`](crate::secret::is_wiped).
+    fn boot_secret(&self) -> Option<&[u8]>;
+
+    /// Fills `buf` with conditioned entropy from the hardware, see
+    /// [`crate::entropy`]. Tasks use the [`Drbg`](crate::random::Drbg) via
+    /// [`TaskEnv::fill_random`](crate::state_machine::task::TaskEnv::fill_random)
+    /// instead.
+    fn fill_entropy(&self, buf: &mut [u8]) -> Result<(), EntropyError>;
+}
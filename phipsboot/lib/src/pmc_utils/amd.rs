@@ -0,0 +1,289 @@
+//! PMCs of AMD CPUs.
+//!
+//! AMD calls the GP PMCs core performance counters. Each of them has a
+//! PERF_CTL register that selects the event and a PERF_CTR register with the
+//! count. With the PerfCtrExtCore feature, which all Zen CPUs have, six of
+//! them are available and their registers alternate:
+//!
+//! ```text
+//! | PERF_CTL0 | PERF_CTR0 | PERF_CTL1 | PERF_CTR1 | ...
+//!  0xc0010200  0xc0010201  0xc0010202  0xc0010203
+//! ```
+//!
+//! PERF_CTL has the same layout as IA32_PERFEVTSELx, so the
+//! `IA32_PERFEVTSEL_*` flags apply. Only the event select is wider: its bits
+//! 11:8 are in bits 35:32, see [`encode_event`].
+
+use core::arch::x86_64::{CpuidResult, __cpuid};
+use log::info;
+
+use crate::pmc_utils::architectural::IA32_PERFEVTSEL_EN;
+use crate::pmc_utils::events::Event;
+use crate::pmc_utils::msr::{HardwareMsr, MsrAccess};
+use crate::pmc_utils::pmu::{PmcError, PmuInfo};
+use crate::pmc_utils::uarch::Microarchitecture;
+use crate::pmc_utils::vendor;
+
+/// PERF_CTL of core counter 0.
+pub const MSR_F15H_PERF_CTL0: u32 = 0xc001_0200;
+/// PERF_CTR of core counter 0.
+pub const MSR_F15H_PERF_CTR0: u32 = 0xc001_0201;
+/// Number of core counters with PerfCtrExtCore.
+pub const AMD_CORE_COUNTERS: u8 = 6;
+/// Width of the core counters in bits.
+pub const AMD_COUNTER_WIDTH: u8 = 48;
+
+/// CPUID leaf with the extended feature flags.
+const CPUID_LEAF_EXT_FEATURES: u32 = 0x8000_0001;
+/// PerfCtrExtCore flag in ECX of CPUID leaf 0x80000001.
+const PERF_CTR_EXT_CORE: u32 = 0x1 << 23;
+
+/// Returns the PERF_CTL encoding of the 12-bit event select `event` and the
+/// unit mask `umask`.
+pub const fn encode_event(event: u16, umask: u8) -> u64 {
+    (event as u64 & 0xff) | (umask as u64) << 8 | ((event as u64 >> 8) & 0xf) << 32
+}
+
+// Demand data cache fills by source. Zen 2 calls them ls_refills_from_sys,
+// later generations ls_dmnd_fills_from_sys and add near and far caches.
+const ZEN2: &[(Event, u64)] = &[
+    // ls_refills_from_sys, all sources
+    (Event::L1DReplacement, encode_event(0x43, 0x5b)),
+    // ls_refills_from_sys, all sources but the local L2
+    (Event::L2Miss, encode_event(0x43, 0x5a)),
+    // ls_refills_from_sys.ls_mabresp_lcl_cache
+    (Event::L3Hit, encode_event(0x43, 0x02)),
+    // ls_refills_from_sys, DRAM and remote caches
+    (Event::L3Miss, encode_event(0x43, 0x58)),
+    // l2_cache_req_stat.ic_dc_miss_in_l2
+    (Event::L2RequestMiss, encode_event(0x64, 0x09)),
+];
+
+const ZEN3: &[(Event, u64)] = &[
+    // ls_any_fills_from_sys, all sources
+    (Event::L1DReplacement, encode_event(0x44, 0x5f)),
+    // ls_dmnd_fills_from_sys, all sources but the local L2
+    (Event::L2Miss, encode_event(0x43, 0x5e)),
+    // ls_dmnd_fills_from_sys.int_cache
+    (Event::L3Hit, encode_event(0x43, 0x02)),
+    // ls_dmnd_fills_from_sys, DRAM and caches of other CCXs
+    (Event::L3Miss, encode_event(0x43, 0x5c)),
+    // l2_cache_req_stat.ic_dc_miss_in_l2
+    (Event::L2RequestMiss, encode_event(0x64, 0x09)),
+];
+
+const ZEN4: &[(Event, u64)] = &[
+    // ls_any_fills_from_sys, all sources including alternate memories
+    (Event::L1DReplacement, encode_event(0x44, 0xdf)),
+    // ls_dmnd_fills_from_sys, all sources but the local L2
+    (Event::L2Miss, encode_event(0x43, 0xde)),
+    // ls_dmnd_fills_from_sys.local_ccx
+    (Event::L3Hit, encode_event(0x43, 0x02)),
+    // ls_dmnd_fills_from_sys, DRAM and caches of other CCXs
+    (Event::L3Miss, encode_event(0x43, 0xdc)),
+    // l2_cache_req_stat.ic_dc_miss_in_l2
+    (Event::L2RequestMiss, encode_event(0x64, 0x09)),
+];
+
+/// Returns the PERF_CTL encoding of `event` on `uarch`. Fails if `uarch` is
+/// not an AMD microarchitecture or does not support the event.
+pub fn encode(uarch: Microarchitecture, event: Event) -> Result<u64, PmcError> {
+    let table = match uarch {
+        Microarchitecture::Zen2 => ZEN2,
+        Microarchitecture::Zen3 => ZEN3,
+        Microarchitecture::Zen4 => ZEN4,
+        _ => &[],
+    };
+    table
+        .iter()
+        .find(|(x, _)| *x == event)
+        .map(|(_, encoding)| *encoding)
+        .ok_or(PmcError::UnsupportedEvent(event))
+}
+
+/// Decodes CPUID leaf 0x80000001. CPUs without PerfCtrExtCore only have the
+/// four legacy counters, which are not supported, and get no counters at all.
+pub fn pmu_from_cpuid(leaf: CpuidResult) -> PmuInfo {
+    if 0 == leaf.ecx & PERF_CTR_EXT_CORE {
+        return PmuInfo::default();
+    }
+    PmuInfo {
+        gp_counters: AMD_CORE_COUNTERS,
+        gp_width: AMD_COUNTER_WIDTH,
+        ..PmuInfo::default()
+    }
+}
+
+/// Queries the core counters of the current CPU. Returns the default value on
+/// CPUs that are not from AMD.
+pub fn query_pmu() -> PmuInfo {
+    if vendor::get_cpu_vendor() != vendor::CpuVendor::Amd {
+        return PmuInfo::default();
+    }
+    let max_leaf = unsafe { __cpuid(0x8000_0000) }.eax;
+    if max_leaf < CPUID_LEAF_EXT_FEATURES {
+        return PmuInfo::default();
+    }
+    pmu_from_cpuid(unsafe { __cpuid(CPUID_LEAF_EXT_FEATURES) })
+}
+
+/// Queries and logs the core counters of the current CPU.
+pub fn query_features_amd() -> PmuInfo {
+    let pmu = query_pmu();
+    info!("{:?}", pmu);
+    pmu
+}
+
+/// Core performance counter of an AMD CPU. Accesses the MSRs via `M` and
+/// checks against the [`PmuInfo`] whether the CPU implements the counter.
+#[derive(Copy, Debug, Clone)]
+pub struct AmdEventCounter<M = HardwareMsr> {
+    pmc_index: u8,
+    event_config: u64,
+    pmu: PmuInfo,
+    msr: M,
+}
+
+impl AmdEventCounter {
+    /// Creates a new AmdEventCounter for core counter `index`.
+    ///
+    /// * `index`   - Index of the PERF_CTLx/PERF_CTRx pair to use
+    ///
+    /// # Safety
+    /// Must only be called in ring 0, see [`HardwareMsr::new`].
+    pub unsafe fn new(index: u8) -> Self {
+        Self::with_msr(query_pmu(), index, HardwareMsr::new())
+    }
+}
+
+impl<M: MsrAccess> AmdEventCounter<M> {
+    /// Creates a new AmdEventCounter for core counter `index` on a CPU with
+    /// the PMU `pmu` that accesses the MSRs via `msr`.
+    ///
+    /// * `pmu`     - Capabilities of the PMU
+    /// * `index`   - Index of the PERF_CTLx/PERF_CTRx pair to use
+    /// * `msr`     - Access to the MSRs
+    pub fn with_msr(pmu: PmuInfo, index: u8, msr: M) -> Self {
+        Self {
+            pmc_index: index,
+            event_config: 0x0_u64,
+            pmu,
+            msr,
+        }
+    }
+
+    /// Updates the configuration stored in this struct.
+    ///
+    /// This does not automatically write to PERF_CTLx.
+    ///
+    /// * `event_config`- Bitvector to use for later operations
+    pub fn set_configuration(&mut self, event_config: u64) {
+        self.event_config = event_config;
+    }
+
+    /// Returns the index of the PERF_CTLx/PERF_CTRx pair in use.
+    pub fn index(&self) -> u8 {
+        self.pmc_index
+    }
+
+    fn ctl(&self) -> u32 {
+        MSR_F15H_PERF_CTL0 + 2 * u32::from(self.pmc_index)
+    }
+
+    fn ctr(&self) -> u32 {
+        MSR_F15H_PERF_CTR0 + 2 * u32::from(self.pmc_index)
+    }
+
+    /// Stops the counter, resets it to `init_v` and starts it with the stored
+    /// configuration. Bits of `init_v` beyond the width of the counter are
+    /// dropped.
+    ///
+    /// Fails if the CPU does not implement the counter.
+    ///
+    /// * `init_v`: Value to reset the counter to
+    pub fn activate_counter(&self, init_v: u64) -> Result<(), PmcError> {
+        self.pmu.check_counter(self.pmc_index)?;
+        self.msr.write(self.ctl(), 0x0_u64);
+        self.msr.write(self.ctr(), init_v & self.pmu.counter_mask());
+        self.msr.write(self.ctl(), self.event_config | IA32_PERFEVTSEL_EN);
+        Ok(())
+    }
+
+    /// Stops the counter by clearing PERF_CTLx. Its value is kept.
+    pub fn deactivate_counter(&self) -> Result<(), PmcError> {
+        self.pmu.check_counter(self.pmc_index)?;
+        self.msr.write(self.ctl(), 0);
+        Ok(())
+    }
+
+    /// Returns the value of the counter. Fails if the CPU does not implement
+    /// it.
+    pub fn read_pcm_val(&self) -> Result<u64, PmcError> {
+        self.pmu.check_counter(self.pmc_index)?;
+        Ok(self.msr.read(self.ctr()))
+    }
+
+    /// Sets the counter to `value` without touching its configuration. Fails
+    /// if the CPU does not implement it.
+    pub fn reset_pcm_val(&self, value: u64) -> Result<(), PmcError> {
+        self.pmu.check_counter(self.pmc_index)?;
+        self.msr.write(self.ctr(), value & self.pmu.counter_mask());
+        Ok(())
+    }
+
+    /// Returns the content of PERF_CTLx. Fails if the CPU does not implement
+    /// the counter.
+    pub fn read_configuration(&self) -> Result<u64, PmcError> {
+        self.pmu.check_counter(self.pmc_index)?;
+        Ok(self.msr.read(self.ctl()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pmc_utils::architectural::{IA32_PERFEVTSEL_INT, IA32_PERFEVTSEL_OS};
+    use crate::pmc_utils::msr::MockMsr;
+
+    /// CPUID leaf 0x80000001 of a Zen 2 CPU.
+    const ZEN2_EXT_FEATURES: CpuidResult = CpuidResult { eax: 0x00830f10, ebx: 0x40000000, ecx: 0x75c237ff, edx: 0x2fd3fbff };
+
+    #[test]
+    fn test_pmu_from_cpuid() {
+        let pmu = pmu_from_cpuid(ZEN2_EXT_FEATURES);
+        assert_eq!((pmu.version, pmu.gp_counters, pmu.gp_width, pmu.fixed_counters), (0, 6, 48, 0));
+        let pmu = pmu_from_cpuid(CpuidResult { ecx: ZEN2_EXT_FEATURES.ecx & !PERF_CTR_EXT_CORE, ..ZEN2_EXT_FEATURES });
+        assert_eq!(pmu, PmuInfo::default());
+    }
+
+    #[test]
+    fn test_activate_counter() {
+        let msr = MockMsr::default();
+        let pmu = pmu_from_cpuid(ZEN2_EXT_FEATURES);
+        let mut counter = AmdEventCounter::with_msr(pmu, 5, &msr);
+        counter.set_configuration(encode_event(0x43, 0x58) | IA32_PERFEVTSEL_OS | IA32_PERFEVTSEL_INT);
+        assert_eq!(counter.activate_counter(u64::MAX), Ok(()));
+        assert_eq!(msr.take_writes(), [
+            (0xc001_020a, 0),
+            (0xc001_020b, 0xffff_ffff_ffff),
+            (0xc001_020a, 0x5843 | IA32_PERFEVTSEL_OS | IA32_PERFEVTSEL_INT | IA32_PERFEVTSEL_EN),
+        ]);
+        msr.set(0xc001_020b, 42);
+        assert_eq!(counter.read_pcm_val(), Ok(42));
+        assert_eq!(counter.read_configuration().map(|config| config & 0xffff), Ok(0x5843));
+
+        let counter = AmdEventCounter::with_msr(pmu, 6, &msr);
+        assert_eq!(counter.activate_counter(0), Err(PmcError::UnavailableCounter(6)));
+        assert_eq!(counter.reset_pcm_val(0), Err(PmcError::UnavailableCounter(6)));
+    }
+
+    #[test]
+    fn test_encode() {
+        // Bits 11:8 of the event select are in bits 35:32
+        assert_eq!(encode_event(0x1c0, 0x01), 0x1_0000_01c0);
+        assert_eq!(encode(Microarchitecture::Zen2, Event::L3Miss), Ok(0x5843));
+        assert_eq!(encode(Microarchitecture::Zen4, Event::L3Miss), Ok(0xdc43));
+        assert_eq!(encode(Microarchitecture::Zen3, Event::L1iMiss), Err(PmcError::UnsupportedEvent(Event::L1iMiss)));
+        assert_eq!(encode(Microarchitecture::IceLake, Event::L2Miss), Err(PmcError::UnsupportedEvent(Event::L2Miss)));
+    }
+}
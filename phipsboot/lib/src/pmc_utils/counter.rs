@@ -0,0 +1,285 @@
+//! Vendor-neutral interface of the GP PMCs.
+//!
+//! Intel's [`ArchitecturalEventCounter`] and AMD's [`AmdEventCounter`] both
+//! implement [`EventCounter`]. [`GpCounter`] is the one of the running CPU.
+
+use crate::pmc_utils::amd::{self, AmdEventCounter};
+use crate::pmc_utils::architectural::{
+    ArchitecturalEventCounter, IA32_PERFEVTSEL_EN, IA32_PERFEVTSEL_OS, IA32_PERFEVTSEL_USR,
+};
+use crate::pmc_utils::global::{gp_counter_bit, GlobalControl};
+use crate::pmc_utils::msr::{HardwareMsr, MsrAccess};
+use crate::pmc_utils::pmu::{PmcError, PmuInfo};
+use crate::pmc_utils::vendor::{self, CpuVendor};
+
+/// General purpose PMC. The event select uses the layout of
+/// IA32_PERFEVTSELx, which AMD shares.
+pub trait EventCounter {
+    /// Returns the index of the counter.
+    fn index(&self) -> u8;
+
+    /// Updates the configuration stored in the counter without writing it.
+    fn set_configuration(&mut self, event_config: u64);
+
+    /// Stops the counter, resets it to `init_v` and starts it with the stored
+    /// configuration.
+    fn activate_counter(&self, init_v: u64) -> Result<(), PmcError>;
+
+    /// Stops the counter. Its value is kept.
+    fn deactivate_counter(&self) -> Result<(), PmcError>;
+
+    /// Returns the value of the counter.
+    fn read_pcm_val(&self) -> Result<u64, PmcError>;
+
+    /// Sets the counter to `value` without touching its configuration.
+    fn reset_pcm_val(&self, value: u64) -> Result<(), PmcError>;
+
+    /// Returns the event select register of the counter.
+    fn read_configuration(&self) -> Result<u64, PmcError>;
+}
+
+impl<M: MsrAccess> EventCounter for ArchitecturalEventCounter<M> {
+    fn index(&self) -> u8 {
+        ArchitecturalEventCounter::index(self)
+    }
+
+    fn set_configuration(&mut self, event_config: u64) {
+        ArchitecturalEventCounter::set_configuration(self, event_config)
+    }
+
+    fn activate_counter(&self, init_v: u64) -> Result<(), PmcError> {
+        ArchitecturalEventCounter::activate_counter(self, init_v)
+    }
+
+    fn deactivate_counter(&self) -> Result<(), PmcError> {
+        ArchitecturalEventCounter::deactivate_counter(self)
+    }
+
+    fn read_pcm_val(&self) -> Result<u64, PmcError> {
+        ArchitecturalEventCounter::read_pcm_val(self)
+    }
+
+    fn reset_pcm_val(&self, value: u64) -> Result<(), PmcError> {
+        ArchitecturalEventCounter::reset_pcm_val(self, value)
+    }
+
+    fn read_configuration(&self) -> Result<u64, PmcError> {
+        ArchitecturalEventCounter::read_configuration(self)
+    }
+}
+
+impl<M: MsrAccess> EventCounter for AmdEventCounter<M> {
+    fn index(&self) -> u8 {
+        AmdEventCounter::index(self)
+    }
+
+    fn set_configuration(&mut self, event_config: u64) {
+        AmdEventCounter::set_configuration(self, event_config)
+    }
+
+    fn activate_counter(&self, init_v: u64) -> Result<(), PmcError> {
+        AmdEventCounter::activate_counter(self, init_v)
+    }
+
+    fn deactivate_counter(&self) -> Result<(), PmcError> {
+        AmdEventCounter::deactivate_counter(self)
+    }
+
+    fn read_pcm_val(&self) -> Result<u64, PmcError> {
+        AmdEventCounter::read_pcm_val(self)
+    }
+
+    fn reset_pcm_val(&self, value: u64) -> Result<(), PmcError> {
+        AmdEventCounter::reset_pcm_val(self, value)
+    }
+
+    fn read_configuration(&self) -> Result<u64, PmcError> {
+        AmdEventCounter::read_configuration(self)
+    }
+}
+
+/// GP PMC of the CPU vendor.
+#[derive(Copy, Debug, Clone)]
+pub enum GpCounter<M = HardwareMsr> {
+    Intel(ArchitecturalEventCounter<M>),
+    Amd(AmdEventCounter<M>),
+}
+
+impl GpCounter {
+    /// Creates the GP PMC `index` of the current CPU. `None` if the vendor
+    /// is neither Intel nor AMD.
+    ///
+    /// # Safety
+    /// Must only be called in ring 0, see [`HardwareMsr::new`].
+    pub unsafe fn new(index: u8) -> Option<Self> {
+        match vendor::get_cpu_vendor() {
+            CpuVendor::Intel => Some(Self::Intel(ArchitecturalEventCounter::new(index))),
+            CpuVendor::Amd => Some(Self::Amd(AmdEventCounter::new(index))),
+            CpuVendor::Unknown => None,
+        }
+    }
+}
+
+impl<M: MsrAccess> GpCounter<M> {
+    /// Creates the GP PMC `index` of a CPU from `vendor` with the PMU `pmu`
+    /// that accesses the MSRs via `msr`. `None` if the vendor is neither
+    /// Intel nor AMD.
+    pub fn with_msr(vendor: CpuVendor, pmu: PmuInfo, index: u8, msr: M) -> Option<Self> {
+        match vendor {
+            CpuVendor::Intel => Some(Self::Intel(ArchitecturalEventCounter::with_msr(pmu, index, msr))),
+            CpuVendor::Amd => Some(Self::Amd(AmdEventCounter::with_msr(pmu, index, msr))),
+            CpuVendor::Unknown => None,
+        }
+    }
+
+    /// Counts the event with the encoding `event` while `f` runs and returns
+    /// the count. `global` stops all other counters meanwhile, which AMD CPUs
+    /// cannot do. The configuration and the value of the counter are restored
+    /// afterwards.
+    pub fn count_event<G: MsrAccess>(&mut self, global: &GlobalControl<G>, event: u64, f: impl FnOnce()) -> Result<u64, PmcError> {
+        let config = self.read_configuration()?;
+        let value = self.read_pcm_val()?;
+        self.set_configuration(event | IA32_PERFEVTSEL_OS | IA32_PERFEVTSEL_USR);
+        self.activate_counter(0)?;
+        global.count_only(gp_counter_bit(self.index()), f);
+        let count = self.read_pcm_val()?;
+
+        if 0 == config & IA32_PERFEVTSEL_EN {
+            self.deactivate_counter()?;
+            self.reset_pcm_val(value)?;
+        } else {
+            self.set_configuration(config);
+            self.activate_counter(value)?;
+        }
+        Ok(count)
+    }
+
+    fn counter(&self) -> &dyn EventCounter {
+        match self {
+            Self::Intel(counter) => counter,
+            Self::Amd(counter) => counter,
+        }
+    }
+}
+
+impl<M: MsrAccess> EventCounter for GpCounter<M> {
+    fn index(&self) -> u8 {
+        self.counter().index()
+    }
+
+    fn set_configuration(&mut self, event_config: u64) {
+        match self {
+            Self::Intel(counter) => counter.set_configuration(event_config),
+            Self::Amd(counter) => counter.set_configuration(event_config),
+        }
+    }
+
+    fn activate_counter(&self, init_v: u64) -> Result<(), PmcError> {
+        self.counter().activate_counter(init_v)
+    }
+
+    fn deactivate_counter(&self) -> Result<(), PmcError> {
+        self.counter().deactivate_counter()
+    }
+
+    fn read_pcm_val(&self) -> Result<u64, PmcError> {
+        self.counter().read_pcm_val()
+    }
+
+    fn reset_pcm_val(&self, value: u64) -> Result<(), PmcError> {
+        self.counter().reset_pcm_val(value)
+    }
+
+    fn read_configuration(&self) -> Result<u64, PmcError> {
+        self.counter().read_configuration()
+    }
+}
+
+/// Queries and logs the PMU of the current CPU. Returns the default value on
+/// CPUs that are neither from Intel nor from AMD.
+pub fn query_features() -> PmuInfo {
+    match vendor::get_cpu_vendor() {
+        CpuVendor::Intel => crate::pmc_utils::intel::query_features_intel(),
+        CpuVendor::Amd => amd::query_features_amd(),
+        CpuVendor::Unknown => PmuInfo::default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pmc_utils::msr::MockMsr;
+    use crate::pmc_utils::pmu::TEST_PMU;
+    use crate::pmc_utils::architectural::IA32_PERFEVTSEL_E;
+    use x86::msr::{IA32_PERFEVTSEL0, IA32_PERFEVTSEL1, IA32_PERF_GLOBAL_CTRL, IA32_PMC0, IA32_PMC1};
+
+    #[test]
+    fn test_vendors() {
+        let msr = MockMsr::default();
+        assert!(GpCounter::with_msr(CpuVendor::Unknown, TEST_PMU, 1, &msr).is_none());
+        for vendor in [CpuVendor::Intel, CpuVendor::Amd] {
+            let mut counter = GpCounter::with_msr(vendor, TEST_PMU, 1, &msr).unwrap();
+            counter.set_configuration(0x10d1);
+            assert_eq!(counter.activate_counter(0), Ok(()));
+        }
+        assert_eq!(msr.take_writes(), [
+            (IA32_PERFEVTSEL1, 0),
+            (IA32_PMC1, 0),
+            (IA32_PERFEVTSEL1, 0x10d1 | IA32_PERFEVTSEL_EN | IA32_PERFEVTSEL_E),
+            (amd::MSR_F15H_PERF_CTL0 + 2, 0),
+            (amd::MSR_F15H_PERF_CTR0 + 2, 0),
+            (amd::MSR_F15H_PERF_CTL0 + 2, 0x10d1 | IA32_PERFEVTSEL_EN),
+        ]);
+
+        msr.set(amd::MSR_F15H_PERF_CTR0 + 2, 7);
+        let counter = GpCounter::with_msr(CpuVendor::Amd, TEST_PMU, 1, &msr).unwrap();
+        assert_eq!(counter.read_pcm_val(), Ok(7));
+    }
+
+    #[test]
+    fn test_count_event() {
+        let enabled = IA32_PERFEVTSEL_OS | IA32_PERFEVTSEL_USR | IA32_PERFEVTSEL_EN | IA32_PERFEVTSEL_E;
+        let msr = MockMsr::default();
+        msr.set(IA32_PMC0, 5);
+        msr.set(IA32_PERF_GLOBAL_CTRL, 0x3);
+        let global = GlobalControl::with_msr(TEST_PMU, &msr);
+        let mut counter = GpCounter::with_msr(CpuVendor::Intel, TEST_PMU, 0, &msr).unwrap();
+        let count = counter.count_event(&global, 0x10d1, || msr.set(IA32_PMC0, 42));
+        assert_eq!(count, Ok(42));
+        assert_eq!(msr.take_writes(), [
+            (IA32_PERFEVTSEL0, 0),
+            (IA32_PMC0, 0),
+            (IA32_PERFEVTSEL0, 0x10d1 | enabled),
+            (IA32_PERF_GLOBAL_CTRL, 0),
+            (IA32_PERF_GLOBAL_CTRL, 0x1),
+            (IA32_PERF_GLOBAL_CTRL, 0),
+            (IA32_PERF_GLOBAL_CTRL, 0x3),
+            (IA32_PERFEVTSEL0, 0),
+            (IA32_PMC0, 5),
+        ]);
+
+        // A running counter is resumed with its configuration and value
+        msr.set(IA32_PERFEVTSEL0, 0x4f2e | enabled);
+        msr.take_writes();
+        assert_eq!(counter.count_event(&global, 0x10d1, || msr.set(IA32_PMC0, 42)), Ok(42));
+        assert_eq!(&msr.take_writes()[7..], [
+            (IA32_PERFEVTSEL0, 0),
+            (IA32_PMC0, 5),
+            (IA32_PERFEVTSEL0, 0x4f2e | enabled),
+        ]);
+
+        // AMD CPUs have no global control, so only the counter is written
+        let pmu = PmuInfo { version: 0, ..TEST_PMU };
+        let global = GlobalControl::with_msr(pmu, &msr);
+        let mut counter = GpCounter::with_msr(CpuVendor::Amd, pmu, 0, &msr).unwrap();
+        let count = counter.count_event(&global, 0x10d1, || msr.set(amd::MSR_F15H_PERF_CTR0, 42));
+        assert_eq!(count, Ok(42));
+        assert_eq!(msr.take_writes(), [
+            (amd::MSR_F15H_PERF_CTL0, 0),
+            (amd::MSR_F15H_PERF_CTR0, 0),
+            (amd::MSR_F15H_PERF_CTL0, 0x10d1 | IA32_PERFEVTSEL_OS | IA32_PERFEVTSEL_USR | IA32_PERFEVTSEL_EN),
+            (amd::MSR_F15H_PERF_CTL0, 0),
+            (amd::MSR_F15H_PERF_CTR0, 0),
+        ]);
+    }
+}
@@ -0,0 +1,181 @@
+//! Events by name, resolved to the encoding of the running CPU.
+//!
+//! Each [`Event`] has a name in the perfmon files, which may differ on the
+//! E-cores of hybrid CPUs. On Intel CPUs, its encoding is looked up in the
+//! events [generated](crate::pmc_utils::perfmon) for the
+//! [`Microarchitecture`]. AMD CPUs have their own tables, see
+//! [`amd::encode`]. Events that are missing are not supported on that
+//! microarchitecture.
+
+use crate::pmc_utils::amd;
+use crate::pmc_utils::perfmon::{self, PerfmonEvent};
+use crate::pmc_utils::pmu::PmcError;
+use crate::pmc_utils::uarch::Microarchitecture;
+use crate::pmc_utils::vendor::CpuVendor;
+
+/// Events the TEE counts, independent of their encoding.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Event {
+    /// L1D.REPLACEMENT: Cache lines that were filled into the L1D cache.
+    L1DReplacement,
+    /// MEM_LOAD_RETIRED.L1_HIT: Loads that hit the L1D cache.
+    L1Hit,
+    /// MEM_LOAD_RETIRED.L1_MISS: Loads that missed the L1D cache.
+    L1Miss,
+    /// MEM_LOAD_RETIRED.L2_MISS: Loads that missed the L2 cache.
+    L2Miss,
+    /// MEM_LOAD_RETIRED.L3_HIT: Loads that hit the L3 cache.
+    L3Hit,
+    /// MEM_LOAD_RETIRED.L3_MISS: Loads that missed all caches.
+    L3Miss,
+    /// L2_RQSTS.MISS: All requests that missed the L2 cache.
+    L2RequestMiss,
+    /// L2_RQSTS.ALL_DEMAND_MISS: Demand requests that missed the L2 cache.
+    L2DemandMiss,
+    /// L2_RQSTS.ALL_DEMAND_DATA_RD: Demand data reads of the L2 cache.
+    L2DemandDataRead,
+    /// L2_LINES_IN.ALL: Cache lines that were filled into the L2 cache.
+    L2LinesIn,
+    /// LONGEST_LAT_CACHE.REFERENCE: References to the LLC.
+    LlcReferences,
+    /// LONGEST_LAT_CACHE.MISS: References that missed the LLC.
+    LlcMisses,
+    /// ICACHE_64B.IFTAG_MISS: Instruction fetches that missed the L1I cache.
+    L1iMiss,
+    /// TOPDOWN.SLOTS_P: Issue slots available to the core.
+    TopdownSlots,
+}
+
+impl Event {
+    /// Returns the name of the event in the perfmon files of the P-cores.
+    pub fn perfmon_name(self) -> &'static str {
+        match self {
+            Self::L1DReplacement => "L1D.REPLACEMENT",
+            Self::L1Hit => "MEM_LOAD_RETIRED.L1_HIT",
+            Self::L1Miss => "MEM_LOAD_RETIRED.L1_MISS",
+            Self::L2Miss => "MEM_LOAD_RETIRED.L2_MISS",
+            Self::L3Hit => "MEM_LOAD_RETIRED.L3_HIT",
+            Self::L3Miss => "MEM_LOAD_RETIRED.L3_MISS",
+            Self::L2RequestMiss => "L2_RQSTS.MISS",
+            Self::L2DemandMiss => "L2_RQSTS.ALL_DEMAND_MISS",
+            Self::L2DemandDataRead => "L2_RQSTS.ALL_DEMAND_DATA_RD",
+            Self::L2LinesIn => "L2_LINES_IN.ALL",
+            Self::LlcReferences => "LONGEST_LAT_CACHE.REFERENCE",
+            Self::LlcMisses => "LONGEST_LAT_CACHE.MISS",
+            Self::L1iMiss => "ICACHE_64B.IFTAG_MISS",
+            Self::TopdownSlots => "TOPDOWN.SLOTS_P",
+        }
+    }
+}
+
+impl Microarchitecture {
+    /// Returns all perfmon events this microarchitecture supports. Empty for
+    /// AMD microarchitectures.
+    pub fn events(self) -> &'static [PerfmonEvent] {
+        match self {
+            Self::Skylake => perfmon::skylake::EVENTS,
+            Self::IceLake => perfmon::icelake::EVENTS,
+            Self::RaptorCove => perfmon::raptor_cove::EVENTS,
+            Self::Gracemont => perfmon::gracemont::EVENTS,
+            Self::Zen2 | Self::Zen3 | Self::Zen4 => &[],
+        }
+    }
+
+    /// Returns the name of `event` in the perfmon files of this
+    /// microarchitecture.
+    pub fn perfmon_name(self, event: Event) -> &'static str {
+        match (self, event) {
+            // Requests that access the LLC missed the L2 cache
+            (Self::Gracemont, Event::L2Miss) => "LONGEST_LAT_CACHE.REFERENCE",
+            (Self::Gracemont, Event::L3Hit) => "MEM_LOAD_UOPS_RETIRED.L3_HIT",
+            (Self::Gracemont, Event::L3Miss) => "MEM_LOAD_UOPS_RETIRED.DRAM_HIT",
+            (Self::Gracemont, Event::L1iMiss) => "ICACHE.MISSES",
+            _ => event.perfmon_name(),
+        }
+    }
+
+    /// Returns the event called `name` in the perfmon files, e.g.,
+    /// `MEM_LOAD_RETIRED.L2_MISS`. `None` if this microarchitecture does not
+    /// support it.
+    pub fn find(self, name: &str) -> Option<&'static PerfmonEvent> {
+        perfmon::find(self.events(), name)
+    }
+
+    /// Returns the encoding of `event`, or an error if this
+    /// microarchitecture does not support it.
+    pub fn encode(self, event: Event) -> Result<u64, PmcError> {
+        if CpuVendor::Amd == self.vendor() {
+            return amd::encode(self, event);
+        }
+        self.find(self.perfmon_name(event))
+            .map(PerfmonEvent::encoding)
+            .ok_or(PmcError::UnsupportedEvent(event))
+    }
+}
+
+/// Returns the encoding of `event` on the current CPU. Fails if the CPU has no
+/// event table or does not support the event.
+pub fn resolve(event: Event) -> Result<u64, PmcError> {
+    Microarchitecture::detect()
+        .ok_or(PmcError::UnknownMicroarchitecture)?
+        .encode(event)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        for uarch in [Microarchitecture::Skylake, Microarchitecture::IceLake, Microarchitecture::RaptorCove] {
+            assert_eq!(uarch.encode(Event::L2Miss), Ok(0x10d1));
+        }
+        assert_eq!(Microarchitecture::IceLake.encode(Event::L2LinesIn), Ok(0x1ff1));
+        assert_eq!(Microarchitecture::RaptorCove.encode(Event::L2LinesIn), Ok(0x1f25));
+        assert_eq!(
+            Microarchitecture::Skylake.encode(Event::TopdownSlots),
+            Err(PmcError::UnsupportedEvent(Event::TopdownSlots))
+        );
+    }
+
+    #[test]
+    fn test_all_events_known() {
+        let events = [
+            Event::L1DReplacement, Event::L1Hit, Event::L1Miss, Event::L2Miss, Event::L3Hit, Event::L3Miss,
+            Event::L2RequestMiss, Event::L2DemandMiss, Event::L2DemandDataRead, Event::L2LinesIn,
+            Event::LlcReferences, Event::LlcMisses, Event::L1iMiss,
+        ];
+        for uarch in [Microarchitecture::Skylake, Microarchitecture::RaptorCove] {
+            for event in events {
+                assert!(uarch.encode(event).is_ok(), "{:?} on {:?}", event, uarch);
+            }
+        }
+        assert_eq!(Microarchitecture::RaptorCove.find("OCR.DEMAND_DATA_RD.ANY_RESPONSE").unwrap().offcore_rsp, Some(0x10001));
+
+        // Version 1.00 of the Ice Lake events lacks the L2 and LLC requests
+        let missing = [Event::L2RequestMiss, Event::LlcReferences, Event::LlcMisses];
+        for event in events {
+            let encoding = Microarchitecture::IceLake.encode(event);
+            assert_eq!(encoding.is_ok(), false == missing.contains(&event), "{:?}", event);
+        }
+
+        // The tamper-detection events are available on AMD as well
+        for uarch in [Microarchitecture::Zen2, Microarchitecture::Zen3, Microarchitecture::Zen4] {
+            for event in [Event::L1DReplacement, Event::L2Miss, Event::L3Hit, Event::L3Miss] {
+                assert!(uarch.encode(event).is_ok(), "{:?} on {:?}", event, uarch);
+            }
+        }
+    }
+
+    #[test]
+    fn test_gracemont() {
+        let uarch = Microarchitecture::Gracemont;
+        assert_eq!(uarch.encode(Event::L2Miss), Ok(0x4f2e));
+        assert_eq!(uarch.encode(Event::L3Hit), Ok(0x1cd1));
+        assert_eq!(uarch.encode(Event::L3Miss), Ok(0x80d1));
+        assert_eq!(uarch.encode(Event::LlcMisses), Ok(0x412e));
+        assert_eq!(uarch.encode(Event::L1iMiss), Ok(0x0280));
+        // The E-cores cannot count all tamper-detection events
+        assert_eq!(uarch.encode(Event::L1DReplacement), Err(PmcError::UnsupportedEvent(Event::L1DReplacement)));
+    }
+}
@@ -0,0 +1,169 @@
+//! Fixed-function PMCs.
+//!
+//! Each fixed counter counts a single event: instructions retired, core cycles
+//! and reference cycles. All of them are configured by a 4-bit field in
+//! IA32_FIXED_CTR_CTRL:
+//!
+//! ```text
+//! | ... | CTR2 | CTR1 | CTR0 |
+//!        11   8 7    4 3    0
+//! ```
+
+use x86::msr::{IA32_FIXED_CTR0, IA32_FIXED_CTR_CTRL};
+
+use crate::pmc_utils::msr::{HardwareMsr, MsrAccess};
+use crate::pmc_utils::pmu::{PmcError, PmuInfo};
+
+/// Index of the fixed counter for INST_RETIRED.ANY.
+pub const FIXED_INSTRUCTIONS_RETIRED: u8 = 0;
+/// Index of the fixed counter for CPU_CLK_UNHALTED.THREAD.
+pub const FIXED_CORE_CYCLES: u8 = 1;
+/// Index of the fixed counter for CPU_CLK_UNHALTED.REF_TSC.
+pub const FIXED_REFERENCE_CYCLES: u8 = 2;
+
+/// When set, the counter is incremented in privilege level 0.
+pub const IA32_FIXED_CTR_CTRL_OS: u64 = 0x1 << 0;
+/// When set, the counter is incremented in privilege level 1, 2 or 3.
+pub const IA32_FIXED_CTR_CTRL_USR: u64 = 0x1 << 1;
+/// When set, the counter is incremented for all logical cores of the physical
+/// core.
+pub const IA32_FIXED_CTR_CTRL_ANY: u64 = 0x1 << 2;
+/// When set, the logical processor generates an exception through its local
+/// APIC on counter overflow.
+pub const IA32_FIXED_CTR_CTRL_PMI: u64 = 0x1 << 3;
+/// Width of the field of a counter in IA32_FIXED_CTR_CTRL.
+const FIELD_WIDTH: u32 = 4;
+
+/// Fixed-function PMC. Accesses the MSRs via `M` and checks against the
+/// [`PmuInfo`] whether the CPU implements the counter.
+#[derive(Copy, Debug, Clone)]
+pub struct FixedEventCounter<M = HardwareMsr> {
+    index: u8,
+    config: u64,
+    pmu: PmuInfo,
+    msr: M,
+}
+
+impl FixedEventCounter {
+    /// Creates a new FixedEventCounter for IA32_FIXED_CTRx.
+    ///
+    /// * `index`   - Index of the IA32_FIXED_CTRx to use
+    ///
+    /// # Safety
+    /// Must only be called in ring 0, see [`HardwareMsr::new`].
+    pub unsafe fn new(index: u8) -> Self {
+        Self::with_msr(PmuInfo::query(), index, HardwareMsr::new())
+    }
+}
+
+impl<M: MsrAccess> FixedEventCounter<M> {
+    /// Creates a new FixedEventCounter for IA32_FIXED_CTRx on a CPU with the
+    /// PMU `pmu` that accesses the MSRs via `msr`.
+    ///
+    /// * `pmu`     - Capabilities of the PMU
+    /// * `index`   - Index of the IA32_FIXED_CTRx to use
+    /// * `msr`     - Access to the MSRs
+    pub fn with_msr(pmu: PmuInfo, index: u8, msr: M) -> Self {
+        Self {
+            index,
+            config: 0x0_u64,
+            pmu,
+            msr,
+        }
+    }
+
+    /// Updates the configuration stored in this struct. Only the
+    /// `IA32_FIXED_CTR_CTRL_*` bits are used.
+    ///
+    /// This does not automatically write to IA32_FIXED_CTR_CTRL.
+    ///
+    /// * `config`  - Bitvector to use for later operations
+    pub fn set_configuration(&mut self, config: u64) {
+        self.config = config;
+    }
+
+    /// Stops the counter, resets it to `init_v` and starts it with the stored
+    /// configuration. The fields of the other fixed counters are preserved.
+    ///
+    /// The counter additionally has to be enabled in IA32_PERF_GLOBAL_CTRL,
+    /// see [`GlobalControl`](crate::pmc_utils::global::GlobalControl).
+    ///
+    /// Fails if the CPU does not implement the counter.
+    ///
+    /// * `init_v`: Value to reset the counter to
+    pub fn activate_counter(&self, init_v: u64) -> Result<(), PmcError> {
+        self.pmu.check_fixed_counter(self.index)?;
+        let stopped = self.msr.read(IA32_FIXED_CTR_CTRL) & !self.field(0xf);
+        self.msr.write(IA32_FIXED_CTR_CTRL, stopped);
+        self.msr.write(IA32_FIXED_CTR0 + u32::from(self.index), init_v);
+        self.msr.write(IA32_FIXED_CTR_CTRL, stopped | self.field(self.config));
+        Ok(())
+    }
+
+    /// Stops the counter. Its value is kept.
+    pub fn deactivate_counter(&self) -> Result<(), PmcError> {
+        self.pmu.check_fixed_counter(self.index)?;
+        let ctrl = self.msr.read(IA32_FIXED_CTR_CTRL);
+        self.msr.write(IA32_FIXED_CTR_CTRL, ctrl & !self.field(0xf));
+        Ok(())
+    }
+
+    /// Returns the value of the counter. Fails if the CPU does not implement
+    /// it.
+    pub fn read_pcm_val(&self) -> Result<u64, PmcError> {
+        self.pmu.check_fixed_counter(self.index)?;
+        Ok(self.msr.read(IA32_FIXED_CTR0 + u32::from(self.index)))
+    }
+
+    /// Sets the counter to `value` without touching its configuration. Fails
+    /// if the CPU does not implement it.
+    pub fn reset_pcm_val(&self, value: u64) -> Result<(), PmcError> {
+        self.pmu.check_fixed_counter(self.index)?;
+        self.msr.write(IA32_FIXED_CTR0 + u32::from(self.index), value);
+        Ok(())
+    }
+
+    /// Moves the low 4 bits of `bits` to the field of this counter.
+    fn field(&self, bits: u64) -> u64 {
+        (bits & 0xf) << (FIELD_WIDTH * u32::from(self.index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pmc_utils::msr::MockMsr;
+    use crate::pmc_utils::pmu::TEST_PMU;
+    use x86::msr::IA32_FIXED_CTR1;
+
+    #[test]
+    fn test_activate_counter() {
+        let msr = MockMsr::default();
+        // Counter 0 and 2 are already running
+        msr.set(IA32_FIXED_CTR_CTRL, 0x3f3);
+        let mut counter = FixedEventCounter::with_msr(TEST_PMU, FIXED_CORE_CYCLES, &msr);
+        counter.set_configuration(IA32_FIXED_CTR_CTRL_OS | IA32_FIXED_CTR_CTRL_PMI);
+        assert_eq!(counter.activate_counter(5), Ok(()));
+        assert_eq!(msr.take_writes(), [
+            (IA32_FIXED_CTR_CTRL, 0x303),
+            (IA32_FIXED_CTR1, 5),
+            (IA32_FIXED_CTR_CTRL, 0x393),
+        ]);
+
+        msr.set(IA32_FIXED_CTR1, 42);
+        assert_eq!(counter.read_pcm_val(), Ok(42));
+        assert_eq!(counter.deactivate_counter(), Ok(()));
+        assert_eq!(counter.reset_pcm_val(0), Ok(()));
+        assert_eq!(msr.take_writes(), [(IA32_FIXED_CTR_CTRL, 0x303), (IA32_FIXED_CTR1, 0)]);
+    }
+
+    #[test]
+    fn test_unavailable_counter() {
+        let msr = MockMsr::default();
+        let counter = FixedEventCounter::with_msr(TEST_PMU, 3, &msr);
+        assert_eq!(counter.activate_counter(0), Err(PmcError::UnavailableFixedCounter(3)));
+        assert_eq!(counter.read_pcm_val(), Err(PmcError::UnavailableFixedCounter(3)));
+        assert_eq!(counter.deactivate_counter(), Err(PmcError::UnavailableFixedCounter(3)));
+        assert_eq!(msr.take_writes(), []);
+    }
+}
@@ -0,0 +1,228 @@
+//! Global control of all PMCs.
+//!
+//! A PMC only counts if its enable bit in IA32_PERF_GLOBAL_CTRL is set, in
+//! addition to its own configuration:
+//!
+//! ```text
+//! | reserved | FIXED_CTRx | reserved | PMCx |
+//!  63        32+x        32          x     0
+//! ```
+//!
+//! A single write to the register starts or stops all counters at once.
+//!
+//! IA32_PERF_GLOBAL_STATUS uses the same layout to flag which counters
+//! overflowed. The flags stay set until they are acknowledged via
+//! IA32_PERF_GLOBAL_OVF_CTRL, which is called IA32_PERF_GLOBAL_STATUS_RESET
+//! since version 4 of the PMU.
+
+use x86::msr::{IA32_PERF_GLOBAL_CTRL, IA32_PERF_GLOBAL_OVF_CTRL, IA32_PERF_GLOBAL_STAUS};
+
+use crate::pmc_utils::msr::{HardwareMsr, MsrAccess};
+use crate::pmc_utils::pmu::PmuInfo;
+
+/// Bit of the first fixed counter in IA32_PERF_GLOBAL_CTRL.
+pub const GLOBAL_CTRL_FIXED_SHIFT: u32 = 32;
+
+/// Returns the bit of the general purpose counter `index`.
+pub const fn gp_counter_bit(index: u8) -> u64 {
+    0x1 << index
+}
+
+/// Returns the bit of the fixed counter `index`.
+pub const fn fixed_counter_bit(index: u8) -> u64 {
+    0x1 << (GLOBAL_CTRL_FIXED_SHIFT + index as u32)
+}
+
+/// Counters that overflowed, in the layout of IA32_PERF_GLOBAL_STATUS.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct OverflowStatus(pub u64);
+
+impl OverflowStatus {
+    /// Returns `true` if the general purpose counter `index` overflowed.
+    pub fn counter(&self, index: u8) -> bool {
+        index < 32 && 0 != self.0 & gp_counter_bit(index)
+    }
+
+    /// Returns `true` if the fixed counter `index` overflowed.
+    pub fn fixed_counter(&self, index: u8) -> bool {
+        index < 32 && 0 != self.0 & fixed_counter_bit(index)
+    }
+
+    /// Returns `true` if no counter overflowed.
+    pub fn is_empty(&self) -> bool {
+        0 == self.0
+    }
+}
+
+/// IA32_PERF_GLOBAL_CTRL of a CPU with the PMU `pmu`. Bits of counters the
+/// CPU does not implement are never written.
+#[derive(Copy, Debug, Clone)]
+pub struct GlobalControl<M = HardwareMsr> {
+    pmu: PmuInfo,
+    msr: M,
+}
+
+impl GlobalControl {
+    /// Creates the global control of the current CPU.
+    ///
+    /// # Safety
+    /// Must only be called in ring 0, see [`HardwareMsr::new`].
+    pub unsafe fn new() -> Self {
+        Self::with_msr(PmuInfo::query(), HardwareMsr::new())
+    }
+}
+
+impl<M: MsrAccess> GlobalControl<M> {
+    /// Creates the global control of a CPU with the PMU `pmu` that accesses
+    /// the MSRs via `msr`.
+    pub fn with_msr(pmu: PmuInfo, msr: M) -> Self {
+        Self { pmu, msr }
+    }
+
+    /// Returns the bits of all counters the CPU implements.
+    pub fn counters_mask(&self) -> u64 {
+        let gp = (0..self.pmu.gp_counters.min(32)).fold(0, |mask, x| mask | gp_counter_bit(x));
+        let fixed = (0..self.pmu.fixed_counters.min(32)).fold(0, |mask, x| mask | fixed_counter_bit(x));
+        gp | fixed
+    }
+
+    /// Returns the enable bits of all counters. On a CPU without a PMU, the
+    /// register does not exist and 0 is returned.
+    pub fn read(&self) -> u64 {
+        if self.pmu.version < 2 {
+            return 0;
+        }
+        self.msr.read(IA32_PERF_GLOBAL_CTRL)
+    }
+
+    /// Sets the enable bits of all counters to `value`.
+    pub fn write(&self, value: u64) {
+        if self.pmu.version < 2 {
+            return;
+        }
+        self.msr.write(IA32_PERF_GLOBAL_CTRL, value & self.counters_mask());
+    }
+
+    /// Starts the counters in `mask` in addition to the running ones.
+    pub fn enable(&self, mask: u64) {
+        self.write(self.read() | mask);
+    }
+
+    /// Stops the counters in `mask`.
+    pub fn disable(&self, mask: u64) {
+        self.write(self.read() & !mask);
+    }
+
+    /// Stops all counters and returns their previous enable bits for
+    /// [`GlobalControl::write`].
+    pub fn disable_all(&self) -> u64 {
+        let saved = self.read();
+        self.write(0);
+        saved
+    }
+
+    /// Returns the counters that overflowed and were not acknowledged yet.
+    /// Flags other than those of the counters are dropped.
+    pub fn overflow_status(&self) -> OverflowStatus {
+        if self.pmu.version < 2 {
+            return OverflowStatus::default();
+        }
+        OverflowStatus(self.msr.read(IA32_PERF_GLOBAL_STAUS) & self.counters_mask())
+    }
+
+    /// Clears the overflow flags of the counters in `status`. The counters
+    /// keep their values, see [`ArchitecturalEventCounter::reset_pcm_val`]
+    /// to re-arm them.
+    ///
+    /// [`ArchitecturalEventCounter::reset_pcm_val`]: crate::pmc_utils::architectural::ArchitecturalEventCounter::reset_pcm_val
+    pub fn acknowledge(&self, status: OverflowStatus) {
+        let bits = status.0 & self.counters_mask();
+        if self.pmu.version < 2 || 0 == bits {
+            return;
+        }
+        self.msr.write(IA32_PERF_GLOBAL_OVF_CTRL, bits);
+    }
+
+    /// Runs `f` with only the counters in `mask` enabled and restores the
+    /// enable bits afterwards.
+    pub fn count_only<R>(&self, mask: u64, f: impl FnOnce() -> R) -> R {
+        let saved = self.disable_all();
+        self.write(mask);
+        let result = f();
+        self.write(0);
+        self.write(saved);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pmc_utils::msr::MockMsr;
+    use crate::pmc_utils::pmu::TEST_PMU;
+
+    #[test]
+    fn test_enable() {
+        let msr = MockMsr::default();
+        let global = GlobalControl::with_msr(TEST_PMU, &msr);
+        assert_eq!(global.counters_mask(), 0x7_0000_000f);
+
+        global.enable(gp_counter_bit(1) | fixed_counter_bit(2));
+        global.enable(gp_counter_bit(3));
+        global.disable(gp_counter_bit(1));
+        // Bits of missing counters are dropped
+        global.enable(gp_counter_bit(4) | fixed_counter_bit(3));
+        assert_eq!(msr.take_writes(), [
+            (IA32_PERF_GLOBAL_CTRL, 0x4_0000_0002),
+            (IA32_PERF_GLOBAL_CTRL, 0x4_0000_000a),
+            (IA32_PERF_GLOBAL_CTRL, 0x4_0000_0008),
+            (IA32_PERF_GLOBAL_CTRL, 0x4_0000_0008),
+        ]);
+    }
+
+    #[test]
+    fn test_count_only() {
+        let msr = MockMsr::default();
+        msr.set(IA32_PERF_GLOBAL_CTRL, 0x7_0000_000f);
+        let global = GlobalControl::with_msr(TEST_PMU, &msr);
+        let result = global.count_only(gp_counter_bit(0), || 42);
+        assert_eq!(result, 42);
+        assert_eq!(msr.take_writes(), [
+            (IA32_PERF_GLOBAL_CTRL, 0),
+            (IA32_PERF_GLOBAL_CTRL, 0x1),
+            (IA32_PERF_GLOBAL_CTRL, 0),
+            (IA32_PERF_GLOBAL_CTRL, 0x7_0000_000f),
+        ]);
+    }
+
+    #[test]
+    fn test_overflow_status() {
+        let msr = MockMsr::default();
+        // CondChgd and a counter the CPU lacks are set as well
+        msr.set(IA32_PERF_GLOBAL_STAUS, 0x8000_0002_0000_0014);
+        let global = GlobalControl::with_msr(TEST_PMU, &msr);
+        let status = global.overflow_status();
+        assert_eq!(status, OverflowStatus(0x2_0000_0004));
+        assert!(status.counter(2));
+        assert!(false == status.counter(4));
+        assert!(status.fixed_counter(1));
+        assert!(false == status.fixed_counter(40));
+
+        global.acknowledge(status);
+        global.acknowledge(OverflowStatus(0x10));
+        assert_eq!(msr.take_writes(), [(IA32_PERF_GLOBAL_OVF_CTRL, 0x2_0000_0004)]);
+    }
+
+    #[test]
+    fn test_no_global_ctrl() {
+        // Version 1 PMUs do not have IA32_PERF_GLOBAL_CTRL
+        let msr = MockMsr::default();
+        let global = GlobalControl::with_msr(PmuInfo { version: 1, ..TEST_PMU }, &msr);
+        assert_eq!(global.disable_all(), 0);
+        global.enable(0x1);
+        msr.set(IA32_PERF_GLOBAL_STAUS, 0x1);
+        assert!(global.overflow_status().is_empty());
+        global.acknowledge(OverflowStatus(0x1));
+        assert_eq!(msr.take_writes(), []);
+    }
+}
@@ -0,0 +1,66 @@
+//! Core types of hybrid Intel CPUs.
+//!
+//! Alder Lake and Raptor Lake combine P-cores (Intel Core) and E-cores (Intel
+//! Atom). Both run the same code, but their PMUs differ: the events have other
+//! names and encodings and the P-cores have more counters. CPUID leaf 0x1A
+//! reports the type of the core it executes on.
+
+use core::arch::x86_64::{__cpuid, __cpuid_count};
+
+use crate::pmc_utils::vendor;
+
+/// CPUID leaf with the structured extended feature flags.
+const CPUID_LEAF_EXT_FEATURES: u32 = 0x7;
+/// Hybrid flag in EDX of CPUID leaf 0x7.
+const HYBRID_FLAG: u32 = 0x1 << 15;
+/// CPUID leaf that reports the core type.
+const CPUID_LEAF_HYBRID: u32 = 0x1a;
+
+/// Type of a core of a hybrid CPU.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CoreType {
+    /// E-core.
+    Atom,
+    /// P-core.
+    Core,
+}
+
+impl CoreType {
+    /// Decodes EAX of CPUID leaf 0x1A. `None` if the type is unknown.
+    pub fn from_cpuid(eax: u32) -> Option<Self> {
+        match eax >> 24 {
+            0x20 => Some(Self::Atom),
+            0x40 => Some(Self::Core),
+            _ => None,
+        }
+    }
+
+    /// Returns the type of the current core. `None` on CPUs that are not
+    /// hybrid.
+    pub fn detect() -> Option<Self> {
+        if vendor::get_cpu_vendor() != vendor::CpuVendor::Intel {
+            return None;
+        }
+        let max_leaf = unsafe { __cpuid(0) }.eax;
+        if max_leaf < CPUID_LEAF_HYBRID {
+            return None;
+        }
+        if 0 == unsafe { __cpuid_count(CPUID_LEAF_EXT_FEATURES, 0) }.edx & HYBRID_FLAG {
+            return None;
+        }
+        Self::from_cpuid(unsafe { __cpuid_count(CPUID_LEAF_HYBRID, 0) }.eax)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_cpuid() {
+        // E-core and P-core of a Raptor Lake CPU
+        assert_eq!(CoreType::from_cpuid(0x20000002), Some(CoreType::Atom));
+        assert_eq!(CoreType::from_cpuid(0x40000001), Some(CoreType::Core));
+        assert_eq!(CoreType::from_cpuid(0), None);
+    }
+}
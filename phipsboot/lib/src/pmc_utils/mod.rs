@@ -1,3 +1,13 @@
+pub mod amd;
 pub mod architectural;
+pub mod counter;
+pub mod events;
+pub mod fixed;
+pub mod global;
+pub mod hybrid;
 pub mod intel;
+pub mod msr;
+pub mod perfmon;
+pub mod pmu;
+pub mod uarch;
 pub mod vendor;
\ No newline at end of file
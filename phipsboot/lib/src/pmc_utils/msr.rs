@@ -0,0 +1,105 @@
+//! Access to model-specific registers.
+//!
+//! The PMC types in [`pmc_utils`](crate::pmc_utils) go through [`MsrAccess`]
+//! instead of calling `rdmsr`/`wrmsr` themselves. The firmware uses
+//! [`HardwareMsr`], tests use [`MockMsr`] to check the exact MSR writes.
+//! Creating a [`HardwareMsr`] is unsafe, as it gives access to all MSRs.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use x86::msr::{rdmsr, wrmsr};
+
+/// Reads and writes MSRs.
+pub trait MsrAccess {
+    /// Returns the value of the MSR with the address `msr`.
+    fn read(&self, msr: u32) -> u64;
+
+    /// Writes `value` to the MSR with the address `msr`.
+    fn write(&self, msr: u32, value: u64);
+}
+
+impl<M: MsrAccess> MsrAccess for &M {
+    fn read(&self, msr: u32) -> u64 {
+        (*self).read(msr)
+    }
+
+    fn write(&self, msr: u32, value: u64) {
+        (*self).write(msr, value)
+    }
+}
+
+/// MSRs of the current core.
+#[derive(Copy, Clone, Debug)]
+pub struct HardwareMsr(());
+
+impl HardwareMsr {
+    /// # Safety
+    /// Must only be called in ring 0. The caller must only access MSRs the CPU
+    /// implements and must not write values that break the memory safety,
+    /// e.g., to the MSRs of the system call entry or the page attributes.
+    pub unsafe fn new() -> Self {
+        Self(())
+    }
+}
+
+impl MsrAccess for HardwareMsr {
+    fn read(&self, msr: u32) -> u64 {
+        unsafe { rdmsr(msr) }
+    }
+
+    fn write(&self, msr: u32, value: u64) {
+        unsafe { wrmsr(msr, value) }
+    }
+}
+
+/// In-memory MSRs. Reads return the last value written or set, 0 for MSRs
+/// that were never written. All writes are recorded in order.
+#[derive(Debug, Default)]
+pub struct MockMsr {
+    values: RefCell<BTreeMap<u32, u64>>,
+    writes: RefCell<Vec<(u32, u64)>>,
+}
+
+impl MockMsr {
+    /// Sets the value of `msr` without recording a write, e.g., to simulate a
+    /// counter that moved.
+    pub fn set(&self, msr: u32, value: u64) {
+        self.values.borrow_mut().insert(msr, value);
+    }
+
+    /// Returns all writes since the creation or the last call, as pairs of
+    /// address and value.
+    pub fn take_writes(&self) -> Vec<(u32, u64)> {
+        self.writes.take()
+    }
+}
+
+impl MsrAccess for MockMsr {
+    fn read(&self, msr: u32) -> u64 {
+        self.values.borrow().get(&msr).copied().unwrap_or(0)
+    }
+
+    fn write(&self, msr: u32, value: u64) {
+        self.values.borrow_mut().insert(msr, value);
+        self.writes.borrow_mut().push((msr, value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock() {
+        let msr = MockMsr::default();
+        assert_eq!(msr.read(0x10), 0);
+        msr.set(0x10, 5);
+        (&msr).write(0x11, 6);
+        msr.write(0x11, 7);
+        assert_eq!(msr.read(0x10), 5);
+        assert_eq!(msr.read(0x11), 7);
+        assert_eq!(msr.take_writes(), [(0x11, 6), (0x11, 7)]);
+        assert_eq!(msr.take_writes(), []);
+    }
+}
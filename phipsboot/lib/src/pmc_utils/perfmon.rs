@@ -0,0 +1,80 @@
+//! Event definitions generated from the Intel perfmon JSON files.
+//!
+//! `build.rs` reads the files in `lib/perfmon/`, which are taken from
+//! https://github.com/intel/perfmon, and generates a module per
+//! microarchitecture with a [`PerfmonEvent`] constant per event, named after
+//! the event with `.` replaced by `_`, e.g., [`icelake::L1D_REPLACEMENT`].
+//! `EVENTS` lists all of them. The brief description of an event is only
+//! kept as documentation of its constant, so that it does not take up space
+//! in the TEE.
+
+use crate::pmc_utils::architectural::{IA32_PERFEVTSEL_E, IA32_PERFEVTSEL_INV};
+
+/// Event as documented by Intel.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct PerfmonEvent {
+    /// Name of the event, e.g., `MEM_LOAD_RETIRED.L2_MISS`.
+    pub name: &'static str,
+    pub event_select: u8,
+    pub umask: u8,
+    /// Counter mask, the event is only counted in cycles in which it occurs at
+    /// least this often. 0 counts every occurrence.
+    pub cmask: u8,
+    /// Inverts the counter mask.
+    pub invert: bool,
+    /// Counts only rising edges of the event.
+    pub edge: bool,
+    /// Value of MSR_OFFCORE_RSP_x for offcore-response events. `event_select`
+    /// selects MSR_OFFCORE_RSP_0 for them.
+    pub offcore_rsp: Option<u64>,
+}
+
+impl PerfmonEvent {
+    /// Returns the bits of IA32_PERFEVTSELx that select the event. The
+    /// privilege level, interrupt and enable bits are not set.
+    pub const fn encoding(&self) -> u64 {
+        let mut encoding = self.event_select as u64 | (self.umask as u64) << 8 | (self.cmask as u64) << 24;
+        if self.edge {
+            encoding |= IA32_PERFEVTSEL_E;
+        }
+        if self.invert {
+            encoding |= IA32_PERFEVTSEL_INV;
+        }
+        encoding
+    }
+}
+
+/// Returns the event called `name` from `events`.
+pub fn find(events: &'static [PerfmonEvent], name: &str) -> Option<&'static PerfmonEvent> {
+    events.iter().find(|event| event.name == name)
+}
+
+include!(concat!(env!("OUT_DIR"), "/perfmon.rs"));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generated() {
+        assert_eq!(icelake::MEM_LOAD_RETIRED_L2_MISS.encoding(), 0x10d1);
+        assert_eq!(raptor_cove::L2_LINES_IN_ALL.encoding(), 0x1f25);
+        assert_eq!(find(skylake::EVENTS, "L1D.REPLACEMENT"), Some(&skylake::L1D_REPLACEMENT));
+        assert_eq!(find(skylake::EVENTS, "TOPDOWN.SLOTS_P"), None);
+        // Events of the fixed counters have no GP encoding
+        assert_eq!(find(skylake::EVENTS, "INST_RETIRED.ANY"), None);
+        assert_eq!(find(icelake::EVENTS, "TOPDOWN.SLOTS"), None);
+
+        // Offcore-response events carry the value of MSR_OFFCORE_RSP_x
+        let event = skylake::OFFCORE_RESPONSE_DEMAND_DATA_RD_ANY_RESPONSE;
+        assert_eq!((event.event_select, event.offcore_rsp), (0xb7, Some(0x10001)));
+        assert_eq!(raptor_cove::OCR_DEMAND_RFO_ANY_RESPONSE.event_select, 0x2a);
+        assert_eq!(icelake::L1D_REPLACEMENT.offcore_rsp, None);
+    }
+
+    #[test]
+    fn test_encoding() {
+        let event = PerfmonEvent { cmask: 1, invert: true, edge: true, ..icelake::L1D_REPLACEMENT };
+        assert_eq!(event.encoding(), 0x0151 | 1 << 24 | IA32_PERFEVTSEL_INV | IA32_PERFEVTSEL_E);
+    }
+}
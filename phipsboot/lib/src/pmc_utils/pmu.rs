@@ -0,0 +1,208 @@
+//! Capabilities of the architectural PMU as reported by CPUID leaf 0xA.
+
+use core::arch::x86_64::{CpuidResult, __cpuid, __cpuid_count};
+
+use crate::pmc_utils::events::Event;
+use crate::pmc_utils::hybrid::CoreType;
+use crate::pmc_utils::uarch::Microarchitecture;
+use crate::pmc_utils::vendor;
+
+/// CPUID leaf that describes the architectural PMU.
+const CPUID_LEAF_PMU: u32 = 0xa;
+
+/// Error of an operation on the PMCs.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PmcError {
+    /// The CPU does not implement the general purpose counter with this index.
+    UnavailableCounter(u8),
+    /// The CPU does not implement the fixed counter with this index.
+    UnavailableFixedCounter(u8),
+    /// There is no MSR_OFFCORE_RSP register with this index.
+    UnavailableOffcoreRegister(u8),
+    /// There is no event table for the microarchitecture of the CPU.
+    UnknownMicroarchitecture,
+    /// The microarchitecture of the CPU cannot count this event.
+    UnsupportedEvent(Event),
+}
+
+/// Architectural events whose availability CPUID leaf 0xA reports. The
+/// discriminant is the bit in [`PmuInfo::events`].
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ArchitecturalEvent {
+    CoreCycles = 0,
+    InstructionsRetired = 1,
+    ReferenceCycles = 2,
+    LlcReferences = 3,
+    LlcMisses = 4,
+    BranchInstructionsRetired = 5,
+    BranchMissesRetired = 6,
+    TopdownSlots = 7,
+}
+
+/// Capabilities of the architectural PMU. A CPU without one has the default
+/// value, i.e., no counters at all.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct PmuInfo {
+    /// Version of the architectural PMU.
+    pub version: u8,
+    /// Number of general purpose counters.
+    pub gp_counters: u8,
+    /// Width of the general purpose counters in bits.
+    pub gp_width: u8,
+    /// Number of fixed counters.
+    pub fixed_counters: u8,
+    /// Width of the fixed counters in bits.
+    pub fixed_width: u8,
+    /// Bit x is set if the [`ArchitecturalEvent`] x is available.
+    pub events: u32,
+}
+
+impl PmuInfo {
+    /// Decodes the registers of CPUID leaf 0xA.
+    pub fn from_cpuid(leaf: CpuidResult) -> Self {
+        let version = leaf.eax as u8;
+        // EBX flags unavailable events, but only as many as EAX[31:24] says
+        let event_bits = (leaf.eax >> 24).min(32);
+        let known = if 32 == event_bits { u32::MAX } else { (1 << event_bits) - 1 };
+        // Fixed counters are only enumerated since version 2
+        let (fixed_counters, fixed_width) = if version > 1 {
+            ((leaf.edx & 0x1f) as u8, (leaf.edx >> 5) as u8)
+        } else {
+            (0, 0)
+        };
+        Self {
+            version,
+            gp_counters: (leaf.eax >> 8) as u8,
+            gp_width: (leaf.eax >> 16) as u8,
+            fixed_counters,
+            fixed_width,
+            events: !leaf.ebx & known,
+        }
+    }
+
+    /// Queries the PMU of the current core. Returns the default value on CPUs
+    /// that are not from Intel or have no architectural PMU.
+    pub fn query() -> Self {
+        if vendor::get_cpu_vendor() != vendor::CpuVendor::Intel {
+            return Self::default();
+        }
+        let max_leaf = unsafe { __cpuid(0) }.eax;
+        if max_leaf < CPUID_LEAF_PMU {
+            return Self::default();
+        }
+        let pmu = Self::from_cpuid(unsafe { __cpuid_count(CPUID_LEAF_PMU, 0) });
+        match CoreType::detect() {
+            Some(core_type) => pmu.for_core(unsafe { __cpuid(1) }.eax, core_type),
+            None => pmu,
+        }
+    }
+
+    /// Returns the PMU of a core of type `core_type` on the hybrid Intel CPU
+    /// with the CPUID `signature`. On Alder Lake and Raptor Lake, CPUID leaf
+    /// 0xA only enumerates the counters that all cores have. The P-cores have
+    /// two more GP counters and a fourth fixed counter. The PMU of other CPUs
+    /// is returned as reported.
+    pub fn for_core(self, signature: u32, core_type: CoreType) -> Self {
+        match (Microarchitecture::from_signature(vendor::CpuVendor::Intel, signature), core_type) {
+            (Some(Microarchitecture::RaptorCove), CoreType::Core) => Self {
+                gp_counters: self.gp_counters + 2,
+                fixed_counters: self.fixed_counters + 1,
+                ..self
+            },
+            _ => self,
+        }
+    }
+
+    /// Returns an error if the general purpose counter `index` does not exist.
+    pub fn check_counter(&self, index: u8) -> Result<(), PmcError> {
+        if index >= self.gp_counters {
+            return Err(PmcError::UnavailableCounter(index));
+        }
+        Ok(())
+    }
+
+    /// Returns an error if the fixed counter `index` does not exist.
+    pub fn check_fixed_counter(&self, index: u8) -> Result<(), PmcError> {
+        if index >= self.fixed_counters {
+            return Err(PmcError::UnavailableFixedCounter(index));
+        }
+        Ok(())
+    }
+
+    /// Returns the mask of the bits a general purpose counter implements.
+    pub fn counter_mask(&self) -> u64 {
+        match self.gp_width {
+            0 => 0,
+            width if width >= 64 => u64::MAX,
+            width => (1 << width) - 1,
+        }
+    }
+
+    /// Returns `true` if the CPU can count `event`.
+    pub fn is_available(&self, event: ArchitecturalEvent) -> bool {
+        0 != self.events & 1 << event as u8
+    }
+}
+
+/// PMU of a Coffee Lake CPU, for the tests of the PMC types.
+#[cfg(test)]
+pub(crate) const TEST_PMU: PmuInfo = PmuInfo {
+    version: 4,
+    gp_counters: 4,
+    gp_width: 48,
+    fixed_counters: 3,
+    fixed_width: 48,
+    events: 0x7f,
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// CPUID leaf 0xA of a Coffee Lake CPU.
+    const COFFEE_LAKE: CpuidResult = CpuidResult { eax: 0x07300404, ebx: 0x00, ecx: 0x00, edx: 0x00000603 };
+
+    #[test]
+    fn test_from_cpuid() {
+        let pmu = PmuInfo::from_cpuid(COFFEE_LAKE);
+        assert_eq!(pmu, TEST_PMU);
+        assert_eq!(pmu.counter_mask(), (1 << 48) - 1);
+        assert!(pmu.is_available(ArchitecturalEvent::LlcMisses));
+        assert!(false == pmu.is_available(ArchitecturalEvent::TopdownSlots));
+
+        // Unavailable events are flagged in EBX
+        let pmu = PmuInfo::from_cpuid(CpuidResult { ebx: 0x10, ..COFFEE_LAKE });
+        assert!(false == pmu.is_available(ArchitecturalEvent::LlcMisses));
+
+        // Version 1 does not enumerate fixed counters
+        let pmu = PmuInfo::from_cpuid(CpuidResult { eax: 0x07300201, ..COFFEE_LAKE });
+        assert_eq!((pmu.gp_counters, pmu.fixed_counters), (2, 0));
+    }
+
+    #[test]
+    fn test_check_counter() {
+        let pmu = PmuInfo::from_cpuid(COFFEE_LAKE);
+        assert_eq!(pmu.check_counter(3), Ok(()));
+        assert_eq!(pmu.check_counter(4), Err(PmcError::UnavailableCounter(4)));
+        assert_eq!(pmu.check_fixed_counter(2), Ok(()));
+        assert_eq!(pmu.check_fixed_counter(3), Err(PmcError::UnavailableFixedCounter(3)));
+        assert_eq!(PmuInfo::default().check_counter(0), Err(PmcError::UnavailableCounter(0)));
+        assert_eq!(PmuInfo::default().counter_mask(), 0);
+    }
+
+    #[test]
+    fn test_for_core() {
+        // CPUID leaf 0xA of a Raptor Lake CPU
+        let pmu = PmuInfo::from_cpuid(CpuidResult { eax: 0x07300605, ebx: 0x00, ecx: 0x00, edx: 0x00008603 });
+        let raptor_lake = 0x000b0671;
+        assert_eq!(pmu.for_core(raptor_lake, CoreType::Atom), pmu);
+        let p_core = pmu.for_core(raptor_lake, CoreType::Core);
+        assert_eq!((p_core.gp_counters, p_core.fixed_counters), (8, 4));
+        assert_eq!(p_core.check_counter(7), Ok(()));
+
+        // Other hybrid CPUs enumerate the counters as they are
+        let meteor_lake = 0x000a06a4;
+        assert_eq!(pmu.for_core(meteor_lake, CoreType::Core), pmu);
+    }
+}
@@ -0,0 +1,135 @@
+//! Detection of the microarchitecture of the CPU.
+//!
+//! The encodings of most non-architectural events differ between
+//! microarchitectures, see [`crate::pmc_utils::events`]. The microarchitecture
+//! is derived from the vendor and the family and model in CPUID leaf 1. On
+//! hybrid CPUs, it also depends on the type of the current core.
+
+use core::arch::x86_64::__cpuid;
+
+use crate::pmc_utils::hybrid::CoreType;
+use crate::pmc_utils::vendor::{self, CpuVendor};
+
+/// Microarchitectures with an event table. CPUs that share the encodings of
+/// their events are grouped together.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Microarchitecture {
+    /// Skylake and its successors up to Comet Lake.
+    Skylake,
+    /// Sunny Cove and Willow Cove cores of Ice Lake and Tiger Lake.
+    IceLake,
+    /// Golden Cove and Raptor Cove P-cores of Alder Lake and Raptor Lake.
+    RaptorCove,
+    /// Gracemont E-cores of Alder Lake and Raptor Lake.
+    Gracemont,
+    /// AMD Zen 2.
+    Zen2,
+    /// AMD Zen 3.
+    Zen3,
+    /// AMD Zen 4.
+    Zen4,
+}
+
+/// Returns the family and the model encoded in `signature`, i.e., EAX of
+/// CPUID leaf 1. The extended fields are already added.
+pub fn family_model(signature: u32) -> (u32, u32) {
+    let mut family = (signature >> 8) & 0xf;
+    let mut model = (signature >> 4) & 0xf;
+    if 0x6 == family || 0xf == family {
+        model |= ((signature >> 16) & 0xf) << 4;
+    }
+    if 0xf == family {
+        family += (signature >> 20) & 0xff;
+    }
+    (family, model)
+}
+
+impl Microarchitecture {
+    /// Returns the microarchitecture of the CPU from `vendor` with the CPUID
+    /// `signature`. `None` if it has no event table.
+    pub fn from_signature(vendor: CpuVendor, signature: u32) -> Option<Self> {
+        match (vendor, family_model(signature)) {
+            (CpuVendor::Intel, (0x6, 0x4e | 0x5e | 0x55 | 0x8e | 0x9e | 0xa5 | 0xa6)) => Some(Self::Skylake),
+            (CpuVendor::Intel, (0x6, 0x6a | 0x6c | 0x7d | 0x7e | 0x8c | 0x8d | 0xa7)) => Some(Self::IceLake),
+            (CpuVendor::Intel, (0x6, 0x97 | 0x9a | 0xb7 | 0xba | 0xbf)) => Some(Self::RaptorCove),
+            // Alder Lake-N only has E-cores
+            (CpuVendor::Intel, (0x6, 0xbe)) => Some(Self::Gracemont),
+            // Zen and Zen+ share family 0x17 with Zen 2
+            (CpuVendor::Amd, (0x17, 0x30..)) => Some(Self::Zen2),
+            (CpuVendor::Amd, (0x19, 0x10..=0x1f | 0x60..=0x7f | 0xa0..=0xaf)) => Some(Self::Zen4),
+            (CpuVendor::Amd, (0x19, _)) => Some(Self::Zen3),
+            _ => None,
+        }
+    }
+
+    /// Returns the microarchitecture of a core of type `core_type`. Hybrid
+    /// CPUs report the signature of their P-cores on all cores.
+    pub fn for_core(self, core_type: Option<CoreType>) -> Self {
+        match (self, core_type) {
+            (Self::RaptorCove, Some(CoreType::Atom)) => Self::Gracemont,
+            _ => self,
+        }
+    }
+
+    /// Returns the microarchitecture of the current core. `None` on CPUs that
+    /// have no event table.
+    pub fn detect() -> Option<Self> {
+        Self::from_signature(vendor::get_cpu_vendor(), unsafe { __cpuid(1) }.eax)
+            .map(|uarch| uarch.for_core(CoreType::detect()))
+    }
+
+    /// Returns the vendor of CPUs with this microarchitecture.
+    pub fn vendor(self) -> CpuVendor {
+        match self {
+            Self::Skylake | Self::IceLake | Self::RaptorCove | Self::Gracemont => CpuVendor::Intel,
+            Self::Zen2 | Self::Zen3 | Self::Zen4 => CpuVendor::Amd,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_family_model() {
+        assert_eq!(family_model(0x000906ea), (0x6, 0x9e));
+        // Zen 2 uses the extended family
+        assert_eq!(family_model(0x00830f10), (0x17, 0x31));
+    }
+
+    #[test]
+    fn test_from_signature() {
+        let intel = |signature| Microarchitecture::from_signature(CpuVendor::Intel, signature);
+        let amd = |signature| Microarchitecture::from_signature(CpuVendor::Amd, signature);
+        // Coffee Lake
+        assert_eq!(intel(0x000906ea), Some(Microarchitecture::Skylake));
+        // Ice Lake client and Tiger Lake
+        assert_eq!(intel(0x000706e5), Some(Microarchitecture::IceLake));
+        assert_eq!(intel(0x000806c1), Some(Microarchitecture::IceLake));
+        // Raptor Lake
+        assert_eq!(intel(0x000b0671), Some(Microarchitecture::RaptorCove));
+        // Alder Lake-N
+        assert_eq!(intel(0x000b06e0), Some(Microarchitecture::Gracemont));
+        // Haswell has no event table
+        assert_eq!(intel(0x000306c3), None);
+        assert_eq!(intel(0x00830f10), None);
+
+        // Rome, Vermeer and Raphael
+        assert_eq!(amd(0x00830f10), Some(Microarchitecture::Zen2));
+        assert_eq!(amd(0x00a20f10), Some(Microarchitecture::Zen3));
+        assert_eq!(amd(0x00a60f12), Some(Microarchitecture::Zen4));
+        // Zen+ has no event table
+        assert_eq!(amd(0x00800f82), None);
+        assert_eq!(Microarchitecture::Zen3.vendor(), CpuVendor::Amd);
+    }
+
+    #[test]
+    fn test_for_core() {
+        let uarch = Microarchitecture::RaptorCove;
+        assert_eq!(uarch.for_core(Some(CoreType::Atom)), Microarchitecture::Gracemont);
+        assert_eq!(uarch.for_core(Some(CoreType::Core)), uarch);
+        assert_eq!(uarch.for_core(None), uarch);
+        assert_eq!(Microarchitecture::Skylake.for_core(Some(CoreType::Atom)), Microarchitecture::Skylake);
+    }
+}
@@ -0,0 +1,105 @@
+//! Deterministic random bit generator for the TEE.
+//!
+//! [`Drbg`] is a ChaCha20 generator with fast key erasure: every request
+//! replaces the key with the first 32 bytes of keystream before the output is
+//! produced, so a later compromise does not reveal earlier output. It is seeded
+//! from [`Platform::fill_entropy`] on first use and reseeded after
+//! [`RESEED_INTERVAL`] bytes.
+
+use chacha20::cipher::{KeyIvInit, StreamCipher};
+use chacha20::ChaCha20;
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+use crate::entropy::EntropyError;
+use crate::platform::Platform;
+
+/// Number of bytes the [`Drbg`] produces before it mixes in fresh entropy.
+pub const RESEED_INTERVAL: u64 = 1 << 20;
+/// Length of the key and of a seed.
+const SEED_LEN: usize = 32;
+
+/// ChaCha20 DRBG. The key is zeroed when dropped.
+#[derive(Default)]
+pub struct Drbg {
+    key: Zeroizing<[u8; SEED_LEN]>,
+    seeded: bool,
+    /// Bytes produced since the last reseed.
+    generated: u64,
+}
+
+impl Drbg {
+    /// Mixes `seed` into the key.
+    pub fn reseed(&mut self, seed: &[u8; SEED_LEN]) {
+        let mut hash = Sha256::new();
+        hash.update(&*self.key);
+        hash.update(seed);
+        self.key.copy_from_slice(&hash.finalize());
+        self.seeded = true;
+        self.generated = 0;
+    }
+
+    /// Fills `buf` with random bytes. Reseeds from `platform` first if
+    /// necessary.
+    pub fn fill(&mut self, platform: &dyn Platform, buf: &mut [u8]) -> Result<(), EntropyError> {
+        if false == self.seeded || self.generated >= RESEED_INTERVAL {
+            let mut seed = Zeroizing::new([0; SEED_LEN]);
+            platform.fill_entropy(&mut *seed)?;
+            self.reseed(&seed);
+        }
+        self.generate(buf);
+        Ok(())
+    }
+
+    fn generate(&mut self, buf: &mut [u8]) {
+        // A fresh key for every request, so the nonce can stay fixed
+        let mut cipher = ChaCha20::new((&*self.key).into(), &[0; 12].into());
+        let mut next_key = Zeroizing::new([0; SEED_LEN]);
+        cipher.apply_keystream(&mut *next_key);
+        buf.fill(0);
+        cipher.apply_keystream(buf);
+        self.key = next_key;
+        self.generated = self.generated.saturating_add(buf.len() as u64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::SimPlatform;
+
+    #[test]
+    fn test_fill() {
+        let platform = SimPlatform::default();
+        let mut drbg = Drbg::default();
+        let mut a = [0; 100];
+        let mut b = [0; 100];
+        drbg.fill(&platform, &mut a).unwrap();
+        drbg.fill(&platform, &mut b).unwrap();
+        assert_ne!(a, [0; 100]);
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn test_reseed() {
+        let mut a = Drbg::default();
+        let mut b = Drbg::default();
+        a.reseed(&[1; SEED_LEN]);
+        b.reseed(&[1; SEED_LEN]);
+        let (mut out_a, mut out_b) = ([0; 16], [0; 16]);
+        a.generate(&mut out_a);
+        b.generate(&mut out_b);
+        assert_eq!(out_a, out_b);
+
+        b.reseed(&[2; SEED_LEN]);
+        a.generate(&mut out_a);
+        b.generate(&mut out_b);
+        assert_ne!(out_a, out_b);
+
+        // Reseeds once the interval is exhausted
+        let platform = SimPlatform::default();
+        a.generated = RESEED_INTERVAL;
+        a.fill(&platform, &mut out_a).unwrap();
+        assert_eq!(a.generated, 16);
+    }
+}
@@ -0,0 +1,490 @@
+//! Address space and services of tasks that run in ring 3.
+//!
+//! Tasks loaded from modules are not trusted. The firmware runs them in ring 3
+//! in their own [`AddressSpace`], which is derived from the kernel page tables.
+//! All kernel mappings stay supervisor-only, so the task can only access its
+//! user window:
+//!
+//! ```text
+//! USER_CODE         code of the module, read + execute
+//! USER_TRAMPOLINE   trampoline that calls the entry point and exits, read + execute
+//! USER_HEAP         pages handed out by `Allocate`, read + write
+//! USER_STACK_TOP    stack growing down, read + write
+//! ```
+//!
+//! The task talks to the TEE via `syscall` with the number of the [`Syscall`]
+//! in `rax` and up to three arguments in `rdi`, `rsi` and `rdx`. The result is
+//! returned in `rax`; failures return [`SYSCALL_ERROR`].
+//!
+//! Tasks run with interrupts disabled. So that a task that never exits cannot
+//! hang the TEE, the firmware aborts it once it retired
+//! [`INSTRUCTION_BUDGET`] instructions in ring 3.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use crate::platform::Platform;
+use crate::state_machine::session::SessionBuffer;
+use crate::state_machine::task::TaskError;
+use crate::tasks::module::ModuleImage;
+
+pub const PAGE_SIZE: u64 = 0x1000;
+
+/// Page table entry flags.
+const PRESENT: u64 = 1 << 0;
+const WRITABLE: u64 = 1 << 1;
+const USER: u64 = 1 << 2;
+const NO_EXECUTE: u64 = 1 << 63;
+const ADDRESS_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+
+/// Start of the user window. It is covered by a single L1 table, so it spans
+/// 2 MiB.
+pub const USER_BASE: u64 = 0x0000_0080_0000_0000;
+/// Index of the user window in the L4 table. This slot must be unused by the
+/// kernel.
+pub const USER_L4_INDEX: usize = 1;
+pub const USER_CODE: u64 = USER_BASE;
+/// Maximum number of pages of module code.
+pub const MAX_CODE_PAGES: u64 = 256;
+pub const USER_TRAMPOLINE: u64 = USER_BASE + MAX_CODE_PAGES * PAGE_SIZE;
+pub const USER_HEAP: u64 = USER_TRAMPOLINE + PAGE_SIZE;
+/// Maximum number of pages a task can [`Syscall::Allocate`].
+pub const MAX_HEAP_PAGES: u64 = 4;
+/// Exclusive top of the stack, which is also the end of the user window.
+pub const USER_STACK_TOP: u64 = USER_BASE + 512 * PAGE_SIZE;
+pub const STACK_PAGES: u64 = 2;
+
+/// Instructions a task may retire in ring 3 before it is aborted.
+pub const INSTRUCTION_BUDGET: u64 = 1 << 32;
+
+/// Returned by a syscall that failed.
+pub const SYSCALL_ERROR: u64 = u64::MAX;
+
+/// Services a task can request.
+#[repr(u64)]
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub enum Syscall {
+    /// `exit(status)`: Terminates the task. A negative status means failure.
+    Exit = 0,
+    /// `read_input(buf, len, offset)`: Copies up to `len` bytes of the input,
+    /// starting at `offset`, to `buf`. Returns the number of bytes copied.
+    ReadInput = 1,
+    /// `write_output(buf, len)`: Appends `len` bytes from `buf` to the output.
+    /// Returns `len`.
+    WriteOutput = 2,
+    /// `allocate(size)`: Maps zeroed memory. Returns its address.
+    Allocate = 3,
+}
+
+impl TryFrom<u64> for Syscall {
+    type Error = u64;
+
+    fn try_from(nr: u64) -> Result<Self, Self::Error> {
+        match nr {
+            0 => Ok(Syscall::Exit),
+            1 => Ok(Syscall::ReadInput),
+            2 => Ok(Syscall::WriteOutput),
+            3 => Ok(Syscall::Allocate),
+            x => Err(x),
+        }
+    }
+}
+
+/// What happens after a syscall.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SyscallResult {
+    /// Return to the task with the given value.
+    Return(u64),
+    /// The task exited with the given status.
+    Exit(i64),
+}
+
+/// Error while setting up a sandbox.
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub enum SandboxError {
+    /// The code exceeds [`MAX_CODE_PAGES`].
+    CodeTooLarge,
+    /// Memory that should be mapped for the task has no physical address.
+    NotMapped(u64),
+    /// The kernel maps memory in the slot of the user window.
+    UserWindowInUse,
+}
+
+impl From<SandboxError> for TaskError {
+    fn from(_error: SandboxError) -> TaskError {
+        TaskError::ExecutionFailed
+    }
+}
+
+/// User page and where the kernel can access it.
+#[derive(Debug)]
+struct UserPage {
+    kernel: *mut u8,
+    writable: bool,
+}
+
+/// Page tables of a task. The kernel half is shared with the kernel, the user
+/// window is private. All memory owned by the address space is zeroed when it
+/// is dropped.
+#[derive(Debug)]
+pub struct AddressSpace {
+    l4: SessionBuffer,
+    l1: SessionBuffer,
+    /// L3 and L2 table of the user window.
+    tables: [SessionBuffer; 2],
+    /// Stack and heap pages.
+    frames: Vec<SessionBuffer>,
+    pages: BTreeMap<u64, UserPage>,
+}
+
+fn new_table() -> SessionBuffer {
+    SessionBuffer::new(PAGE_SIZE as usize, PAGE_SIZE as usize)
+}
+
+fn physical_address(platform: &dyn Platform, virt: *const u8) -> Result<u64, SandboxError> {
+    match platform.get_physical_address(virt as u64) {
+        0 => Err(SandboxError::NotMapped(virt as u64)),
+        phys => Ok(phys),
+    }
+}
+
+unsafe fn write_entry(table: &SessionBuffer, index: usize, value: u64) {
+    (table.as_ptr() as *mut u64).add(index).write_volatile(value);
+}
+
+impl AddressSpace {
+    /// Creates an address space that shares all mappings of `kernel_l4`, the
+    /// root page table of the kernel.
+    pub fn new(platform: &dyn Platform, kernel_l4: &[u64; 512]) -> Result<Self, SandboxError> {
+        if 0 != kernel_l4[USER_L4_INDEX] & PRESENT {
+            return Err(SandboxError::UserWindowInUse);
+        }
+        let space = Self {
+            l4: new_table(),
+            l1: new_table(),
+            tables: [new_table(), new_table()],
+            frames: Vec::new(),
+            pages: BTreeMap::new(),
+        };
+        let [l3, l2] = &space.tables;
+        let table_flags = PRESENT | WRITABLE | USER;
+        unsafe {
+            for (index, entry) in kernel_l4.iter().enumerate() {
+                write_entry(&space.l4, index, *entry);
+            }
+            write_entry(&space.l4, USER_L4_INDEX, physical_address(platform, l3.as_ptr())? | table_flags);
+            write_entry(l3, 0, physical_address(platform, l2.as_ptr())? | table_flags);
+            write_entry(l2, 0, physical_address(platform, space.l1.as_ptr())? | table_flags);
+        }
+        Ok(space)
+    }
+
+    /// Returns the physical address of the root page table, i.e., the value
+    /// for `CR3`.
+    pub fn root(&self, platform: &dyn Platform) -> Result<u64, SandboxError> {
+        physical_address(platform, self.l4.as_ptr())
+    }
+
+    /// Maps the kernel page `kernel` at the user page `user`.
+    fn map(&mut self, platform: &dyn Platform, user: u64, kernel: *mut u8, flags: u64) -> Result<(), SandboxError> {
+        assert!((USER_BASE..USER_STACK_TOP).contains(&user) && 0 == user % PAGE_SIZE);
+        let phys = physical_address(platform, kernel)?;
+        let index = ((user - USER_BASE) / PAGE_SIZE) as usize;
+        unsafe { write_entry(&self.l1, index, (phys & ADDRESS_MASK) | flags | PRESENT | USER) };
+        self.pages.insert(user, UserPage { kernel, writable: 0 != flags & WRITABLE });
+        Ok(())
+    }
+
+    /// Maps the pages that contain `code` read-only and executable. Returns the
+    /// user address of the first byte of `code`.
+    pub fn map_code(&mut self, platform: &dyn Platform, code: &[u8]) -> Result<u64, SandboxError> {
+        let start = code.as_ptr() as u64 & !(PAGE_SIZE - 1);
+        let end = code.as_ptr() as u64 + code.len() as u64;
+        let pages = (end - start + PAGE_SIZE - 1) / PAGE_SIZE;
+        if pages > MAX_CODE_PAGES {
+            return Err(SandboxError::CodeTooLarge);
+        }
+        for page in 0..pages {
+            self.map(platform, USER_CODE + page * PAGE_SIZE, (start + page * PAGE_SIZE) as *mut u8, 0)?;
+        }
+        Ok(USER_CODE + (code.as_ptr() as u64 - start))
+    }
+
+    /// Maps the page that contains `trampoline` read-only and executable.
+    /// Returns the user address of `trampoline`.
+    pub fn map_trampoline(&mut self, platform: &dyn Platform, trampoline: *const u8) -> Result<u64, SandboxError> {
+        let page = trampoline as u64 & !(PAGE_SIZE - 1);
+        self.map(platform, USER_TRAMPOLINE, page as *mut u8, 0)?;
+        Ok(USER_TRAMPOLINE + (trampoline as u64 - page))
+    }
+
+    /// Maps a new zeroed page that is writable and not executable.
+    pub fn map_zeroed(&mut self, platform: &dyn Platform, user: u64) -> Result<(), SandboxError> {
+        let frame = new_table();
+        self.map(platform, user, frame.as_ptr(), WRITABLE | NO_EXECUTE)?;
+        self.frames.push(frame);
+        Ok(())
+    }
+
+    /// Returns the kernel address of the user address `user` if it is mapped
+    /// and, for writes, writable.
+    fn kernel_address(&self, user: u64, write: bool) -> Option<*mut u8> {
+        let page = self.pages.get(&(user & !(PAGE_SIZE - 1)))?;
+        if write && !page.writable {
+            return None;
+        }
+        Some(unsafe { page.kernel.add((user % PAGE_SIZE) as usize) })
+    }
+
+    /// Copies user memory at `user` into `dst`. Returns `false` if not all of
+    /// the memory is mapped.
+    pub fn copy_from_user(&self, user: u64, dst: &mut [u8]) -> bool {
+        for (offset, byte) in dst.iter_mut().enumerate() {
+            match user.checked_add(offset as u64).and_then(|addr| self.kernel_address(addr, false)) {
+                Some(src) => *byte = unsafe { src.read_volatile() },
+                None => return false,
+            }
+        }
+        true
+    }
+
+    /// Copies `src` to user memory at `user`. Returns `false` if not all of the
+    /// memory is mapped writable.
+    pub fn copy_to_user(&mut self, user: u64, src: &[u8]) -> bool {
+        // Check first, so that a failing copy has no effect
+        let len = src.len() as u64;
+        let pages_ok = (0..len)
+            .step_by(PAGE_SIZE as usize)
+            .chain(len.checked_sub(1))
+            .all(|offset| user.checked_add(offset).and_then(|addr| self.kernel_address(addr, true)).is_some());
+        if !pages_ok {
+            return false;
+        }
+        for (offset, byte) in src.iter().enumerate() {
+            let dst = self.kernel_address(user + offset as u64, true).unwrap();
+            unsafe { dst.write_volatile(*byte) };
+        }
+        true
+    }
+}
+
+/// A task loaded from a module together with its address space and I/O.
+pub struct Sandbox<'a> {
+    platform: &'a dyn Platform,
+    space: AddressSpace,
+    entry: u64,
+    trampoline: u64,
+    input: &'a [u8],
+    output: Vec<u8>,
+    max_output: usize,
+    heap_next: u64,
+}
+
+impl<'a> Sandbox<'a> {
+    /// Prepares the address space for `image`. `trampoline` is the kernel
+    /// address of the code that calls the entry point and exits.
+    pub fn new(
+        platform: &'a dyn Platform,
+        kernel_l4: &[u64; 512],
+        image: &ModuleImage,
+        trampoline: *const u8,
+        input: &'a [u8],
+        max_output: usize,
+    ) -> Result<Self, SandboxError> {
+        let mut space = AddressSpace::new(platform, kernel_l4)?;
+        let code = space.map_code(platform, image.code())?;
+        let entry = code + (image.entry() - image.code().as_ptr() as u64);
+        let trampoline = space.map_trampoline(platform, trampoline)?;
+        for page in 1..=STACK_PAGES {
+            space.map_zeroed(platform, USER_STACK_TOP - page * PAGE_SIZE)?;
+        }
+        Ok(Self {
+            platform,
+            space,
+            entry,
+            trampoline,
+            input,
+            output: Vec::new(),
+            max_output,
+            heap_next: USER_HEAP,
+        })
+    }
+
+    pub fn address_space(&self) -> &AddressSpace {
+        &self.space
+    }
+
+    /// Returns the user address of the entry point of the task.
+    pub fn entry(&self) -> u64 {
+        self.entry
+    }
+
+    /// Returns the user address of the trampoline.
+    pub fn trampoline(&self) -> u64 {
+        self.trampoline
+    }
+
+    /// Returns the output the task wrote so far.
+    pub fn into_output(self) -> Vec<u8> {
+        self.output
+    }
+
+    /// Handles the syscall `nr` of the task.
+    pub fn syscall(&mut self, nr: u64, arg0: u64, arg1: u64, arg2: u64) -> SyscallResult {
+        let result = match Syscall::try_from(nr) {
+            Ok(Syscall::Exit) => return SyscallResult::Exit(arg0 as i64),
+            Ok(Syscall::ReadInput) => self.read_input(arg0, arg1, arg2),
+            Ok(Syscall::WriteOutput) => self.write_output(arg0, arg1),
+            Ok(Syscall::Allocate) => self.allocate(arg0),
+            Err(nr) => {
+                log::info!("Unknown syscall {:#x?}", nr);
+                None
+            }
+        };
+        SyscallResult::Return(result.unwrap_or(SYSCALL_ERROR))
+    }
+
+    fn read_input(&mut self, buf: u64, len: u64, offset: u64) -> Option<u64> {
+        let rest = self.input.get(usize::try_from(offset).ok()?..)?;
+        let len = rest.len().min(usize::try_from(len).ok()?);
+        self.space.copy_to_user(buf, &rest[..len]).then_some(len as u64)
+    }
+
+    fn write_output(&mut self, buf: u64, len: u64) -> Option<u64> {
+        let len = usize::try_from(len).ok()?;
+        if self.output.len().checked_add(len)? > self.max_output {
+            return None;
+        }
+        let start = self.output.len();
+        self.output.resize(start + len, 0);
+        if !self.space.copy_from_user(buf, &mut self.output[start..]) {
+            self.output.truncate(start);
+            return None;
+        }
+        Some(len as u64)
+    }
+
+    fn allocate(&mut self, size: u64) -> Option<u64> {
+        let pages = size.checked_add(PAGE_SIZE - 1)? / PAGE_SIZE;
+        let end = self.heap_next.checked_add(pages * PAGE_SIZE)?;
+        if 0 == pages || end > USER_HEAP + MAX_HEAP_PAGES * PAGE_SIZE {
+            return None;
+        }
+        let start = self.heap_next;
+        for page in 0..pages {
+            self.space.map_zeroed(self.platform, start + page * PAGE_SIZE).ok()?;
+        }
+        self.heap_next = end;
+        Some(start)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::SimPlatform;
+    use crate::tasks::module::tests::module;
+
+    static TRAMPOLINE: [u8; 16] = [0xc3; 16];
+
+    /// Stack address the tests use as scratch buffer.
+    const SCRATCH: u64 = USER_STACK_TOP - 0x100;
+
+    fn kernel_l4() -> [u64; 512] {
+        let mut l4 = [0; 512];
+        l4[511] = 0x1000 | PRESENT | WRITABLE;
+        l4
+    }
+
+    /// Returns the L1 entry of the user address `user`.
+    fn l1_entry(platform: &SimPlatform, space: &AddressSpace, user: u64) -> u64 {
+        let mut table = space.l4.as_ptr() as *const u64;
+        for index in [USER_L4_INDEX, 0, 0] {
+            let entry = unsafe { table.add(index).read() };
+            assert_eq!(entry & (PRESENT | USER), PRESENT | USER);
+            table = platform.get_virtual_address(entry & ADDRESS_MASK) as *const u64;
+        }
+        unsafe { table.add(((user - USER_BASE) / PAGE_SIZE) as usize).read() }
+    }
+
+    fn sandbox<'a>(platform: &'a SimPlatform, input: &'a [u8]) -> Sandbox<'a> {
+        let image = ModuleImage::parse(module(0x100, "sandboxed", 2, &[0x90, 0x90, 0xc3])).unwrap();
+        Sandbox::new(platform, &kernel_l4(), &image, TRAMPOLINE[1..].as_ptr(), input, 8).unwrap()
+    }
+
+    #[test]
+    fn test_address_space() {
+        let platform = SimPlatform::default();
+        let sandbox = sandbox(&platform, &[]);
+        let space = sandbox.address_space();
+        // Kernel mappings are shared but stay supervisor-only
+        assert_eq!(unsafe { (space.l4.as_ptr() as *const u64).add(511).read() }, kernel_l4()[511]);
+
+        let code = l1_entry(&platform, space, sandbox.entry() & !(PAGE_SIZE - 1));
+        assert_eq!(code & (WRITABLE | NO_EXECUTE), 0);
+        assert_eq!(sandbox.trampoline() % PAGE_SIZE, (TRAMPOLINE.as_ptr() as u64 + 1) % PAGE_SIZE);
+        let trampoline = l1_entry(&platform, space, USER_TRAMPOLINE);
+        assert_eq!(trampoline & (WRITABLE | NO_EXECUTE), 0);
+        let stack = l1_entry(&platform, space, USER_STACK_TOP - PAGE_SIZE);
+        assert_eq!(stack & (WRITABLE | NO_EXECUTE), WRITABLE | NO_EXECUTE);
+        assert_eq!(l1_entry(&platform, space, USER_HEAP), 0);
+    }
+
+    #[test]
+    fn test_user_window_in_use() {
+        let platform = SimPlatform::default();
+        let mut l4 = kernel_l4();
+        l4[USER_L4_INDEX] = 0x2000 | PRESENT;
+        assert_eq!(AddressSpace::new(&platform, &l4).unwrap_err(), SandboxError::UserWindowInUse);
+    }
+
+    #[test]
+    fn test_read_input() {
+        let platform = SimPlatform::default();
+        let mut sandbox = sandbox(&platform, &[1, 2, 3, 4]);
+        assert_eq!(sandbox.syscall(Syscall::ReadInput as u64, SCRATCH, 16, 1), SyscallResult::Return(3));
+        let mut buf = [0; 4];
+        assert!(sandbox.address_space().copy_from_user(SCRATCH, &mut buf));
+        assert_eq!(buf, [2, 3, 4, 0]);
+        // Code is not writable
+        let entry = sandbox.entry();
+        assert_eq!(sandbox.syscall(Syscall::ReadInput as u64, entry, 1, 0), SyscallResult::Return(SYSCALL_ERROR));
+        assert_eq!(sandbox.syscall(Syscall::ReadInput as u64, SCRATCH, 1, 5), SyscallResult::Return(SYSCALL_ERROR));
+    }
+
+    #[test]
+    fn test_write_output() {
+        let platform = SimPlatform::default();
+        let mut sandbox = sandbox(&platform, &[]);
+        assert!(sandbox.space.copy_to_user(SCRATCH, &[7; 8]));
+        assert_eq!(sandbox.syscall(Syscall::WriteOutput as u64, SCRATCH, 6, 0), SyscallResult::Return(6));
+        // The output is limited to 8 bytes
+        assert_eq!(sandbox.syscall(Syscall::WriteOutput as u64, SCRATCH, 3, 0), SyscallResult::Return(SYSCALL_ERROR));
+        // Kernel memory is not accessible
+        assert_eq!(sandbox.syscall(Syscall::WriteOutput as u64, 0x1000, 1, 0), SyscallResult::Return(SYSCALL_ERROR));
+        // Lengths that overflow are rejected
+        assert_eq!(
+            sandbox.syscall(Syscall::WriteOutput as u64, SCRATCH, u64::MAX, 0),
+            SyscallResult::Return(SYSCALL_ERROR)
+        );
+        assert_eq!(sandbox.syscall(Syscall::Exit as u64, -1_i64 as u64, 0, 0), SyscallResult::Exit(-1));
+        assert_eq!(sandbox.into_output(), [7; 6]);
+    }
+
+    #[test]
+    fn test_allocate() {
+        let platform = SimPlatform::default();
+        let mut sandbox = sandbox(&platform, &[]);
+        assert_eq!(sandbox.syscall(Syscall::Allocate as u64, 1, 0, 0), SyscallResult::Return(USER_HEAP));
+        assert_eq!(
+            sandbox.syscall(Syscall::Allocate as u64, PAGE_SIZE + 1, 0, 0),
+            SyscallResult::Return(USER_HEAP + PAGE_SIZE)
+        );
+        assert!(sandbox.space.copy_to_user(USER_HEAP + 3 * PAGE_SIZE - 1, &[1]));
+        assert_eq!(
+            sandbox.syscall(Syscall::Allocate as u64, 2 * PAGE_SIZE, 0, 0),
+            SyscallResult::Return(SYSCALL_ERROR)
+        );
+        assert_eq!(sandbox.syscall(0x42, 0, 0, 0), SyscallResult::Return(SYSCALL_ERROR));
+    }
+}
@@ -0,0 +1,199 @@
+//! Sealing of data, so that it survives the TEE instance.
+//!
+//! A sealed blob is encrypted and authenticated with XChaCha20-Poly1305 under
+//! the [`SealingKey`]. The key is derived with HKDF-SHA256 from the boot secret
+//! and the [measurement](crate::measurement) of the TEE. Therefore, only the
+//! same TEE binary that received the same boot secret can unseal a blob. Each
+//! [`SealPurpose`] has its own key, so a blob sealed for one purpose cannot be
+//! unsealed for another.
+//!
+//! ```text
+//! 0         1       25
+//! | version | nonce | ciphertext | tag (16 bytes) |
+//! ```
+//!
+//! The nonce is derived from the data, so sealing is deterministic: sealing the
+//! same data twice yields the same blob.
+
+use alloc::vec::Vec;
+use chacha20poly1305::aead::AeadInPlace;
+use chacha20poly1305::{KeyInit, Tag, XChaCha20Poly1305, XNonce};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+use crate::measurement::Measurement;
+use crate::platform::Platform;
+use crate::state_machine::task::TaskError;
+
+/// Version of the blob format described in the [module docs](self).
+pub const SEAL_VERSION: u8 = 1;
+const NONCE_LEN: usize = 24;
+const TAG_LEN: usize = 16;
+const HEADER_LEN: usize = 1 + NONCE_LEN;
+/// Number of bytes a blob is larger than the sealed data.
+pub const SEAL_OVERHEAD: usize = HEADER_LEN + TAG_LEN;
+
+/// Reason why a blob could not be unsealed.
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub enum SealError {
+    /// The blob is shorter than [`SEAL_OVERHEAD`] or does not fit the output.
+    InvalidLength,
+    UnsupportedVersion(u8),
+    /// The blob was modified, or it was sealed by a different TEE binary or
+    /// with a different boot secret.
+    AuthenticationFailed,
+}
+
+impl From<SealError> for TaskError {
+    fn from(_error: SealError) -> TaskError {
+        TaskError::InvalidInput
+    }
+}
+
+/// What a [`SealingKey`] seals.
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub enum SealPurpose {
+    /// Data of the host, see [`crate::tasks::seal`].
+    Data,
+    /// State of the monotonic counters, see [`crate::tasks::counter`].
+    Counters,
+}
+
+impl SealPurpose {
+    /// Returns the HKDF info of the key and of the nonce key.
+    fn info(self) -> (&'static [u8], &'static [u8]) {
+        match self {
+            SealPurpose::Data => (b"phipsboot seal key", b"phipsboot seal nonce"),
+            SealPurpose::Counters => (b"phipsboot counters key", b"phipsboot counters nonce"),
+        }
+    }
+}
+
+/// Keys to seal and unseal data. They are zeroed when dropped.
+pub struct SealingKey {
+    key: Zeroizing<[u8; 32]>,
+    nonce_key: Zeroizing<[u8; 32]>,
+}
+
+impl SealingKey {
+    /// Derives the key for `purpose` of the TEE with the measurement
+    /// `measurement` that received `boot_secret`.
+    pub fn derive(purpose: SealPurpose, measurement: &Measurement, boot_secret: &[u8]) -> Self {
+        let hkdf = Hkdf::<Sha256>::new(Some(measurement), boot_secret);
+        let (key_info, nonce_info) = purpose.info();
+        let mut key = Zeroizing::new([0; 32]);
+        let mut nonce_key = Zeroizing::new([0; 32]);
+        // Only fails for outputs longer than 255 hashes
+        hkdf.expand(key_info, &mut *key).unwrap();
+        hkdf.expand(nonce_info, &mut *nonce_key).unwrap();
+        Self { key, nonce_key }
+    }
+
+    /// Derives the key for `purpose` from the measurement and the boot secret
+    /// of `platform`. Returns `None` if the TEE received no boot secret.
+    pub fn from_platform(purpose: SealPurpose, platform: &dyn Platform) -> Option<Self> {
+        let boot_secret = platform.boot_secret()?;
+        Some(Self::derive(purpose, &platform.measurement(), boot_secret))
+    }
+
+    fn cipher(&self) -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new((&*self.key).into())
+    }
+
+    /// Encrypts and authenticates `data`. Returns the blob.
+    pub fn seal(&self, data: &[u8]) -> Vec<u8> {
+        // Only fails for keys that are too long
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&*self.nonce_key).unwrap();
+        mac.update(data);
+        let nonce = XNonce::clone_from_slice(&mac.finalize().into_bytes()[..NONCE_LEN]);
+
+        let mut blob = Vec::with_capacity(data.len() + SEAL_OVERHEAD);
+        blob.push(SEAL_VERSION);
+        blob.extend_from_slice(&nonce);
+        blob.extend_from_slice(data);
+        // Only fails for data larger than 256 GiB
+        let tag = self
+            .cipher()
+            .encrypt_in_place_detached(&nonce, &[SEAL_VERSION], &mut blob[HEADER_LEN..])
+            .unwrap();
+        blob.extend_from_slice(&tag);
+        blob
+    }
+
+    /// Returns the length of the data sealed in `blob`.
+    pub fn data_len(blob: &[u8]) -> Result<usize, SealError> {
+        blob.len().checked_sub(SEAL_OVERHEAD).ok_or(SealError::InvalidLength)
+    }
+
+    /// Verifies `blob` and decrypts its data into `out`, which must be exactly
+    /// [`SealingKey::data_len`] bytes long. `out` only receives plaintext if the
+    /// blob is authentic.
+    pub fn unseal(&self, blob: &[u8], out: &mut [u8]) -> Result<(), SealError> {
+        if Self::data_len(blob)? != out.len() {
+            return Err(SealError::InvalidLength);
+        }
+        if SEAL_VERSION != blob[0] {
+            return Err(SealError::UnsupportedVersion(blob[0]));
+        }
+        let nonce = XNonce::from_slice(&blob[1..HEADER_LEN]);
+        let (ciphertext, tag) = blob[HEADER_LEN..].split_at(out.len());
+        out.copy_from_slice(ciphertext);
+        self.cipher()
+            .decrypt_in_place_detached(nonce, &[SEAL_VERSION], out, Tag::from_slice(tag))
+            .map_err(|_| SealError::AuthenticationFailed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    const MEASUREMENT: Measurement = [0x11; 32];
+    const BOOT_SECRET: [u8; 32] = [0x22; 32];
+
+    fn unseal(key: &SealingKey, blob: &[u8]) -> Result<Vec<u8>, SealError> {
+        let mut out = vec![0; SealingKey::data_len(blob)?];
+        key.unseal(blob, &mut out).map(|_| out)
+    }
+
+    #[test]
+    fn test_seal_unseal() {
+        let key = SealingKey::derive(SealPurpose::Data, &MEASUREMENT, &BOOT_SECRET);
+        let blob = key.seal(b"secret");
+        assert_eq!(blob.len(), 6 + SEAL_OVERHEAD);
+        assert_eq!(blob[0], SEAL_VERSION);
+        assert!(!blob.windows(6).any(|window| b"secret" == window));
+        assert_eq!(unseal(&key, &blob), Ok(b"secret".to_vec()));
+        // Deterministic, but different data gets a different nonce
+        assert_eq!(key.seal(b"secret"), blob);
+        assert_ne!(key.seal(b"secreT")[1..HEADER_LEN], blob[1..HEADER_LEN]);
+        assert_eq!(unseal(&key, &key.seal(&[])), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn test_unseal_errors() {
+        let key = SealingKey::derive(SealPurpose::Data, &MEASUREMENT, &BOOT_SECRET);
+        let blob = key.seal(b"secret");
+
+        let mut tampered = blob.clone();
+        tampered[HEADER_LEN] ^= 1;
+        assert_eq!(unseal(&key, &tampered), Err(SealError::AuthenticationFailed));
+        let mut tampered = blob.clone();
+        tampered[0] = 2;
+        assert_eq!(unseal(&key, &tampered), Err(SealError::UnsupportedVersion(2)));
+        assert_eq!(unseal(&key, &blob[..SEAL_OVERHEAD - 1]), Err(SealError::InvalidLength));
+        assert_eq!(key.unseal(&blob, &mut [0; 5]), Err(SealError::InvalidLength));
+
+        // Bound to the measurement and the boot secret
+        let other_binary = SealingKey::derive(SealPurpose::Data, &[0x12; 32], &BOOT_SECRET);
+        assert_eq!(unseal(&other_binary, &blob), Err(SealError::AuthenticationFailed));
+        let other_secret = SealingKey::derive(SealPurpose::Data, &MEASUREMENT, &[0x23; 32]);
+        assert_eq!(unseal(&other_secret, &blob), Err(SealError::AuthenticationFailed));
+        // and to the purpose
+        let counters = SealingKey::derive(SealPurpose::Counters, &MEASUREMENT, &BOOT_SECRET);
+        assert_eq!(unseal(&counters, &blob), Err(SealError::AuthenticationFailed));
+    }
+}
@@ -0,0 +1,245 @@
+//! Containers that zero secrets when they are dropped.
+//!
+//! [`Secret`] holds a single value, [`SecretBox`] a buffer of bytes. Both live
+//! on the heap and overwrite their memory with volatile writes followed by a
+//! compiler fence when dropped, so that the zeroing cannot be optimized away.
+//!
+//! All live secrets are tracked in a registry. [`wipe_all`] zeroes them at
+//! once, which the TEE does when it detects tampering or panics. Their owners
+//! see zeroes afterwards and must check [`is_wiped`] before they derive keys
+//! from long-lived secrets.
+
+use alloc::alloc::{alloc_zeroed, dealloc, handle_alloc_error, Layout};
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use core::cell::{Cell, RefCell};
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+use core::ptr::{self, NonNull};
+use core::sync::atomic::{compiler_fence, Ordering};
+
+/// Addresses and lengths of all live secrets.
+type Registry = BTreeMap<usize, usize>;
+
+#[cfg(not(test))]
+static REGISTRY: crate::safe::Safe<RefCell<Registry>> = crate::safe::Safe::new(RefCell::new(BTreeMap::new()));
+/// Set once [`wipe_all`] ran.
+#[cfg(not(test))]
+static WIPED: crate::safe::Safe<Cell<bool>> = crate::safe::Safe::new(Cell::new(false));
+
+// Tests run in parallel threads, each of them gets its own registry.
+#[cfg(test)]
+std::thread_local! {
+    static REGISTRY: RefCell<Registry> = const { RefCell::new(BTreeMap::new()) };
+    static WIPED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Runs `f` on the registry. Returns `None` if the registry is in use, e.g.,
+/// because a panic interrupted an update.
+fn with_registry<R>(f: impl FnOnce(&mut Registry) -> R) -> Option<R> {
+    #[cfg(not(test))]
+    return REGISTRY.try_borrow_mut().ok().map(|mut registry| f(&mut registry));
+    #[cfg(test)]
+    return REGISTRY.with(|registry| registry.try_borrow_mut().ok().map(|mut registry| f(&mut registry)));
+}
+
+fn register(ptr: *const u8, len: usize) {
+    if 0 != len {
+        with_registry(|registry| registry.insert(ptr as usize, len));
+    }
+}
+
+fn unregister(ptr: *const u8) {
+    with_registry(|registry| registry.remove(&(ptr as usize)));
+}
+
+/// Overwrites `len` bytes at `ptr` with zeroes.
+///
+/// # Safety
+/// `ptr` must be valid for writes of `len` bytes.
+unsafe fn wipe_bytes(ptr: *mut u8, len: usize) {
+    for x in 0..len {
+        ptr::write_volatile(ptr.add(x), 0);
+    }
+    compiler_fence(Ordering::SeqCst);
+}
+
+/// Zeroes all live secrets. Returns the number of wiped secrets.
+pub fn wipe_all() -> usize {
+    WIPED.set(true);
+    with_registry(|registry| {
+        for (&addr, &len) in registry.iter() {
+            unsafe { wipe_bytes(addr as *mut u8, len) };
+        }
+        registry.len()
+    })
+    .unwrap_or(0)
+}
+
+/// Returns `true` if [`wipe_all`] ran. Secrets that were live back then are
+/// zeroes now and stay so.
+pub fn is_wiped() -> bool {
+    WIPED.get()
+}
+
+/// Returns the number of live secrets.
+pub fn live_secrets() -> usize {
+    with_registry(|registry| registry.len()).unwrap_or(0)
+}
+
+/// Types for which all bytes being zero is a valid value.
+///
+/// # Safety
+/// The type must not contain padding, references or other types with invalid
+/// bit patterns.
+pub unsafe trait Zeroable: Copy {}
+
+unsafe impl Zeroable for u8 {}
+unsafe impl Zeroable for u16 {}
+unsafe impl Zeroable for u32 {}
+unsafe impl Zeroable for u64 {}
+unsafe impl Zeroable for u128 {}
+unsafe impl Zeroable for usize {}
+unsafe impl<T: Zeroable, const N: usize> Zeroable for [T; N] {}
+
+/// Value on the heap that is zeroed when dropped.
+pub struct Secret<T: Zeroable> {
+    value: Box<T>,
+}
+
+impl<T: Zeroable> Secret<T> {
+    pub fn new(value: T) -> Self {
+        let value = Box::new(value);
+        register(&*value as *const T as *const u8, core::mem::size_of::<T>());
+        Self { value }
+    }
+
+    /// Returns a pointer to the value. It stays the same while the secret
+    /// lives.
+    pub fn as_ptr(&self) -> *mut T {
+        &*self.value as *const T as *mut T
+    }
+
+    /// Overwrites the value with zeroes.
+    pub fn wipe(&mut self) {
+        unsafe { wipe_bytes(self.as_ptr() as *mut u8, core::mem::size_of::<T>()) };
+    }
+}
+
+impl<T: Zeroable> Deref for Secret<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.value
+    }
+}
+
+impl<T: Zeroable> DerefMut for Secret<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.value
+    }
+}
+
+impl<T: Zeroable> fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret(..)")
+    }
+}
+
+impl<T: Zeroable> Drop for Secret<T> {
+    fn drop(&mut self) {
+        self.wipe();
+        unregister(self.as_ptr() as *const u8);
+    }
+}
+
+/// Heap buffer of bytes that is zeroed when dropped.
+#[derive(Debug)]
+pub struct SecretBox {
+    ptr: NonNull<u8>,
+    layout: Layout,
+}
+
+impl SecretBox {
+    /// Allocates a zeroed buffer with the given size and alignment. Panics if
+    /// `size` is 0 or `align` is not a power of two.
+    pub fn new(size: usize, align: usize) -> Self {
+        // Allocations of zero bytes are undefined behavior
+        assert!(0 != size, "secret buffers must not be empty");
+        let layout = Layout::from_size_align(size, align).unwrap();
+        let ptr = unsafe { alloc_zeroed(layout) };
+        let ptr = NonNull::new(ptr).unwrap_or_else(|| handle_alloc_error(layout));
+        register(ptr.as_ptr(), size);
+        Self { ptr, layout }
+    }
+
+    /// Returns a pointer to the first byte of the buffer.
+    pub fn as_ptr(&self) -> *mut u8 {
+        self.ptr.as_ptr()
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.as_ptr(), self.len()) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.as_ptr(), self.len()) }
+    }
+
+    /// Returns the size of the buffer in bytes.
+    pub fn len(&self) -> usize {
+        self.layout.size()
+    }
+
+    /// Returns `true` if the buffer has a size of zero bytes.
+    pub fn is_empty(&self) -> bool {
+        0 == self.len()
+    }
+
+    /// Overwrites the whole buffer with zeroes.
+    pub fn wipe(&mut self) {
+        unsafe { wipe_bytes(self.as_ptr(), self.len()) };
+    }
+}
+
+impl Drop for SecretBox {
+    fn drop(&mut self) {
+        self.wipe();
+        unregister(self.as_ptr());
+        unsafe { dealloc(self.as_ptr(), self.layout) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secret() {
+        let mut secret = Secret::new([0xab_u8; 16]);
+        assert_eq!(*secret, [0xab; 16]);
+        secret[0] = 1;
+        assert_eq!(secret[..2], [1, 0xab]);
+        assert_eq!(live_secrets(), 1);
+        secret.wipe();
+        assert_eq!(*secret, [0; 16]);
+        drop(secret);
+        assert_eq!(live_secrets(), 0);
+    }
+
+    #[test]
+    fn test_wipe_all() {
+        let secret = Secret::new(0x1337_beef_u32);
+        let mut buffer = SecretBox::new(64, 8);
+        buffer.as_mut_slice().fill(0xab);
+        assert_eq!(live_secrets(), 2);
+
+        assert_eq!(wipe_all(), 2);
+        assert_eq!(*secret, 0);
+        assert_eq!(buffer.as_slice(), [0; 64]);
+        // Wiped secrets stay usable
+        buffer.as_mut_slice()[0] = 1;
+        drop(buffer);
+        assert_eq!(live_secrets(), 1);
+    }
+}
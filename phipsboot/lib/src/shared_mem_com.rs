@@ -0,0 +1,295 @@
+//! Communication with the host via a shared memory region.
+//!
+//! The region starts with a header, followed by the payload:
+//!
+//! ```text
+//! 0        1         2      4         6          8        12         16
+//! | status | trailer | task | session | reserved | length | reserved | payload ...
+//! ```
+//!
+//! - `status`: [`TeeCommand`] that is written by the host to issue a request
+//!   and by the TEE to respond.
+//! - `task`: Little-endian [`TaskId`] of the task to execute.
+//! - `session`: Little-endian [`SessionId`] the request belongs to. The TEE
+//!   writes the ID of a newly opened session here.
+//! - `length`: Little-endian number of valid payload bytes. The host sets it to
+//!   the size of the input, the TEE to the size of the output.
+//! - `trailer`: Number of bytes the TEE appended directly after the payload,
+//!   i.e., the [`TaskProfile`](crate::state_machine::profile::TaskProfile) of
+//!   the task that ran. 0 if there is no trailer, e.g., because it did not
+//!   fit.
+//!
+//! If the TEE responds with [`TeeCommand::TeeError`], the payload holds a
+//! single error code.
+
+use crate::state_machine::session::{SessionId, DEFAULT_SESSION};
+use crate::state_machine::task_id::TaskId;
+
+use alloc::vec::Vec;
+use core::slice;
+use core::ptr;
+
+/// Offset of the status byte.
+const STATUS_OFFSET: usize = 0;
+/// Offset of the trailer length.
+const TRAILER_OFFSET: usize = 1;
+/// Offset of the task ID.
+const TASK_OFFSET: usize = 2;
+/// Offset of the session ID.
+const SESSION_OFFSET: usize = 4;
+/// Offset of the payload length.
+const LENGTH_OFFSET: usize = 8;
+/// Size of the header in front of the payload.
+pub const HEADER_SIZE: usize = 16;
+
+#[repr(C, align(8), u8)]
+#[derive(Copy, Clone, Debug)]
+pub enum TeeCommand {
+    None = 0,
+    TeeReady = 0x01,
+    TeeSend = 0x02,
+    /// The TEE could not process the request, e.g., because the session is
+    /// unknown.
+    TeeError = 0x03,
+    HostSend = 0x11,
+    HostOpenSession = 0x12,
+    HostCloseSession = 0x13,
+    /// Closes all sessions.
+    HostReset = 0x14,
+    Unknown (u8),
+}
+
+
+#[derive(Debug)]
+#[repr(C, align(8))]
+pub struct SharedMemCommunicator {
+    memory : *mut u8,
+    size: usize,
+    still_waiting: bool,
+}
+
+impl From<u8> for TeeCommand {
+    fn from(num: u8) -> TeeCommand {
+        match num {
+            0 => TeeCommand::None,
+            0x01 => TeeCommand::TeeReady,
+            0x02 => TeeCommand::TeeSend,
+            0x03 => TeeCommand::TeeError,
+            0x11 => TeeCommand::HostSend,
+            0x12 => TeeCommand::HostOpenSession,
+            0x13 => TeeCommand::HostCloseSession,
+            0x14 => TeeCommand::HostReset,
+            x => TeeCommand::Unknown(x),
+        }
+    }
+}
+
+impl From<TeeCommand> for u8 {
+    fn from(command: TeeCommand) -> u8 {
+        match command {
+            TeeCommand::None => 0,
+            TeeCommand::TeeReady => 0x01,
+            TeeCommand::TeeSend => 0x02,
+            TeeCommand::TeeError => 0x03,
+            TeeCommand::HostSend => 0x11,
+            TeeCommand::HostOpenSession => 0x12,
+            TeeCommand::HostCloseSession => 0x13,
+            TeeCommand::HostReset => 0x14,
+            TeeCommand::Unknown(x) => x,
+        }
+    }
+}
+
+
+impl SharedMemCommunicator {
+    pub unsafe fn from_raw_parts(mem: *mut u8, size: usize) -> Self{
+        SharedMemCommunicator {
+            memory: mem,
+            size,
+            still_waiting: false,
+        }
+    }
+
+    pub fn get_status(&self) -> TeeCommand {
+        if true == self.memory.is_null() {
+            return TeeCommand::Unknown(0xff);
+        }
+        unsafe {
+            Into::<TeeCommand>::into(ptr::read(self.memory.add(STATUS_OFFSET)))
+        }
+    }
+
+    pub fn get_task(&self) -> TaskId {
+        if true == self.memory.is_null() {
+            return TaskId::UNKNOWN;
+        }
+        unsafe {
+            TaskId(u16::from_le(ptr::read_unaligned(self.memory.add(TASK_OFFSET) as *const u16)))
+        }
+    }
+
+    pub fn get_session(&self) -> SessionId {
+        if true == self.memory.is_null() {
+            return DEFAULT_SESSION;
+        }
+        unsafe {
+            SessionId::from_le(ptr::read_unaligned(self.memory.add(SESSION_OFFSET) as *const SessionId))
+        }
+    }
+
+    /// Returns the number of valid payload bytes.
+    pub fn get_length(&self) -> usize {
+        if true == self.memory.is_null() {
+            return 0;
+        }
+        unsafe {
+            u32::from_le(ptr::read_unaligned(self.memory.add(LENGTH_OFFSET) as *const u32)) as usize
+        }
+    }
+
+    /// Returns the size of the payload area.
+    pub fn capacity(&self) -> usize {
+        self.size - HEADER_SIZE
+    }
+
+    pub fn set_status(&self, command: TeeCommand) {
+        unsafe {
+            (ptr::write(self.memory.add(STATUS_OFFSET), Into::<u8>::into(command)));
+        }
+    }
+
+    pub fn set_task(&self, task: TaskId) {
+        unsafe {
+            ptr::write_unaligned(self.memory.add(TASK_OFFSET) as *mut u16, u16::from(task).to_le());
+        }
+    }
+
+    pub fn set_session(&self, session: SessionId) {
+        unsafe {
+            ptr::write_unaligned(self.memory.add(SESSION_OFFSET) as *mut SessionId, session.to_le());
+        }
+    }
+
+    /// Sets the number of valid payload bytes. Must not exceed
+    /// [`SharedMemCommunicator::capacity`].
+    pub fn set_length(&self, length: usize) {
+        assert!(length <= self.capacity());
+        unsafe {
+            ptr::write_unaligned(self.memory.add(LENGTH_OFFSET) as *mut u32, (length as u32).to_le());
+        }
+    }
+
+    /// Copies the valid payload bytes into `dst`. Returns `false` if the length
+    /// in the header exceeds the payload area.
+    pub fn read_payload(&self, dst: &mut Vec<u8>) -> bool {
+        let length = self.get_length();
+        if length > self.capacity() {
+            return false;
+        }
+        dst.clear();
+        dst.extend_from_slice(unsafe {
+            slice::from_raw_parts(self.memory.add(HEADER_SIZE), length)
+        });
+        true
+    }
+
+    /// Copies `src` to the payload area and sets the length accordingly.
+    /// Returns `false` if `src` does not fit.
+    pub fn write_payload(&self, src: &[u8]) -> bool {
+        if src.len() > self.capacity() {
+            return false;
+        }
+        unsafe { self.write_mem(src, HEADER_SIZE) };
+        self.set_length(src.len());
+        self.clear_trailer();
+        true
+    }
+
+    /// Marks the response as having no trailer.
+    pub fn clear_trailer(&self) {
+        unsafe { ptr::write(self.memory.add(TRAILER_OFFSET), 0) };
+    }
+
+    /// Copies `src` behind the valid payload bytes and sets the trailer length
+    /// accordingly. Returns `false` and leaves no trailer if `src` does not
+    /// fit.
+    pub fn write_trailer(&self, src: &[u8]) -> bool {
+        let offset = HEADER_SIZE + self.get_length();
+        if src.len() > usize::from(u8::MAX) || offset + src.len() > self.size {
+            self.clear_trailer();
+            return false;
+        }
+        unsafe {
+            self.write_mem(src, offset);
+            ptr::write(self.memory.add(TRAILER_OFFSET), src.len() as u8);
+        }
+        true
+    }
+
+    /// Copies the trailer into `dst`. Returns `false` if there is none or if
+    /// it exceeds the shared memory.
+    pub fn read_trailer(&self, dst: &mut Vec<u8>) -> bool {
+        let offset = HEADER_SIZE + self.get_length();
+        let length = usize::from(unsafe { ptr::read(self.memory.add(TRAILER_OFFSET)) });
+        if 0 == length || offset + length > self.size {
+            return false;
+        }
+        dst.clear();
+        dst.extend_from_slice(unsafe {
+            slice::from_raw_parts(self.memory.add(offset), length)
+        });
+        true
+    }
+
+    pub unsafe fn write_status(&mut self, status: u8) {
+        ptr::write(self.memory.add(STATUS_OFFSET), status);
+    }
+
+    pub unsafe fn get_slice<'a>(&mut self) -> &'a mut [u8]{
+        slice::from_raw_parts_mut(self.memory.add(HEADER_SIZE), self.size - HEADER_SIZE)
+    }
+
+    /// Write bytes from source to the shared memory with offset `offset`
+    pub unsafe fn write_mem(&self, src: &[u8], offset: usize) {
+        let bytes_to_copy = if offset < self.size {
+            if src.len() < (self.size - offset) {
+                src.len()
+            } else {
+                self.size - offset
+            }
+        } else {
+            0
+        };
+        ptr::copy_nonoverlapping(src.as_ptr(), self.memory.add(offset), bytes_to_copy);
+    }
+
+    /// Waits until the host issues a command and returns it.
+    pub fn poll(&mut self) -> TeeCommand {
+        loop {
+            match self.get_status() {
+                TeeCommand::None =>{
+                    if  false == self.still_waiting {
+                        log::info!("nothing ot do!");
+                    }
+                    self.still_waiting = true;
+                }
+                TeeCommand::TeeSend | TeeCommand::TeeReady | TeeCommand::TeeError => {
+                    // log::info!("Waiting for response!");
+                },
+                command @ (TeeCommand::HostSend
+                | TeeCommand::HostOpenSession
+                | TeeCommand::HostCloseSession
+                | TeeCommand::HostReset) => {
+                    log::info!("Received message - Task {:?}, Command {:?}, Session {}", self.get_task(), command, self.get_session());
+                    self.still_waiting = false;
+                    unsafe{ self.write_status(TeeCommand::None.into()) };
+                    return command;
+                },
+                TeeCommand::Unknown(x) => log::info!("Found unknown status: {:#02x?}", x)
+            }
+            for _x in 0..(1024u64 * 1024 * 1024 * 10) {
+                unsafe { core::arch::asm!("nop") };
+            }
+        }
+    }
+}
@@ -0,0 +1,584 @@
+//! Host-runnable simulator of the TEE request loop.
+//!
+//! The [`Simulator`] backs the shared memory region with a heap allocation and
+//! runs the regular [`StateMachine`] on top of a [`SimPlatform`] that mocks
+//! paging and the PMCs. This allows to drive tasks end to end on a machine
+//! without the thesis tooling, for example in `cargo test`.
+
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::Cell;
+
+use crate::attestation::CpuInfo;
+use crate::entropy::EntropyError;
+use crate::measurement::Measurement;
+use crate::platform::{Platform, COUNTER_NUM};
+use crate::pmc_utils::architectural::{IA32_PERFEVTSEL_EN, IA32_PERFEVTSEL_INT, IA32_PERFEVTSEL_OS, IA32_PERFEVTSEL_USR};
+use crate::pmc_utils::events::Event;
+use crate::pmc_utils::global::{gp_counter_bit, OverflowStatus};
+use crate::pmc_utils::pmu::PmuInfo;
+use crate::secret;
+use crate::shared_mem_com::{SharedMemCommunicator, TeeCommand, HEADER_SIZE};
+use crate::state_machine::profile::TaskProfile;
+use crate::state_machine::session::SessionId;
+use crate::state_machine::task::{DuplicateTaskIdError, TaskDescriptor, TaskError};
+use crate::state_machine::task_id::TaskId;
+use crate::state_machine::{run_state_machine, StateInitialized, StateMachine};
+use crate::tasks::module::ModuleImage;
+
+/// Default size of the simulated shared memory region.
+pub const DEFAULT_SHARED_MEM_SIZE: usize = 0x1000 /* 4 KiB */;
+
+/// Offset between the simulated virtual and physical address spaces. The
+/// simulated "physical" address of a virtual address is `virt - offset`.
+const PHYS_OFFSET: u64 = 0x1000_0000_0000;
+
+/// [`Platform`] with mocked paging and PMCs.
+///
+/// Every virtual address is mapped to a physical address at a fixed offset.
+/// The PMCs are plain values that are armed with `u64::MAX`, just as the
+/// firmware does, and that can be changed via [`SimPlatform::set_pmcs`]. A
+/// PMC overflows if its new value is below the old one. All of them are
+/// configured as in [`SIM_PMC_CONFIG`]. No events are counted for
+/// benchmarks. The TSC advances by [`SIM_TSC_STEP`] on every read.
+/// Module tasks cannot be executed, as the host memory is not executable.
+/// The measurement, the CPU and the boot secret are the fixed
+/// [`SIM_MEASUREMENT`], [`SIM_CPU_INFO`] and [`SIM_BOOT_SECRET`]. Entropy is
+/// a deterministic pseudo-random sequence.
+#[derive(Debug, Default)]
+pub struct SimPlatform {
+    pmcs: Cell<[u64; COUNTER_NUM]>,
+    overflows: Cell<OverflowStatus>,
+    tsc: Cell<u64>,
+    entropy_state: Cell<u64>,
+    armed: Cell<bool>,
+}
+
+/// Measurement of the simulated TEE.
+pub const SIM_MEASUREMENT: Measurement = [0x5a; 32];
+/// Event selects of the simulated PMCs. All of them raise an interrupt on
+/// overflow and thus detect tampering.
+pub const SIM_PMC_CONFIG: [u64; COUNTER_NUM] =
+    [IA32_PERFEVTSEL_USR | IA32_PERFEVTSEL_OS | IA32_PERFEVTSEL_INT | IA32_PERFEVTSEL_EN; COUNTER_NUM];
+/// PMU of the simulated TEE, with 48 bit wide counters.
+pub const SIM_PMU: PmuInfo = PmuInfo {
+    version: 4,
+    gp_counters: COUNTER_NUM as u8,
+    gp_width: 48,
+    fixed_counters: 3,
+    fixed_width: 48,
+    events: 0x7f,
+};
+/// CPU of the simulated TEE.
+pub const SIM_CPU_INFO: CpuInfo = CpuInfo { signature: 0x000906ea, microcode: 0xf0 };
+/// Cycles the simulated TSC advances per read.
+pub const SIM_TSC_STEP: u64 = 100;
+/// Boot secret of the simulated TEE.
+pub const SIM_BOOT_SECRET: [u8; 32] = [0xa5; 32];
+
+impl SimPlatform {
+    /// Overwrites the values that are returned by [`Platform::read_pmcs`].
+    pub fn set_pmcs(&self, values: [u64; COUNTER_NUM]) {
+        let old = self.pmcs.replace(values);
+        let wrapped = (0..COUNTER_NUM).filter(|&x| values[x] < old[x]);
+        let bits = wrapped.fold(self.overflows.get().0, |bits, x| bits | gp_counter_bit(x as u8));
+        self.overflows.set(OverflowStatus(bits));
+    }
+
+    /// Overwrites the value that is returned by [`Platform::pmcs_armed`],
+    /// e.g., to simulate a CPU that cannot count the tamper-detection events.
+    pub fn set_armed(&self, armed: bool) {
+        self.armed.set(armed);
+    }
+}
+
+impl Platform for SimPlatform {
+    fn get_physical_address(&self, virt_addr: u64) -> u64 {
+        virt_addr.wrapping_sub(PHYS_OFFSET)
+    }
+
+    fn get_virtual_address(&self, phys_addr: u64) -> u64 {
+        phys_addr.wrapping_add(PHYS_OFFSET)
+    }
+
+    fn setup_pmcs(&self) -> bool {
+        self.pmcs.set([u64::MAX; COUNTER_NUM]);
+        self.overflows.set(OverflowStatus::default());
+        self.armed.set(true);
+        true
+    }
+
+    fn pmcs_armed(&self) -> bool {
+        self.armed.get()
+    }
+
+    fn read_pmcs(&self) -> [u64; COUNTER_NUM] {
+        self.pmcs.get()
+    }
+
+    fn pmu(&self) -> PmuInfo {
+        SIM_PMU
+    }
+
+    fn pmc_overflows(&self) -> OverflowStatus {
+        self.overflows.get()
+    }
+
+    fn rearm_pmcs(&self, status: OverflowStatus) {
+        let mut pmcs = self.pmcs.get();
+        for x in 0..COUNTER_NUM {
+            if status.counter(x as u8) {
+                pmcs[x] = u64::MAX;
+            }
+        }
+        self.pmcs.set(pmcs);
+        self.overflows.set(OverflowStatus(self.overflows.get().0 & !status.0));
+    }
+
+    fn read_tsc(&self) -> u64 {
+        let tsc = self.tsc.get() + SIM_TSC_STEP;
+        self.tsc.set(tsc);
+        tsc
+    }
+
+    fn pmc_config(&self) -> [u64; COUNTER_NUM] {
+        SIM_PMC_CONFIG
+    }
+
+    fn count_event(&self, _event: Event, f: &mut dyn FnMut()) -> u64 {
+        f();
+        0
+    }
+
+    fn run_module(&self, image: &ModuleImage, _input: &[u8], _output: &mut [u8]) -> Result<usize, TaskError> {
+        log::info!("Cannot execute module task {:?} in the simulator", image.name());
+        Err(TaskError::ExecutionFailed)
+    }
+
+    fn measurement(&self) -> Measurement {
+        SIM_MEASUREMENT
+    }
+
+    fn cpu_info(&self) -> CpuInfo {
+        SIM_CPU_INFO
+    }
+
+    fn boot_secret(&self) -> Option<&[u8]> {
+        if secret::is_wiped() {
+            return None;
+        }
+        Some(&SIM_BOOT_SECRET)
+    }
+
+    fn fill_entropy(&self, buf: &mut [u8]) -> Result<(), EntropyError> {
+        // SplitMix64
+        for chunk in buf.chunks_mut(8) {
+            let state = self.entropy_state.get().wrapping_add(0x9e37_79b9_7f4a_7c15);
+            self.entropy_state.set(state);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^= z >> 31;
+            chunk.copy_from_slice(&z.to_le_bytes()[..chunk.len()]);
+        }
+        Ok(())
+    }
+}
+
+/// Simulated TEE together with the host side of the shared memory.
+pub struct Simulator {
+    memory: *mut [u8],
+    host: SharedMemCommunicator,
+    machine: Option<StateMachine<StateInitialized, SimPlatform>>,
+}
+
+impl Simulator {
+    /// Creates a simulator with a zeroed shared memory region of `size` bytes.
+    /// Panics if `size` does not even fit the header of the region.
+    pub fn new(size: usize) -> Self {
+        assert!(size >= HEADER_SIZE, "the shared memory must fit its header");
+        let memory = Box::into_raw(vec![0_u8; size].into_boxed_slice());
+        let (host, tee) = unsafe {
+            (
+                SharedMemCommunicator::from_raw_parts(memory.cast(), size),
+                SharedMemCommunicator::from_raw_parts(memory.cast(), size),
+            )
+        };
+        let platform = SimPlatform::default();
+        platform.setup_pmcs();
+        Self {
+            memory,
+            host,
+            machine: Some(StateMachine::new(tee, platform)),
+        }
+    }
+
+    /// Returns the mocked platform of the simulated TEE.
+    pub fn platform(&self) -> &SimPlatform {
+        self.machine.as_ref().unwrap().platform()
+    }
+
+    /// Registers an additional task, like the firmware does for modules.
+    pub fn register_task(&mut self, descriptor: TaskDescriptor) -> Result<(), DuplicateTaskIdError> {
+        self.machine.as_mut().unwrap().register_task(descriptor)
+    }
+
+    /// Returns the status byte as seen by the host.
+    pub fn status(&self) -> TeeCommand {
+        self.host.get_status()
+    }
+
+    /// Returns the task ID as seen by the host.
+    pub fn task(&self) -> TaskId {
+        self.host.get_task()
+    }
+
+    /// Returns the session ID as seen by the host.
+    pub fn session(&self) -> SessionId {
+        self.host.get_session()
+    }
+
+    /// Selects the session subsequent requests are sent in.
+    pub fn set_session(&mut self, session: SessionId) {
+        self.host.set_session(session);
+    }
+
+    /// Returns the payload area of the shared memory as seen by the host.
+    pub fn payload(&mut self) -> &mut [u8] {
+        unsafe { self.host.get_slice() }
+    }
+
+    /// Returns the valid payload bytes of the last response.
+    pub fn output(&self) -> Vec<u8> {
+        let mut output = Vec::new();
+        assert!(self.host.read_payload(&mut output));
+        output
+    }
+
+    /// Returns the profile the TEE appended to the last response, if any.
+    pub fn profile(&self) -> Option<TaskProfile> {
+        let mut trailer = Vec::new();
+        if false == self.host.read_trailer(&mut trailer) {
+            return None;
+        }
+        TaskProfile::decode(&trailer)
+    }
+
+    /// Issues `command` like the host does and runs one iteration of the state
+    /// machine. Returns the status the TEE responded with.
+    pub fn command(&mut self, command: TeeCommand) -> TeeCommand {
+        self.host.set_status(command);
+        let machine = self.machine.take().unwrap();
+        self.machine = Some(run_state_machine(machine));
+        self.status()
+    }
+
+    /// Requests the execution of `task` with `input` in the currently selected
+    /// session. The response can be read via [`Simulator::output`].
+    pub fn call(&mut self, task: TaskId, input: &[u8]) -> TeeCommand {
+        assert!(self.host.write_payload(input));
+        self.host.set_task(task);
+        self.command(TeeCommand::HostSend)
+    }
+
+    /// Opens a new session and selects it for subsequent requests.
+    pub fn open_session(&mut self) -> Option<SessionId> {
+        match self.command(TeeCommand::HostOpenSession) {
+            TeeCommand::TeeSend => Some(self.session()),
+            _ => None,
+        }
+    }
+
+    /// Closes the given session.
+    pub fn close_session(&mut self, session: SessionId) -> TeeCommand {
+        self.set_session(session);
+        self.command(TeeCommand::HostCloseSession)
+    }
+}
+
+impl Default for Simulator {
+    fn default() -> Self {
+        Self::new(DEFAULT_SHARED_MEM_SIZE)
+    }
+}
+
+impl Drop for Simulator {
+    fn drop(&mut self) {
+        // The state machine must not outlive the memory it points to.
+        self.machine = None;
+        drop(unsafe { Box::from_raw(self.memory) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attestation::NONCE_LEN;
+    use crate::seal::SEAL_OVERHEAD;
+    use crate::state_machine::session::{SessionError, DEFAULT_SESSION};
+    use crate::state_machine::{NOT_ARMED, TAMPER_DETECTED};
+    use crate::tasks::module;
+    use core::ptr;
+
+    /// Returns the buffer the attack tasks reported in their output.
+    fn attack_buffer(sim: &mut Simulator) -> *mut u32 {
+        let phys = u64::from_le_bytes(sim.output()[2..10].try_into().unwrap());
+        sim.platform().get_virtual_address(phys) as *mut u32
+    }
+
+    #[test]
+    fn test_initial_state() {
+        let sim = Simulator::default();
+        assert!(matches!(sim.status(), TeeCommand::TeeReady));
+        assert_eq!(sim.task(), TaskId::UNKNOWN);
+        assert_eq!(sim.platform().read_pmcs(), [u64::MAX; COUNTER_NUM]);
+    }
+
+    #[test]
+    fn test_ping() {
+        let mut sim = Simulator::default();
+        assert!(matches!(sim.call(TaskId::PING, &[41]), TeeCommand::TeeSend));
+        assert_eq!(sim.task(), TaskId::PING);
+        assert_eq!(sim.output(), [42]);
+        sim.call(TaskId::PING, &[42]);
+        assert_eq!(sim.output(), [43]);
+        sim.call(TaskId::PING, &[]);
+        assert_eq!(sim.output(), [1]);
+    }
+
+    #[test]
+    fn test_profile() {
+        let mut sim = Simulator::default();
+        sim.call(TaskId::PING, &[41]);
+        let profile = sim.profile().unwrap();
+        assert_eq!(profile.task, TaskId::PING);
+        assert_eq!(profile.cycles, SIM_TSC_STEP);
+        assert_eq!(profile.pmcs, [0; COUNTER_NUM]);
+        assert_eq!(sim.output(), [42]);
+
+        // Failed tasks are profiled too, requests that run no task are not
+        assert!(matches!(sim.call(TaskId::PING, &[1, 2]), TeeCommand::TeeError));
+        assert_eq!(sim.profile(), None);
+        assert!(matches!(sim.call(TaskId::COUNTER_READ, b"unknown"), TeeCommand::TeeError));
+        assert_eq!(sim.profile().unwrap().task, TaskId::COUNTER_READ);
+        sim.open_session().unwrap();
+        assert_eq!(sim.profile(), None);
+
+        // The trailer is left out if it does not fit
+        sim.call(TaskId::SHA256, &[]);
+        assert!(sim.profile().is_some());
+        let mut sim = Simulator::new(HEADER_SIZE + 32);
+        assert!(matches!(sim.call(TaskId::SHA256, &[]), TeeCommand::TeeSend));
+        assert_eq!(sim.output().len(), 32);
+        assert_eq!(sim.profile(), None);
+    }
+
+    #[test]
+    fn test_unknown_task_is_rejected() {
+        let mut sim = Simulator::default();
+        assert!(matches!(sim.call(TaskId(0x1234), &[]), TeeCommand::TeeError));
+        assert_eq!(sim.output(), [TaskError::UnknownTask as u8]);
+    }
+
+    #[test]
+    fn test_input_limits() {
+        let mut sim = Simulator::default();
+        assert!(matches!(sim.call(TaskId::PING, &[1, 2]), TeeCommand::TeeError));
+        assert_eq!(sim.output(), [TaskError::InputTooLarge as u8]);
+
+        sim.host.set_length(DEFAULT_SHARED_MEM_SIZE - HEADER_SIZE);
+        assert!(matches!(sim.command(TeeCommand::HostSend), TeeCommand::TeeError));
+        assert_eq!(sim.output(), [TaskError::InputTooLarge as u8]);
+    }
+
+    #[test]
+    fn test_attack_write_mem() {
+        let mut sim = Simulator::default();
+        assert!(matches!(sim.call(TaskId::ATTACK_WRITE_MEM, &[]), TeeCommand::TeeSend));
+        assert_eq!(sim.task(), TaskId::ATTACK_WRITE_MEM);
+        assert_eq!(sim.output().len(), 10);
+        assert_eq!(sim.output()[0], 1);
+        let buffer = attack_buffer(&mut sim);
+        assert_eq!(unsafe { ptr::read_volatile(buffer) }, 1);
+
+        sim.call(TaskId::ATTACK_WRITE_MEM, &[]);
+        sim.call(TaskId::ATTACK_WRITE_MEM, &[]);
+        for x in 0..1024 {
+            assert_eq!(unsafe { ptr::read_volatile(buffer.add(x)) }, 3);
+        }
+    }
+
+    #[test]
+    fn test_attack_read_and_nop_mem_leave_buffer_untouched() {
+        let mut sim = Simulator::default();
+        sim.call(TaskId::ATTACK_READ_MEM, &[]);
+        let buffer = attack_buffer(&mut sim);
+        assert!(matches!(sim.call(TaskId::ATTACK_READ_MEM, &[]), TeeCommand::TeeSend));
+        assert!(matches!(sim.call(TaskId::ATTACK_NOP_MEM, &[]), TeeCommand::TeeSend));
+        assert_eq!(sim.task(), TaskId::ATTACK_NOP_MEM);
+        assert_eq!(attack_buffer(&mut sim), buffer);
+        for x in 0..1024 {
+            assert_eq!(unsafe { ptr::read_volatile(buffer.add(x)) }, 1);
+        }
+    }
+
+    #[test]
+    fn test_task_in_unknown_session_is_rejected() {
+        let mut sim = Simulator::default();
+        sim.set_session(42);
+        assert!(matches!(sim.call(TaskId::PING, &[1]), TeeCommand::TeeError));
+        assert_eq!(sim.output(), [0x11]);
+        assert!(matches!(sim.close_session(42), TeeCommand::TeeError));
+    }
+
+    #[test]
+    fn test_sessions_own_their_state() {
+        let mut sim = Simulator::default();
+        let a = sim.open_session().unwrap();
+        assert_eq!(sim.session(), a);
+        sim.call(TaskId::ATTACK_WRITE_MEM, &[]);
+        let buffer_a = attack_buffer(&mut sim);
+
+        let b = sim.open_session().unwrap();
+        assert_ne!(a, b);
+        sim.call(TaskId::ATTACK_WRITE_MEM, &[]);
+        let buffer_b = attack_buffer(&mut sim);
+        assert_ne!(buffer_a, buffer_b);
+
+        // Writing in session a only touches the buffer of session a
+        sim.set_session(a);
+        sim.call(TaskId::ATTACK_WRITE_MEM, &[]);
+        assert_eq!(unsafe { ptr::read_volatile(buffer_a) }, 2);
+        assert_eq!(unsafe { ptr::read_volatile(buffer_b) }, 1);
+
+        assert!(matches!(sim.close_session(a), TeeCommand::TeeSend));
+        assert!(matches!(sim.call(TaskId::ATTACK_WRITE_MEM, &[]), TeeCommand::TeeError));
+        sim.set_session(b);
+        assert!(matches!(sim.call(TaskId::ATTACK_WRITE_MEM, &[]), TeeCommand::TeeSend));
+    }
+
+    #[test]
+    fn test_state_does_not_depend_on_payload() {
+        let mut sim = Simulator::default();
+        sim.call(TaskId::ATTACK_WRITE_MEM, &[]);
+        let buffer = attack_buffer(&mut sim);
+        // A host that clears the payload does not cause a new allocation
+        sim.payload().fill(0);
+        sim.call(TaskId::ATTACK_WRITE_MEM, &[]);
+        assert_eq!(unsafe { ptr::read_volatile(buffer) }, 2);
+    }
+
+    #[test]
+    fn test_reset_closes_all_sessions() {
+        let mut sim = Simulator::default();
+        let a = sim.open_session().unwrap();
+        let b = sim.open_session().unwrap();
+        assert!(matches!(sim.command(TeeCommand::HostReset), TeeCommand::TeeSend));
+        sim.set_session(a);
+        assert!(matches!(sim.call(TaskId::PING, &[]), TeeCommand::TeeError));
+        sim.set_session(b);
+        assert!(matches!(sim.call(TaskId::PING, &[]), TeeCommand::TeeError));
+        sim.set_session(0);
+        assert!(matches!(sim.call(TaskId::PING, &[]), TeeCommand::TeeSend));
+    }
+
+    #[test]
+    fn test_tamper_closes_all_sessions() {
+        let mut sim = Simulator::default();
+        let a = sim.open_session().unwrap();
+        sim.platform().set_pmcs([u64::MAX, 1, u64::MAX, u64::MAX]);
+        assert!(matches!(sim.call(TaskId::PING, &[]), TeeCommand::TeeError));
+        assert_eq!(sim.output(), [TAMPER_DETECTED]);
+        assert!(matches!(sim.call(TaskId::PING, &[]), TeeCommand::TeeError));
+        assert_eq!(sim.output(), [u8::from(SessionError::UnknownSession(a))]);
+
+        // The tamper was handled, the default session works again
+        sim.set_session(DEFAULT_SESSION);
+        assert!(matches!(sim.call(TaskId::PING, &[]), TeeCommand::TeeSend));
+    }
+
+    #[test]
+    fn test_refuse_without_tamper_detection() {
+        let mut sim = Simulator::default();
+        sim.platform().set_armed(false);
+        assert!(matches!(sim.call(TaskId::PING, &[]), TeeCommand::TeeError));
+        assert_eq!(sim.output(), [NOT_ARMED]);
+        assert!(sim.open_session().is_none());
+    }
+
+    #[test]
+    fn test_tamper_wipes_boot_secret() {
+        let mut sim = Simulator::default();
+        assert!(matches!(sim.call(TaskId::SEAL, b"data"), TeeCommand::TeeSend));
+        sim.platform().set_pmcs([u64::MAX, 1, u64::MAX, u64::MAX]);
+        assert!(matches!(sim.call(TaskId::PING, &[]), TeeCommand::TeeError));
+        assert_eq!(sim.output(), [TAMPER_DETECTED]);
+
+        // No key can be derived from the wiped boot secret anymore
+        assert!(matches!(sim.call(TaskId::SEAL, b"data"), TeeCommand::TeeError));
+        assert_eq!(sim.output(), [TaskError::ExecutionFailed as u8]);
+        assert!(matches!(sim.call(TaskId::GET_REPORT, &[0; NONCE_LEN]), TeeCommand::TeeError));
+        assert!(matches!(sim.call(TaskId::COUNTER_EXPORT, &[]), TeeCommand::TeeError));
+    }
+
+    #[test]
+    fn test_tamper_rearms_pmcs() {
+        let mut sim = Simulator::default();
+        sim.platform().set_pmcs([u64::MAX, 1, u64::MAX, u64::MAX]);
+        assert!(matches!(sim.call(TaskId::PING, &[]), TeeCommand::TeeError));
+        assert_eq!(sim.platform().read_pmcs(), [u64::MAX; COUNTER_NUM]);
+        assert!(sim.platform().pmc_overflows().is_empty());
+
+        // A PMC that wrapped around to its old value is caught by the overflow
+        sim.platform().set_pmcs([u64::MAX, 0, u64::MAX, u64::MAX]);
+        sim.platform().set_pmcs([u64::MAX; COUNTER_NUM]);
+        assert!(matches!(sim.call(TaskId::PING, &[]), TeeCommand::TeeError));
+        assert_eq!(sim.output(), [TAMPER_DETECTED]);
+        assert!(matches!(sim.call(TaskId::PING, &[]), TeeCommand::TeeSend));
+    }
+
+    #[test]
+    fn test_attack_ipi() {
+        let mut sim = Simulator::default();
+        assert!(matches!(sim.call(TaskId::ATTACK_IPI, &[]), TeeCommand::TeeSend));
+        assert_eq!(sim.task(), TaskId::ATTACK_IPI);
+        assert_eq!(sim.output()[0], 1);
+        let secret = attack_buffer(&mut sim);
+        assert_eq!(unsafe { ptr::read_volatile(secret) }, 0x1337_beef);
+    }
+
+    #[test]
+    fn test_module_task() {
+        let mut sim = Simulator::default();
+        let bytes = module::tests::module(0x100, "module", 0, &[0xc3]);
+        let descriptor = ModuleImage::parse(bytes).unwrap().into_descriptor();
+        assert_eq!(sim.register_task(descriptor), Ok(()));
+        assert_eq!(sim.register_task(descriptor), Err(DuplicateTaskIdError(TaskId(0x100))));
+
+        // The limits of the manifest are enforced before the task runs
+        assert!(matches!(sim.call(TaskId(0x100), &[0; 17]), TeeCommand::TeeError));
+        assert_eq!(sim.output(), [TaskError::InputTooLarge as u8]);
+        assert!(matches!(sim.call(TaskId(0x100), &[0; 16]), TeeCommand::TeeError));
+        assert_eq!(sim.output(), [TaskError::ExecutionFailed as u8]);
+    }
+
+    #[test]
+    fn test_seal_unseal() {
+        let mut sim = Simulator::default();
+        assert!(matches!(sim.call(TaskId::SEAL, b"secret"), TeeCommand::TeeSend));
+        let blob = sim.output();
+        assert_eq!(blob.len(), 6 + SEAL_OVERHEAD);
+
+        // The plaintext stays in the session
+        assert!(matches!(sim.call(TaskId::UNSEAL, &blob), TeeCommand::TeeSend));
+        assert_eq!(sim.output(), 0_u16.to_le_bytes());
+        sim.call(TaskId::UNSEAL, &blob);
+        assert_eq!(sim.output(), 1_u16.to_le_bytes());
+
+        let mut tampered = blob.clone();
+        tampered[SEAL_OVERHEAD] ^= 1;
+        assert!(matches!(sim.call(TaskId::UNSEAL, &tampered), TeeCommand::TeeError));
+        assert_eq!(sim.output(), [TaskError::InvalidInput as u8]);
+    }
+}
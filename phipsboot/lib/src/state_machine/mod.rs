@@ -0,0 +1,292 @@
+pub mod profile;
+pub mod session;
+pub mod task_id;
+pub mod task;
+pub mod task_codec;
+
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use zeroize::Zeroizing;
+
+use crate::platform::{Platform, COUNTER_NUM};
+use crate::secret;
+use crate::pmc_utils::architectural::IA32_PERFEVTSEL_INT;
+use crate::pmc_utils::global::{gp_counter_bit, OverflowStatus};
+use crate::shared_mem_com::{SharedMemCommunicator, TeeCommand};
+use crate::state_machine::profile::TaskProfile;
+use crate::state_machine::session::SessionManager;
+use crate::state_machine::task::{DuplicateTaskIdError, TaskDescriptor, TaskError, TaskRegistry};
+use crate::state_machine::task_id::TaskId;
+
+/// Error code reported to the host if a PMC that raises an interrupt on
+/// overflow moved while the TEE handled requests. All sessions are closed in
+/// that case.
+pub const TAMPER_DETECTED: u8 = 0x20;
+/// Error code reported to the host if a PMC of the tamper detection could not
+/// be armed. The TEE refuses all requests in that case.
+pub const NOT_ARMED: u8 = 0x21;
+
+pub struct StateMachine<S, P: Platform> {
+    communicator: SharedMemCommunicator,
+    platform: Rc<P>,
+    tasks: TaskRegistry,
+    sessions: SessionManager,
+    /// Command received by the last poll.
+    command: TeeCommand,
+    /// PMC values at the last tamper check.
+    pmcs: [u64; COUNTER_NUM],
+    _state: S,
+}
+
+#[derive(Debug, Default)]
+pub struct StateInitialized;
+#[derive(Debug, Default)]
+pub struct StatePolling;
+#[derive(Debug, Default)]
+pub struct StateLocking;
+#[derive(Debug, Default)]
+pub struct StateExecuteApp;
+#[derive(Debug, Default)]
+pub struct StateUnlocking;
+#[derive(Debug, Default)]
+pub struct StateTransmitResult;
+
+impl<P: Platform + 'static> StateMachine<StateInitialized, P> {
+    pub fn new(communicator: SharedMemCommunicator, platform: P) -> Self {
+        communicator.set_status(TeeCommand::TeeReady);
+        communicator.set_task(TaskId::UNKNOWN);
+        log::info!("Init task registry...");
+        let platform = Rc::new(platform);
+        StateMachine {
+            communicator: communicator,
+            platform: platform.clone(),
+            tasks: TaskRegistry::with_builtin_tasks(),
+            sessions: SessionManager::new(platform.clone()),
+            command: TeeCommand::None,
+            pmcs: platform.read_pmcs(),
+            _state: StateInitialized{},
+        }
+    }
+
+    /// Registers a task in addition to the built-in ones, e.g., one loaded
+    /// from a module.
+    pub fn register_task(&mut self, descriptor: TaskDescriptor) -> Result<(), DuplicateTaskIdError> {
+        self.tasks.register(descriptor)
+    }
+}
+
+impl<S, P: Platform> StateMachine<S, P> {
+    /// Returns the platform the state machine runs on.
+    pub fn platform(&self) -> &P {
+        &self.platform
+    }
+
+    /// Moves the machine into the next state.
+    fn transition<T>(self, state: T) -> StateMachine<T, P> {
+        StateMachine {
+            communicator: self.communicator,
+            platform: self.platform,
+            tasks: self.tasks,
+            sessions: self.sessions,
+            command: self.command,
+            pmcs: self.pmcs,
+            _state: state,
+        }
+    }
+
+    /// Writes `payload` and, if given, the trailer with `profile`. The status is
+    /// written last, so that the host never sees a partial response.
+    fn respond(&mut self, payload: &[u8], profile: Option<TaskProfile>, status: TeeCommand) {
+        self.communicator.write_payload(payload);
+        if let Some(profile) = profile {
+            self.communicator.write_trailer(&profile.encode());
+        }
+        self.communicator.set_status(status);
+    }
+
+    /// Reports `code` to the host as the reason why the request failed.
+    fn respond_error(&mut self, code: u8) {
+        self.respond(&[code], None, TeeCommand::TeeError);
+    }
+
+    /// Returns `true` if a PMC that raises an interrupt on overflow overflowed
+    /// or moved since the last check. The PMCs that fired are logged and
+    /// re-armed. All sessions are closed then, so that their secrets are
+    /// zeroed, and all remaining secrets are wiped.
+    ///
+    /// These PMCs count events that must not happen while the TEE is resident
+    /// in the cache. Usually their interrupt fires first, but this check also
+    /// covers CPUs on which it cannot be delivered.
+    fn detect_tamper(&mut self) -> bool {
+        let pmcs = self.platform.read_pmcs();
+        let config = self.platform.pmc_config();
+        let overflows = self.platform.pmc_overflows();
+        let fired = (0..COUNTER_NUM)
+            .filter(|&x| 0 != config[x] & IA32_PERFEVTSEL_INT)
+            .filter(|&x| overflows.counter(x as u8) || self.pmcs[x] != pmcs[x])
+            .fold(0, |mask, x| mask | gp_counter_bit(x as u8));
+        if 0 == fired {
+            return false;
+        }
+        for x in (0..COUNTER_NUM).filter(|&x| 0 != fired & gp_counter_bit(x as u8)) {
+            log::info!("PMC {} with event select {:#x} fired, it moved from {:#x} to {:#x}", x, config[x], self.pmcs[x], pmcs[x]);
+        }
+        log::info!("Closing all sessions");
+        self.platform.rearm_pmcs(OverflowStatus(overflows.0 | fired));
+        self.pmcs = self.platform.read_pmcs();
+        self.sessions.reset();
+        let wiped = secret::wipe_all();
+        log::info!("Wiped {} secrets that outlived their session", wiped);
+        true
+    }
+
+    /// Runs the requested task in the requested session and returns its output.
+    /// Stores the [`TaskProfile`] in `profile` if the task ran.
+    fn execute_task(&mut self, task_id: TaskId, profile: &mut Option<TaskProfile>) -> Result<Zeroizing<Vec<u8>>, u8> {
+        let session_id = self.communicator.get_session();
+        let session = self.sessions.get_mut(session_id)?;
+        let task = session.task(&self.tasks, task_id)?;
+
+        // The task works on a private copy, so that the host cannot change the
+        // input while the task runs. Both buffers are large enough for the
+        // payload area, so that they are not reallocated, which would leave
+        // copies of the data on the heap, and are zeroed when dropped.
+        let mut input = Zeroizing::new(Vec::with_capacity(self.communicator.capacity()));
+        if false == self.communicator.read_payload(&mut input)
+            || input.len() > task.max_input_len()
+        {
+            return Err(TaskError::InputTooLarge.into());
+        }
+        let mut output = Zeroizing::new(Vec::with_capacity(self.communicator.capacity()));
+        let pmcs = self.platform.read_pmcs();
+        let start = self.platform.read_tsc();
+        let result = task.run(&input, &mut output);
+        let cycles = self.platform.read_tsc().wrapping_sub(start);
+        *profile = Some(TaskProfile::new(&self.platform.pmu(), task_id, cycles, pmcs, self.platform.read_pmcs()));
+        result?;
+        if output.len() > task.max_output_len() || output.len() > self.communicator.capacity() {
+            return Err(TaskError::OutputTooLarge.into());
+        }
+        Ok(output)
+    }
+
+    /// Executes the command received by the last poll and writes the response
+    /// status. Refuses it if the tamper detection is not armed.
+    fn execute_command(&mut self) {
+        self.communicator.clear_trailer();
+        if false == self.platform.pmcs_armed() {
+            log::error!("The tamper detection is not armed, refusing the request");
+            self.respond_error(NOT_ARMED);
+            return;
+        }
+        if self.detect_tamper() {
+            self.respond_error(TAMPER_DETECTED);
+            return;
+        }
+        match self.command {
+            TeeCommand::HostSend => {
+                let task_id = self.communicator.get_task();
+                let mut profile = None;
+                match self.execute_task(task_id, &mut profile) {
+                    // The output must not leave if it may have been observed
+                    Ok(_) if self.detect_tamper() => self.respond(&[TAMPER_DETECTED], profile, TeeCommand::TeeError),
+                    Ok(output) => {
+                        self.communicator.set_task(task_id);
+                        self.respond(&output, profile, TeeCommand::TeeSend);
+                    }
+                    Err(code) => {
+                        log::info!("Task {:?} failed with error {:#02x?}", task_id, code);
+                        self.respond(&[code], profile, TeeCommand::TeeError);
+                    }
+                }
+            }
+            TeeCommand::HostOpenSession => match self.sessions.open() {
+                Ok(session_id) => {
+                    log::info!("Opened session {}", session_id);
+                    self.communicator.set_session(session_id);
+                    self.communicator.set_status(TeeCommand::TeeSend);
+                }
+                Err(e) => {
+                    log::info!("Could not open session: {:?}", e);
+                    self.respond_error(e.into());
+                }
+            },
+            TeeCommand::HostCloseSession => {
+                let session_id = self.communicator.get_session();
+                match self.sessions.close(session_id) {
+                    Ok(()) => {
+                        log::info!("Closed session {}", session_id);
+                        self.communicator.set_status(TeeCommand::TeeSend);
+                    }
+                    Err(e) => {
+                        log::info!("Could not close session: {:?}", e);
+                        self.respond_error(e.into());
+                    }
+                }
+            }
+            TeeCommand::HostReset => {
+                log::info!("Reset: closing all sessions");
+                self.sessions.reset();
+                self.communicator.set_status(TeeCommand::TeeSend);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<P: Platform> From<StateMachine<StateInitialized, P>> for StateMachine<StatePolling, P> {
+    fn from(mut m: StateMachine<StateInitialized, P>) -> StateMachine<StatePolling, P> {
+        // info!("Polling...");
+        m.command = m.communicator.poll();
+        // info!("Received command");
+        m.transition(StatePolling{})
+    }
+}
+
+impl<P: Platform> From<StateMachine<StatePolling, P>> for StateMachine<StateLocking, P> {
+    fn from(m: StateMachine<StatePolling, P>) -> StateMachine<StateLocking, P> {
+        // pmc::setup_pmcs();
+        m.transition(StateLocking{})
+    }
+}
+
+impl<P: Platform> From<StateMachine<StateLocking, P>> for StateMachine<StateExecuteApp, P> {
+    fn from(mut m: StateMachine<StateLocking, P>) -> StateMachine<StateExecuteApp, P> {
+        // Execute task, collect results
+        // info!("Execute task with ID {:#02x?}", m.communicator.get_task());
+        m.execute_command();
+        m.transition(StateExecuteApp{})
+    }
+}
+
+impl<P: Platform> From<StateMachine<StateExecuteApp, P>> for StateMachine<StateUnlocking, P> {
+    fn from(m: StateMachine<StateExecuteApp, P>) -> StateMachine<StateUnlocking, P> {
+        // info!("Unlock TEE");
+        // pmc::read_and_print_pmcs();
+        m.transition(StateUnlocking{})
+    }
+}
+
+impl<P: Platform> From<StateMachine<StateUnlocking, P>> for StateMachine<StateTransmitResult, P> {
+    fn from(m: StateMachine<StateUnlocking, P>) -> StateMachine<StateTransmitResult, P> {
+        // Copy results
+        m.transition(StateTransmitResult{})
+    }
+}
+
+impl<P: Platform> From<StateMachine<StateTransmitResult, P>> for StateMachine<StateInitialized, P> {
+    fn from(m: StateMachine<StateTransmitResult, P>) -> StateMachine<StateInitialized, P> {
+        // Change to initialized again; wait for commands
+        m.transition(StateInitialized{})
+    }
+}
+
+pub fn run_state_machine<P: Platform>(machine: StateMachine<StateInitialized, P>) -> StateMachine<StateInitialized, P> {
+    let machine = StateMachine::<StatePolling, P>::from(machine);
+    let machine = StateMachine::<StateLocking, P>::from(machine);
+    let machine = StateMachine::<StateExecuteApp, P>::from(machine);
+    let machine = StateMachine::<StateUnlocking, P>::from(machine);
+    let machine = StateMachine::<StateTransmitResult, P>::from(machine);
+    let machine = StateMachine::<StateInitialized, P>::from(machine);
+    machine
+}
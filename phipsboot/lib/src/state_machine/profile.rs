@@ -0,0 +1,99 @@
+//! Module for [`TaskProfile`].
+//!
+//! The state machine reads the TSC and the PMCs right before and after every
+//! task invocation and appends the differences to the response as a trailer,
+//! see [`crate::shared_mem_com`]:
+//!
+//! ```text
+//! 0         1          2      4        12
+//! | version | counters | task | cycles | PMC 0 | PMC 1 | ...
+//! ```
+//!
+//! `counters` is the number of PMC deltas that follow. All values are
+//! little-endian, the cycles and deltas are `u64`.
+
+use crate::platform::COUNTER_NUM;
+use crate::pmc_utils::pmu::PmuInfo;
+use crate::state_machine::task_id::TaskId;
+
+/// Version of the trailer format described in the [module docs](self).
+pub const PROFILE_VERSION: u8 = 1;
+/// Length of an encoded [`TaskProfile`].
+pub const PROFILE_LEN: usize = 1 + 1 + 2 + 8 + COUNTER_NUM * 8;
+
+/// Cycles and PMC deltas of a single task invocation.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct TaskProfile {
+    pub task: TaskId,
+    /// TSC cycles the task ran.
+    pub cycles: u64,
+    /// Events counted by the PMCs while the task ran.
+    pub pmcs: [u64; COUNTER_NUM],
+}
+
+impl TaskProfile {
+    /// Creates the profile of `task` from the PMC values of `pmu` before and
+    /// after it ran. Deltas are computed modulo the counter width, so that a
+    /// counter that overflowed still yields the right count.
+    pub fn new(pmu: &PmuInfo, task: TaskId, cycles: u64, before: [u64; COUNTER_NUM], after: [u64; COUNTER_NUM]) -> Self {
+        let mask = pmu.counter_mask();
+        let pmcs = core::array::from_fn(|x| after[x].wrapping_sub(before[x]) & mask);
+        Self { task, cycles, pmcs }
+    }
+
+    /// Encodes the profile as described in the [module docs](self).
+    pub fn encode(&self) -> [u8; PROFILE_LEN] {
+        let mut out = [0; PROFILE_LEN];
+        out[0] = PROFILE_VERSION;
+        out[1] = COUNTER_NUM as u8;
+        out[2..4].copy_from_slice(&u16::from(self.task).to_le_bytes());
+        out[4..12].copy_from_slice(&self.cycles.to_le_bytes());
+        for (x, delta) in self.pmcs.iter().enumerate() {
+            out[12 + x * 8..20 + x * 8].copy_from_slice(&delta.to_le_bytes());
+        }
+        out
+    }
+
+    /// Decodes a profile encoded by [`TaskProfile::encode`].
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        if PROFILE_LEN != data.len() || PROFILE_VERSION != data[0] || COUNTER_NUM != usize::from(data[1]) {
+            return None;
+        }
+        let u64_at = |offset: usize| u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap());
+        Some(Self {
+            task: TaskId(u16::from_le_bytes([data[2], data[3]])),
+            cycles: u64_at(4),
+            pmcs: core::array::from_fn(|x| u64_at(12 + x * 8)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::SIM_PMU;
+
+    #[test]
+    fn test_overflowed_counters() {
+        // Armed counters overflow on the first event
+        let armed = (1 << 48) - 1;
+        let profile = TaskProfile::new(&SIM_PMU, TaskId::PING, 10, [armed, 5, u64::MAX, 0], [1, 5, 0, 3]);
+        assert_eq!(profile.pmcs, [2, 0, 1, 3]);
+
+        // The deltas follow the width of the counters
+        let pmu = PmuInfo { gp_width: 40, ..SIM_PMU };
+        let profile = TaskProfile::new(&pmu, TaskId::PING, 10, [(1 << 40) - 1, 0, 0, 0], [0, 0, 0, 0]);
+        assert_eq!(profile.pmcs, [1, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_encode() {
+        let profile = TaskProfile { task: TaskId::SEAL, cycles: 0x1234, pmcs: [1, 2, 3, 4] };
+        let encoded = profile.encode();
+        assert_eq!(encoded[..4], [PROFILE_VERSION, 4, 0x07, 0x00]);
+        assert_eq!(encoded[4..12], 0x1234_u64.to_le_bytes());
+        assert_eq!(encoded[PROFILE_LEN - 8..], 4_u64.to_le_bytes());
+        assert_eq!(TaskProfile::decode(&encoded), Some(profile));
+        assert_eq!(TaskProfile::decode(&encoded[1..]), None);
+    }
+}
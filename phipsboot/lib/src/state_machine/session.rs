@@ -0,0 +1,270 @@
+//! Client sessions of the TEE.
+//!
+//! Every task runs in the context of a [`Session`]. A session owns the task
+//! instances and all state and allocations they create on behalf of a client.
+//! Closing a session drops this state, which zeroes and frees the memory.
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::rc::Rc;
+use core::any::{Any, TypeId};
+use core::cell::RefCell;
+
+use crate::platform::Platform;
+use crate::random::Drbg;
+use crate::secret::SecretBox;
+use crate::state_machine::task::{Task, TaskEnv, TaskError, TaskRegistry};
+use crate::state_machine::task_id::TaskId;
+
+/// ID of a session as transferred in the shared memory.
+pub type SessionId = u16;
+
+/// Session that is used when the host does not open a session explicitly. It
+/// is always open; closing it only wipes its state.
+pub const DEFAULT_SESSION: SessionId = 0;
+
+/// Maximum number of sessions that can be open at the same time, including
+/// [`DEFAULT_SESSION`].
+pub const MAX_SESSIONS: usize = 16;
+
+/// Heap allocation owned by a session. The memory is zeroed and freed when the
+/// buffer is dropped, or when all secrets are wiped.
+pub type SessionBuffer = SecretBox;
+
+/// State that the tasks of a session share, one entry per type.
+#[derive(Debug, Default)]
+pub struct SessionState {
+    entries: BTreeMap<TypeId, Box<dyn Any>>,
+}
+
+impl SessionState {
+    /// Returns the entry of type `T`. It is created with its default value on
+    /// first access.
+    pub fn get<T: Any + Default>(&mut self) -> &mut T {
+        self.entries
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(T::default()))
+            .downcast_mut::<T>()
+            .unwrap()
+    }
+}
+
+/// State of one client session.
+pub struct Session {
+    id: SessionId,
+    env: TaskEnv,
+    /// Task instances, created on first use.
+    tasks: BTreeMap<TaskId, Box<dyn Task>>,
+}
+
+impl Session {
+    fn new(id: SessionId, env: TaskEnv) -> Self {
+        Self {
+            id,
+            env,
+            tasks: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the ID of the session.
+    pub fn id(&self) -> SessionId {
+        self.id
+    }
+
+    /// Returns the environment the tasks of this session run in.
+    pub fn env(&self) -> &TaskEnv {
+        &self.env
+    }
+
+    /// Returns the instance of the given task for this session. The instance is
+    /// created on first use.
+    pub fn task(&mut self, registry: &TaskRegistry, id: TaskId) -> Result<&mut dyn Task, TaskError> {
+        if !self.tasks.contains_key(&id) {
+            let descriptor = registry.get(id).ok_or(TaskError::UnknownTask)?;
+            self.tasks.insert(id, (descriptor.create)(self.env.clone()));
+        }
+        Ok(self.tasks.get_mut(&id).unwrap().as_mut())
+    }
+}
+
+/// Error returned by [`SessionManager`].
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub enum SessionError {
+    /// All [`MAX_SESSIONS`] sessions are in use.
+    Exhausted,
+    /// No session with the given ID is open.
+    UnknownSession(SessionId),
+}
+
+impl From<SessionError> for u8 {
+    /// Returns the error code that is reported to the host.
+    fn from(error: SessionError) -> u8 {
+        match error {
+            SessionError::Exhausted => 0x10,
+            SessionError::UnknownSession(_) => 0x11,
+        }
+    }
+}
+
+/// Keeps track of all open sessions.
+pub struct SessionManager {
+    sessions: BTreeMap<SessionId, Session>,
+    last_id: SessionId,
+    platform: Rc<dyn Platform>,
+    /// Outlives the sessions, so that it is only seeded once.
+    rng: Rc<RefCell<Drbg>>,
+    /// State shared by all sessions, see [`TaskEnv::global_state`].
+    global: Rc<RefCell<SessionState>>,
+}
+
+impl SessionManager {
+    /// Creates a manager in which only the [`DEFAULT_SESSION`] is open.
+    pub fn new(platform: Rc<dyn Platform>) -> Self {
+        let mut manager = Self {
+            sessions: BTreeMap::new(),
+            last_id: DEFAULT_SESSION,
+            platform,
+            rng: Rc::default(),
+            global: Rc::default(),
+        };
+        manager.reset();
+        manager
+    }
+
+    /// Creates a session with empty state.
+    fn new_session(&self, id: SessionId) -> Session {
+        let env = TaskEnv::new(self.platform.clone(), self.rng.clone(), self.global.clone(), Rc::default());
+        Session::new(id, env)
+    }
+
+    /// Opens a new session and returns its ID.
+    pub fn open(&mut self) -> Result<SessionId, SessionError> {
+        if self.sessions.len() >= MAX_SESSIONS {
+            return Err(SessionError::Exhausted);
+        }
+        // IDs are handed out round-robin so that a stale ID of a closed session
+        // is not immediately valid again.
+        let mut id = self.last_id;
+        loop {
+            id = id.wrapping_add(1);
+            if DEFAULT_SESSION != id && !self.sessions.contains_key(&id) {
+                break;
+            }
+        }
+        self.last_id = id;
+        self.sessions.insert(id, self.new_session(id));
+        Ok(id)
+    }
+
+    /// Closes the given session and drops all of its state. Closing the
+    /// [`DEFAULT_SESSION`] only wipes its state.
+    pub fn close(&mut self, id: SessionId) -> Result<(), SessionError> {
+        self.sessions
+            .remove(&id)
+            .ok_or(SessionError::UnknownSession(id))?;
+        if DEFAULT_SESSION == id {
+            self.sessions.insert(id, self.new_session(id));
+        }
+        Ok(())
+    }
+
+    /// Closes all sessions. Only a fresh [`DEFAULT_SESSION`] remains.
+    pub fn reset(&mut self) {
+        self.sessions.clear();
+        self.sessions.insert(DEFAULT_SESSION, self.new_session(DEFAULT_SESSION));
+    }
+
+    /// Returns the open session with the given ID.
+    pub fn get_mut(&mut self, id: SessionId) -> Result<&mut Session, SessionError> {
+        self.sessions
+            .get_mut(&id)
+            .ok_or(SessionError::UnknownSession(id))
+    }
+
+    /// Returns the number of open sessions, including [`DEFAULT_SESSION`].
+    pub fn count(&self) -> usize {
+        self.sessions.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::SimPlatform;
+    use core::ptr;
+
+    fn manager() -> SessionManager {
+        SessionManager::new(Rc::new(SimPlatform::default()))
+    }
+
+    #[test]
+    fn test_default_session_always_open() {
+        let mut manager = manager();
+        assert_eq!(manager.count(), 1);
+        assert!(manager.get_mut(DEFAULT_SESSION).is_ok());
+        assert_eq!(manager.close(DEFAULT_SESSION), Ok(()));
+        assert!(manager.get_mut(DEFAULT_SESSION).is_ok());
+    }
+
+    #[test]
+    fn test_open_close() {
+        let mut manager = manager();
+        let a = manager.open().unwrap();
+        let b = manager.open().unwrap();
+        assert_ne!(a, DEFAULT_SESSION);
+        assert_ne!(a, b);
+        assert_eq!(manager.close(a), Ok(()));
+        assert_eq!(manager.close(a), Err(SessionError::UnknownSession(a)));
+        assert_eq!(manager.get_mut(a).err(), Some(SessionError::UnknownSession(a)));
+        // IDs of closed sessions are not reused right away
+        assert_ne!(manager.open().unwrap(), a);
+    }
+
+    #[test]
+    fn test_exhausted() {
+        let mut manager = manager();
+        for _ in 1..MAX_SESSIONS {
+            manager.open().unwrap();
+        }
+        assert_eq!(manager.open(), Err(SessionError::Exhausted));
+        manager.reset();
+        assert_eq!(manager.count(), 1);
+        assert!(manager.open().is_ok());
+    }
+
+    #[test]
+    fn test_state_is_per_session_and_dropped_on_close() {
+        let mut manager = manager();
+        let a = manager.open().unwrap();
+        let b = manager.open().unwrap();
+        *manager.get_mut(a).unwrap().env().state::<u32>() = 42;
+        assert_eq!(*manager.get_mut(a).unwrap().env().state::<u32>(), 42);
+        assert_eq!(*manager.get_mut(b).unwrap().env().state::<u32>(), 0);
+
+        manager.close(a).unwrap();
+        let a = manager.open().unwrap();
+        assert_eq!(*manager.get_mut(a).unwrap().env().state::<u32>(), 0);
+    }
+
+    #[test]
+    fn test_task_instances() {
+        let registry = TaskRegistry::with_builtin_tasks();
+        let mut manager = manager();
+        let session = manager.get_mut(DEFAULT_SESSION).unwrap();
+        assert_eq!(session.task(&registry, TaskId::PING).unwrap().id(), TaskId::PING);
+        assert_eq!(
+            session.task(&registry, TaskId::UNKNOWN).err(),
+            Some(TaskError::UnknownTask)
+        );
+    }
+
+    #[test]
+    fn test_buffer_wipe() {
+        let mut buffer = SessionBuffer::new(64, 8);
+        assert_eq!(buffer.len(), 64);
+        unsafe { ptr::write_bytes(buffer.as_ptr(), 0xab, buffer.len()) };
+        buffer.wipe();
+        let content = unsafe { core::slice::from_raw_parts(buffer.as_ptr(), buffer.len()) };
+        assert!(content.iter().all(|b| 0 == *b));
+    }
+}
@@ -0,0 +1,316 @@
+//! The [`Task`] abstraction and the [`TaskRegistry`].
+//!
+//! A task only transforms an input into an output. Everything around it, such
+//! as copying the input out of the shared memory, enforcing the size limits and
+//! reporting the status to the host, is done by the state machine.
+//!
+//! Stateless tasks are declared with [`tee_task`] on a function. Tasks that
+//! keep state implement [`Task`] and are listed in
+//! [`BUILTIN_TASKS`](crate::tasks::BUILTIN_TASKS).
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::cell::{RefCell, RefMut};
+use core::{fmt, mem, ptr, slice};
+
+use crate::platform::Platform;
+use crate::random::Drbg;
+use crate::state_machine::session::SessionState;
+use crate::state_machine::task_id::TaskId;
+
+pub use macros::tee_task;
+
+/// Items the code generated by [`tee_task`] refers to.
+#[doc(hidden)]
+pub mod __private {
+    pub use alloc::boxed::Box;
+    pub use alloc::vec::Vec;
+}
+
+/// Error of a task invocation. The discriminant is reported to the host.
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub enum TaskError {
+    /// No task with the requested ID is registered.
+    UnknownTask = 0x01,
+    /// The input exceeds the limit of the task or the shared memory.
+    InputTooLarge = 0x02,
+    /// The output exceeds the limit of the task or the shared memory.
+    OutputTooLarge = 0x03,
+    /// The input could not be decoded.
+    InvalidInput = 0x04,
+    /// The task could not be executed or aborted.
+    ExecutionFailed = 0x05,
+}
+
+impl From<TaskError> for u8 {
+    fn from(error: TaskError) -> u8 {
+        error as u8
+    }
+}
+
+/// A unit of work the host can request.
+///
+/// An instance is created per session, so all state a task keeps in `self`
+/// belongs to that session and is dropped when the session is closed.
+pub trait Task {
+    /// Returns the ID of the task.
+    fn id(&self) -> TaskId;
+
+    /// Returns a human-readable name of the task.
+    fn name(&self) -> &'static str;
+
+    /// Returns the maximum number of input bytes the task accepts.
+    fn max_input_len(&self) -> usize;
+
+    /// Returns the maximum number of output bytes the task produces.
+    fn max_output_len(&self) -> usize;
+
+    /// Executes the task. The input is a private copy of the request payload.
+    /// The output is written to the host when `Ok` is returned.
+    fn run(&mut self, input: &[u8], out: &mut Vec<u8>) -> Result<(), TaskError>;
+}
+
+/// Everything a task can access besides its input and output.
+#[derive(Clone)]
+pub struct TaskEnv {
+    platform: Rc<dyn Platform>,
+    rng: Rc<RefCell<Drbg>>,
+    global: Rc<RefCell<SessionState>>,
+    state: Rc<RefCell<SessionState>>,
+}
+
+impl TaskEnv {
+    pub(crate) fn new(
+        platform: Rc<dyn Platform>,
+        rng: Rc<RefCell<Drbg>>,
+        global: Rc<RefCell<SessionState>>,
+        state: Rc<RefCell<SessionState>>,
+    ) -> Self {
+        Self { platform, rng, global, state }
+    }
+
+    /// Returns the platform the TEE runs on.
+    pub fn platform(&self) -> &dyn Platform {
+        &*self.platform
+    }
+
+    /// Fills `buf` with random bytes from the [`Drbg`] of the TEE, which is
+    /// shared by all sessions.
+    pub fn fill_random(&self, buf: &mut [u8]) -> Result<(), TaskError> {
+        self.rng.borrow_mut().fill(&*self.platform, buf).map_err(|e| {
+            log::info!("No entropy available: {:?}", e);
+            TaskError::ExecutionFailed
+        })
+    }
+
+    /// Returns the state of type `T` of the session the task runs in. The state
+    /// is shared by all tasks of the session that use the same type.
+    pub fn state<T: Any + Default>(&self) -> RefMut<'_, T> {
+        RefMut::map(self.state.borrow_mut(), |state| state.get::<T>())
+    }
+
+    /// Returns the state of type `T` that is shared by all sessions. Unlike
+    /// [`TaskEnv::state`], it survives when sessions are closed.
+    pub fn global_state<T: Any + Default>(&self) -> RefMut<'_, T> {
+        RefMut::map(self.global.borrow_mut(), |state| state.get::<T>())
+    }
+}
+
+/// Creates a new instance of a task for a session.
+pub type TaskFactory = dyn Fn(TaskEnv) -> Box<dyn Task> + Sync;
+
+/// Static description of a task that is used to instantiate it.
+#[derive(Copy, Clone)]
+pub struct TaskDescriptor {
+    pub id: TaskId,
+    pub name: &'static str,
+    pub create: &'static TaskFactory,
+}
+
+impl fmt::Debug for TaskDescriptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TaskDescriptor")
+            .field("id", &self.id)
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Claims the ID of the built-in task with the [`TaskId`] constant `$name`.
+/// [`tee_task`] does so for tasks with `id = TaskId::$name`, the other
+/// built-in tasks use this macro. A second claim of the same ID fails to
+/// compile or to link.
+#[macro_export]
+macro_rules! claim_task_id {
+    ($name:ident) => {
+        const _: () = {
+            #[used]
+            #[export_name = concat!("__tee_task_id_", stringify!($name))]
+            static ID_CLAIM: u8 = 0;
+        };
+    };
+}
+
+/// Returns the tasks declared with [`tee_task`] in the whole binary.
+pub fn linked_tasks() -> &'static [TaskDescriptor] {
+    // Defined by the linker for the `tee_tasks` section. For the firmware, the
+    // linker script places the section in the RO segment.
+    extern "Rust" {
+        #[link_name = "__start_tee_tasks"]
+        static TASKS_START: TaskDescriptor;
+        #[link_name = "__stop_tee_tasks"]
+        static TASKS_END: TaskDescriptor;
+    }
+    unsafe {
+        let start = ptr::addr_of!(TASKS_START);
+        let end = ptr::addr_of!(TASKS_END);
+        let len = (end as usize - start as usize) / mem::size_of::<TaskDescriptor>();
+        slice::from_raw_parts(start, len)
+    }
+}
+
+/// Error returned by [`TaskRegistry::register`].
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct DuplicateTaskIdError(pub TaskId);
+
+/// All tasks the host can request.
+#[derive(Debug, Default)]
+pub struct TaskRegistry {
+    tasks: BTreeMap<TaskId, TaskDescriptor>,
+}
+
+impl TaskRegistry {
+    /// Creates a registry that contains the tasks of [`crate::tasks`] and all
+    /// [`linked_tasks`].
+    pub fn with_builtin_tasks() -> Self {
+        Self::with_tasks(crate::tasks::BUILTIN_TASKS.iter().chain(linked_tasks()))
+    }
+
+    /// Creates a registry that contains `descriptors`. If several of them
+    /// share an ID, the first one is kept and the others are logged and
+    /// skipped.
+    pub fn with_tasks<'a>(descriptors: impl IntoIterator<Item = &'a TaskDescriptor>) -> Self {
+        let mut registry = Self::default();
+        for descriptor in descriptors {
+            if let Err(DuplicateTaskIdError(id)) = registry.register(*descriptor) {
+                log::error!("Skipping task {:?}, its ID {:?} is already taken", descriptor.name, id);
+            }
+        }
+        registry
+    }
+
+    /// Adds a task to the registry.
+    pub fn register(&mut self, descriptor: TaskDescriptor) -> Result<(), DuplicateTaskIdError> {
+        if self.tasks.contains_key(&descriptor.id) {
+            return Err(DuplicateTaskIdError(descriptor.id));
+        }
+        self.tasks.insert(descriptor.id, descriptor);
+        Ok(())
+    }
+
+    /// Returns the task with the given ID.
+    pub fn get(&self, id: TaskId) -> Option<&TaskDescriptor> {
+        self.tasks.get(&id)
+    }
+
+    /// Returns all registered tasks, ordered by their ID.
+    pub fn iter(&self) -> impl Iterator<Item = &TaskDescriptor> {
+        self.tasks.values()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::SimPlatform;
+
+    #[tee_task(id = 0x7f00, name = "test_add")]
+    fn add(a: u16, b: u16) -> u16 {
+        a.wrapping_add(b)
+    }
+
+    #[tee_task(id = 0x7f01, name = "test_checked_sub", max_input = 3)]
+    fn checked_sub(env: &TaskEnv, a: u8, b: Option<u8>) -> Result<[u8; 1], TaskError> {
+        *env.state::<u32>() += 1;
+        a.checked_sub(b.unwrap_or(1))
+            .map(|x| [x])
+            .ok_or(TaskError::InvalidInput)
+    }
+
+    fn create(registry: &TaskRegistry, id: TaskId) -> Box<dyn Task> {
+        let env = TaskEnv::new(Rc::new(SimPlatform::default()), Rc::default(), Rc::default(), Rc::default());
+        (registry.get(id).unwrap().create)(env)
+    }
+
+    fn run(task: &mut dyn Task, input: &[u8]) -> Result<Vec<u8>, TaskError> {
+        let mut out = Vec::new();
+        task.run(input, &mut out).map(|()| out)
+    }
+
+    #[test]
+    fn test_builtin_tasks() {
+        let registry = TaskRegistry::with_builtin_tasks();
+        assert_eq!(registry.get(TaskId::PING).unwrap().name, "ping");
+        assert!(registry.get(TaskId::UNKNOWN).is_none());
+        for descriptor in registry.iter() {
+            let task = create(&registry, descriptor.id);
+            assert_eq!(task.id(), descriptor.id);
+            assert_eq!(task.name(), descriptor.name);
+        }
+    }
+
+    #[test]
+    fn test_duplicate_id() {
+        let mut registry = TaskRegistry::with_builtin_tasks();
+        let mut descriptor = *registry.get(TaskId::PING).unwrap();
+        assert_eq!(
+            registry.register(descriptor),
+            Err(DuplicateTaskIdError(TaskId::PING))
+        );
+        descriptor.id = TaskId(0x1234);
+        assert_eq!(registry.register(descriptor), Ok(()));
+
+        // Duplicates among the linked tasks do not abort the boot
+        let mut duplicate = descriptor;
+        duplicate.name = "duplicate";
+        let registry = TaskRegistry::with_tasks(&[descriptor, duplicate]);
+        assert_eq!(registry.iter().count(), 1);
+        assert_eq!(registry.get(TaskId(0x1234)).unwrap().name, descriptor.name);
+    }
+
+    #[test]
+    fn test_linked_tasks() {
+        let registry = TaskRegistry::with_builtin_tasks();
+        for id in [TaskId::PING, TaskId(0x7f00), TaskId(0x7f01)] {
+            assert!(linked_tasks().iter().any(|descriptor| id == descriptor.id));
+            assert!(registry.get(id).is_some());
+        }
+        assert_eq!(registry.get(TaskId(0x7f00)).unwrap().name, "test_add");
+    }
+
+    #[test]
+    fn test_macro_decodes_arguments() {
+        let registry = TaskRegistry::with_builtin_tasks();
+        let mut task = create(&registry, TaskId(0x7f00));
+        assert_eq!(task.max_input_len(), 4);
+        assert_eq!(task.max_output_len(), 2);
+        assert_eq!(run(&mut *task, &[0x01, 0x01, 0x02, 0x00]), Ok([0x03, 0x01].to_vec()));
+        assert_eq!(run(&mut *task, &[0x01, 0x01, 0x02]), Err(TaskError::InvalidInput));
+        assert_eq!(run(&mut *task, &[0; 5]), Err(TaskError::InvalidInput));
+    }
+
+    #[test]
+    fn test_macro_env_and_result() {
+        let registry = TaskRegistry::with_builtin_tasks();
+        let mut task = create(&registry, TaskId(0x7f01));
+        assert_eq!(task.max_input_len(), 3);
+        assert_eq!(task.max_output_len(), 1);
+        assert_eq!(run(&mut *task, &[5, 2]), Ok([3].to_vec()));
+        assert_eq!(run(&mut *task, &[5]), Ok([4].to_vec()));
+        assert_eq!(run(&mut *task, &[0]), Err(TaskError::InvalidInput));
+    }
+}
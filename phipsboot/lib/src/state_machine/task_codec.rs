@@ -0,0 +1,170 @@
+//! Decoding of task arguments and encoding of task results.
+//!
+//! Tasks declared with [`tee_task`](crate::state_machine::task::tee_task)
+//! receive their arguments decoded from the input in declaration order and
+//! have their result encoded into the output. Integers are little-endian.
+
+use alloc::vec::Vec;
+
+use crate::state_machine::task::TaskError;
+
+/// Value that can be decoded from the input of a task.
+pub trait TaskInput: Sized {
+    /// Maximum number of bytes the value occupies in the input.
+    const MAX_LEN: usize;
+
+    /// Decodes the value from the front of `input` and advances `input` past
+    /// it.
+    fn decode(input: &mut &[u8]) -> Result<Self, TaskError>;
+}
+
+/// Value that can be encoded into the output of a task.
+pub trait TaskOutput {
+    /// Maximum number of bytes the value occupies in the output.
+    const MAX_LEN: usize;
+
+    /// Appends the encoded value to `out`.
+    fn encode(self, out: &mut Vec<u8>);
+}
+
+/// Removes the first `N` bytes from `input`.
+fn take<const N: usize>(input: &mut &[u8]) -> Result<[u8; N], TaskError> {
+    if input.len() < N {
+        return Err(TaskError::InvalidInput);
+    }
+    let (head, tail) = input.split_at(N);
+    *input = tail;
+    Ok(head.try_into().unwrap())
+}
+
+macro_rules! impl_int_codec {
+    ($($t:ty),*) => {
+        $(
+            impl TaskInput for $t {
+                const MAX_LEN: usize = core::mem::size_of::<$t>();
+
+                fn decode(input: &mut &[u8]) -> Result<Self, TaskError> {
+                    take(input).map(<$t>::from_le_bytes)
+                }
+            }
+
+            impl TaskOutput for $t {
+                const MAX_LEN: usize = core::mem::size_of::<$t>();
+
+                fn encode(self, out: &mut Vec<u8>) {
+                    out.extend_from_slice(&self.to_le_bytes());
+                }
+            }
+        )*
+    };
+}
+
+impl_int_codec!(u8, u16, u32, u64, i8, i16, i32, i64);
+
+impl TaskInput for bool {
+    const MAX_LEN: usize = 1;
+
+    fn decode(input: &mut &[u8]) -> Result<Self, TaskError> {
+        match take::<1>(input)? {
+            [0] => Ok(false),
+            [1] => Ok(true),
+            _ => Err(TaskError::InvalidInput),
+        }
+    }
+}
+
+impl TaskOutput for bool {
+    const MAX_LEN: usize = 1;
+
+    fn encode(self, out: &mut Vec<u8>) {
+        out.push(self as u8);
+    }
+}
+
+impl<const N: usize> TaskInput for [u8; N] {
+    const MAX_LEN: usize = N;
+
+    fn decode(input: &mut &[u8]) -> Result<Self, TaskError> {
+        take(input)
+    }
+}
+
+impl<const N: usize> TaskOutput for [u8; N] {
+    const MAX_LEN: usize = N;
+
+    fn encode(self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self);
+    }
+}
+
+/// Takes all remaining bytes. Therefore, it can only be the last argument.
+impl TaskInput for Vec<u8> {
+    const MAX_LEN: usize = usize::MAX;
+
+    fn decode(input: &mut &[u8]) -> Result<Self, TaskError> {
+        let value = input.to_vec();
+        *input = &[];
+        Ok(value)
+    }
+}
+
+impl TaskOutput for Vec<u8> {
+    const MAX_LEN: usize = usize::MAX;
+
+    fn encode(self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self);
+    }
+}
+
+/// `None` if the input is exhausted. Therefore, it can only be the last
+/// argument.
+impl<T: TaskInput> TaskInput for Option<T> {
+    const MAX_LEN: usize = T::MAX_LEN;
+
+    fn decode(input: &mut &[u8]) -> Result<Self, TaskError> {
+        if input.is_empty() {
+            Ok(None)
+        } else {
+            T::decode(input).map(Some)
+        }
+    }
+}
+
+impl TaskOutput for () {
+    const MAX_LEN: usize = 0;
+
+    fn encode(self, _out: &mut Vec<u8>) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_sequence() {
+        let mut input: &[u8] = &[0x34, 0x12, 1, 0xaa, 0xbb, 0xcc];
+        assert_eq!(u16::decode(&mut input), Ok(0x1234));
+        assert_eq!(bool::decode(&mut input), Ok(true));
+        assert_eq!(<[u8; 2]>::decode(&mut input), Ok([0xaa, 0xbb]));
+        assert_eq!(u32::decode(&mut input), Err(TaskError::InvalidInput));
+        assert_eq!(Vec::<u8>::decode(&mut input), Ok([0xcc].to_vec()));
+        assert!(input.is_empty());
+        assert_eq!(Option::<u8>::decode(&mut input), Ok(None));
+    }
+
+    #[test]
+    fn test_invalid_bool() {
+        let mut input: &[u8] = &[2];
+        assert_eq!(bool::decode(&mut input), Err(TaskError::InvalidInput));
+    }
+
+    #[test]
+    fn test_encode() {
+        let mut out = Vec::new();
+        0x1234_u16.encode(&mut out);
+        true.encode(&mut out);
+        [7_u8; 2].encode(&mut out);
+        ().encode(&mut out);
+        assert_eq!(out, [0x34, 0x12, 1, 7, 7]);
+    }
+}
@@ -0,0 +1,132 @@
+//! Module for [`TaskId`].
+
+/// ID of a task as transferred in the shared memory.
+///
+/// IDs are not a closed set: every task that is registered in the
+/// [`TaskRegistry`](crate::state_machine::task::TaskRegistry) brings its own
+/// ID. The constants below are the IDs of the built-in tasks.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug)]
+pub struct TaskId(pub u16);
+
+impl TaskId {
+    pub const PING: TaskId = TaskId(0x01);
+    pub const ATTACK_READ_MEM: TaskId = TaskId(0x02);
+    pub const ATTACK_WRITE_MEM: TaskId = TaskId(0x03);
+    pub const ATTACK_NOP_MEM: TaskId = TaskId(0x04);
+    pub const ATTACK_IPI: TaskId = TaskId(0x05);
+    pub const WASM: TaskId = TaskId(0x06);
+    pub const SEAL: TaskId = TaskId(0x07);
+    pub const UNSEAL: TaskId = TaskId(0x08);
+    pub const SHA256: TaskId = TaskId(0x09);
+    pub const SHA512: TaskId = TaskId(0x0a);
+    pub const HMAC_SHA256: TaskId = TaskId(0x0b);
+    pub const GENERATE_KEY: TaskId = TaskId(0x0c);
+    pub const ED25519_GENERATE: TaskId = TaskId(0x0d);
+    pub const ED25519_PUBLIC_KEY: TaskId = TaskId(0x0e);
+    pub const ED25519_SIGN: TaskId = TaskId(0x0f);
+    pub const DELETE_SECRET: TaskId = TaskId(0x10);
+    pub const GET_RANDOM: TaskId = TaskId(0x11);
+    pub const GET_REPORT: TaskId = TaskId(0x12);
+    pub const KV_PUT: TaskId = TaskId(0x13);
+    pub const KV_GET: TaskId = TaskId(0x14);
+    pub const KV_DELETE: TaskId = TaskId(0x15);
+    pub const KV_LIST: TaskId = TaskId(0x16);
+    pub const COUNTER_CREATE: TaskId = TaskId(0x17);
+    pub const COUNTER_INCREMENT: TaskId = TaskId(0x18);
+    pub const COUNTER_READ: TaskId = TaskId(0x19);
+    pub const COUNTER_EXPORT: TaskId = TaskId(0x1a);
+    pub const COUNTER_IMPORT: TaskId = TaskId(0x1b);
+    pub const BENCH: TaskId = TaskId(0x1c);
+    pub const ATTACK_EVICT: TaskId = TaskId(0x1d);
+    pub const ATTACK_FLUSH: TaskId = TaskId(0x1e);
+    pub const ATTACK_PRIME_PROBE: TaskId = TaskId(0x1f);
+    pub const ATTACK_CONTENTION: TaskId = TaskId(0x20);
+    /// No task. Written by the TEE when it is ready for the first request.
+    pub const UNKNOWN: TaskId = TaskId(0xffff);
+
+    /// IDs of all built-in tasks.
+    pub const BUILTIN: &'static [TaskId] = &[
+        TaskId::PING,
+        TaskId::ATTACK_READ_MEM,
+        TaskId::ATTACK_WRITE_MEM,
+        TaskId::ATTACK_NOP_MEM,
+        TaskId::ATTACK_IPI,
+        TaskId::WASM,
+        TaskId::SEAL,
+        TaskId::UNSEAL,
+        TaskId::SHA256,
+        TaskId::SHA512,
+        TaskId::HMAC_SHA256,
+        TaskId::GENERATE_KEY,
+        TaskId::ED25519_GENERATE,
+        TaskId::ED25519_PUBLIC_KEY,
+        TaskId::ED25519_SIGN,
+        TaskId::DELETE_SECRET,
+        TaskId::GET_RANDOM,
+        TaskId::GET_REPORT,
+        TaskId::KV_PUT,
+        TaskId::KV_GET,
+        TaskId::KV_DELETE,
+        TaskId::KV_LIST,
+        TaskId::COUNTER_CREATE,
+        TaskId::COUNTER_INCREMENT,
+        TaskId::COUNTER_READ,
+        TaskId::COUNTER_EXPORT,
+        TaskId::COUNTER_IMPORT,
+        TaskId::BENCH,
+        TaskId::ATTACK_EVICT,
+        TaskId::ATTACK_FLUSH,
+        TaskId::ATTACK_PRIME_PROBE,
+        TaskId::ATTACK_CONTENTION,
+    ];
+
+    /// Returns `true` if this is the ID of a built-in task.
+    pub const fn is_builtin(self) -> bool {
+        let mut x = 0;
+        while x < Self::BUILTIN.len() {
+            if Self::BUILTIN[x].0 == self.0 {
+                return true;
+            }
+            x += 1;
+        }
+        false
+    }
+}
+
+// The built-in tasks have distinct IDs
+const _: () = {
+    let mut x = 0;
+    while x < TaskId::BUILTIN.len() {
+        let mut y = x + 1;
+        while y < TaskId::BUILTIN.len() {
+            assert!(TaskId::BUILTIN[x].0 != TaskId::BUILTIN[y].0, "two built-in tasks share an ID");
+            y += 1;
+        }
+        x += 1;
+    }
+};
+
+impl From<u16> for TaskId {
+    fn from(raw_task: u16) -> Self {
+        TaskId(raw_task)
+    }
+}
+
+impl From<TaskId> for u16 {
+    fn from(task: TaskId) -> Self {
+        task.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_builtin() {
+        assert!(TaskId::SHA256.is_builtin());
+        assert!(TaskId::ATTACK_CONTENTION.is_builtin());
+        assert!(false == TaskId::UNKNOWN.is_builtin());
+        assert!(false == TaskId(0x7f00).is_builtin());
+    }
+}
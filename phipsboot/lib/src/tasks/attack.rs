@@ -0,0 +1,540 @@
+//! Tasks that support simulated attacks against the TEE.
+//!
+//! The tasks place data in enclave memory and tell the host its physical
+//! address, so that the host can try to access it from another core.
+//!
+//! All tasks respond with the same layout:
+//!
+//! ```text
+//! 0             1       2                  10
+//! | initialized | moved | physical address |
+//! ```
+//!
+//! The [`AttackCache`] tasks report in `moved` which of the tamper-detection
+//! PMCs moved while the TEE accessed its data, one bit per PMC. The attacks
+//! have a [`PmcSignature`] that tells which events are expected to be
+//! counted, so that the tamper detection can be evaluated. These tasks append
+//! a byte with the [verdict](SIGNATURE_EVALUATED) of the signature, and
+//! `attack_prime_probe` the number of slow lines as `u32` behind it. All other
+//! tasks leave `moved` zero.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::arch::x86_64::{_mm_clflush, _mm_lfence, _mm_mfence, _rdtsc};
+use core::ptr;
+use log::info;
+
+use crate::platform::COUNTER_NUM;
+use crate::pmc_utils::architectural::{
+    IA32_PERFEVTSEL_EN, IA32_PERFEVTSEL_INT, IA32_PERFEVTSEL_OS, IA32_PERFEVTSEL_USR,
+};
+use crate::pmc_utils::events::Event;
+use crate::pmc_utils::uarch::Microarchitecture;
+use crate::secret::Secret;
+use crate::state_machine::session::SessionBuffer;
+use crate::state_machine::task::{Task, TaskDescriptor, TaskEnv, TaskError};
+use crate::state_machine::task_id::TaskId;
+
+/// Size of the response of all attack tasks.
+const OUTPUT_LEN: usize = 10;
+/// Size of a cache line.
+const CACHE_LINE: usize = 64;
+/// Bits of an event select register that are not part of the event.
+const FLAGS_MASK: u64 = IA32_PERFEVTSEL_EN | IA32_PERFEVTSEL_INT | IA32_PERFEVTSEL_OS | IA32_PERFEVTSEL_USR;
+
+/// Verdict bit: the signature was evaluated. Not set on the first invocation
+/// and on CPUs without event table.
+pub const SIGNATURE_EVALUATED: u8 = 0x1;
+/// Verdict bit: the moved PMCs match the signature.
+pub const SIGNATURE_MATCHED: u8 = 0x1 << 1;
+/// Verdict bit: the attack is expected to trigger the tamper detection.
+pub const SIGNATURE_DETECTED: u8 = 0x1 << 2;
+
+/// Writes the response with the given moved PMCs and physical address to
+/// `out`.
+fn write_output(out: &mut Vec<u8>, moved: u8, phys_addr: u64) {
+    // First byte denotes that the data was initialized
+    out.extend_from_slice(&[1, moved]);
+    out.extend_from_slice(&phys_addr.to_le_bytes());
+}
+
+/// Events an attack is expected to cause while the TEE accesses its data.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct PmcSignature {
+    /// Events that must be counted.
+    pub counted: &'static [Event],
+    /// Events that must not be counted.
+    pub silent: &'static [Event],
+}
+
+impl PmcSignature {
+    /// Returns the bitmask of the enabled PMCs in `config` that count one of
+    /// `events` in their encoding on `uarch`.
+    fn counters(uarch: Microarchitecture, config: &[u64; COUNTER_NUM], events: &[Event]) -> u8 {
+        let counts = |x: usize| {
+            events.iter().any(|&event| uarch.encode(event) == Ok(config[x] & !FLAGS_MASK))
+        };
+        (0..COUNTER_NUM)
+            .filter(|&x| 0 != config[x] & IA32_PERFEVTSEL_EN && counts(x))
+            .fold(0, |mask, x| mask | 1 << x)
+    }
+
+    /// Returns whether `moved`, the bitmask of the PMCs that moved, matches the
+    /// signature for PMCs programmed with `config` on `uarch`. Events no PMC
+    /// counts are ignored.
+    pub fn matches(&self, uarch: Microarchitecture, config: &[u64; COUNTER_NUM], moved: u8) -> bool {
+        let counted = Self::counters(uarch, config, self.counted);
+        counted == moved & counted && 0 == moved & Self::counters(uarch, config, self.silent)
+    }
+
+    /// Returns whether the tamper detection is expected to fire, i.e., whether
+    /// a counted event is counted by a PMC that raises an interrupt.
+    pub fn is_detected(&self, uarch: Microarchitecture, config: &[u64; COUNTER_NUM]) -> bool {
+        let interrupting = (0..COUNTER_NUM)
+            .filter(|&x| 0 != config[x] & IA32_PERFEVTSEL_INT)
+            .fold(0, |mask, x| mask | 1 << x);
+        0 != Self::counters(uarch, config, self.counted) & interrupting
+    }
+
+    /// Returns the verdict byte of the response for the PMCs in `moved`.
+    pub fn verdict(&self, uarch: Microarchitecture, config: &[u64; COUNTER_NUM], moved: u8) -> u8 {
+        let mut verdict = SIGNATURE_EVALUATED;
+        if self.matches(uarch, config, moved) {
+            verdict |= SIGNATURE_MATCHED;
+        }
+        if self.is_detected(uarch, config) {
+            verdict |= SIGNATURE_DETECTED;
+        }
+        verdict
+    }
+}
+
+/// Returns the bitmask of the PMCs that differ between `before` and `after`.
+fn moved(before: [u64; COUNTER_NUM], after: [u64; COUNTER_NUM]) -> u8 {
+    (0..COUNTER_NUM)
+        .filter(|&x| before[x] != after[x])
+        .fold(0, |mask, x| mask | 1 << x)
+}
+
+/// What [`AttackMem`] does with its buffer.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum AttackMemMode {
+    /// Read every element.
+    Read,
+    /// Increment every element.
+    Write,
+    /// Touch nothing.
+    Nop,
+}
+
+/// Buffer the [`AttackMem`] tasks operate on. It is shared between all modes
+/// and lives as long as the session.
+#[derive(Debug, Default)]
+struct AttackMemState {
+    data: Option<SessionBuffer>,
+}
+
+/// Allocates a 4 KiB buffer on first invocation and accesses it according to
+/// its [`AttackMemMode`] on all further invocations.
+pub struct AttackMem {
+    env: TaskEnv,
+    mode: AttackMemMode,
+}
+
+impl AttackMem {
+    /// Size of the buffer. We want to fill 4 KiB of memory.
+    const NUM_ELEMENTS: usize = 0x1 << 12;
+
+    pub const READ_DESCRIPTOR: TaskDescriptor = TaskDescriptor {
+        id: TaskId::ATTACK_READ_MEM,
+        name: "attack_read_mem",
+        create: &|env| Box::new(AttackMem::new(env, AttackMemMode::Read)),
+    };
+
+    pub const WRITE_DESCRIPTOR: TaskDescriptor = TaskDescriptor {
+        id: TaskId::ATTACK_WRITE_MEM,
+        name: "attack_write_mem",
+        create: &|env| Box::new(AttackMem::new(env, AttackMemMode::Write)),
+    };
+
+    pub const NOP_DESCRIPTOR: TaskDescriptor = TaskDescriptor {
+        id: TaskId::ATTACK_NOP_MEM,
+        name: "attack_nop_mem",
+        create: &|env| Box::new(AttackMem::new(env, AttackMemMode::Nop)),
+    };
+
+    pub fn new(env: TaskEnv, mode: AttackMemMode) -> Self {
+        Self { env, mode }
+    }
+
+    fn descriptor(&self) -> &'static TaskDescriptor {
+        match self.mode {
+            AttackMemMode::Read => &Self::READ_DESCRIPTOR,
+            AttackMemMode::Write => &Self::WRITE_DESCRIPTOR,
+            AttackMemMode::Nop => &Self::NOP_DESCRIPTOR,
+        }
+    }
+}
+
+crate::claim_task_id!(ATTACK_READ_MEM);
+crate::claim_task_id!(ATTACK_WRITE_MEM);
+crate::claim_task_id!(ATTACK_NOP_MEM);
+
+impl Task for AttackMem {
+    fn id(&self) -> TaskId {
+        self.descriptor().id
+    }
+
+    fn name(&self) -> &'static str {
+        self.descriptor().name
+    }
+
+    fn max_input_len(&self) -> usize {
+        0
+    }
+
+    fn max_output_len(&self) -> usize {
+        OUTPUT_LEN
+    }
+
+    fn run(&mut self, _input: &[u8], out: &mut Vec<u8>) -> Result<(), TaskError> {
+        let mut state = self.env.state::<AttackMemState>();
+        match state.data {
+            None => {
+                let data = SessionBuffer::new(Self::NUM_ELEMENTS, 4096);
+                let data_ptr = data.as_ptr() as *mut u32;
+                for x in 0..(Self::NUM_ELEMENTS / 4) {
+                    unsafe {
+                        ptr::write_volatile(data_ptr.add(x), 0x1_u32);
+                    }
+                }
+                info!(
+                    "Initialized vector: {:#016x?} -> {:#016x?}",
+                    data_ptr as u64,
+                    self.env.platform().get_physical_address(data_ptr as u64)
+                );
+                state.data = Some(data);
+            }
+            Some(ref data) => unsafe {
+                let mut current_value: u32;
+                let data_ptr = data.as_ptr() as *mut u32;
+                for x in 0..(Self::NUM_ELEMENTS / 4) {
+                    current_value = if AttackMemMode::Nop != self.mode {
+                        ptr::read_volatile(data_ptr.add(x))
+                    } else {
+                        0
+                    };
+                    if AttackMemMode::Write == self.mode {
+                        ptr::write_volatile(data_ptr.add(x), current_value + 1);
+                    }
+                }
+            },
+        }
+        let data_ptr = state.data.as_ref().unwrap().as_ptr();
+        write_output(out, 0, self.env.platform().get_physical_address(data_ptr as u64));
+        Ok(())
+    }
+}
+
+/// Places a secret in enclave memory on first invocation.
+pub struct AttackIpi {
+    env: TaskEnv,
+    secret: Option<Secret<u32>>,
+}
+
+impl AttackIpi {
+    const SECRET: u32 = 0x1337_beef;
+
+    pub const DESCRIPTOR: TaskDescriptor = TaskDescriptor {
+        id: TaskId::ATTACK_IPI,
+        name: "attack_ipi",
+        create: &|env| Box::new(AttackIpi { env, secret: None }),
+    };
+}
+
+crate::claim_task_id!(ATTACK_IPI);
+
+impl Task for AttackIpi {
+    fn id(&self) -> TaskId {
+        Self::DESCRIPTOR.id
+    }
+
+    fn name(&self) -> &'static str {
+        Self::DESCRIPTOR.name
+    }
+
+    fn max_input_len(&self) -> usize {
+        0
+    }
+
+    fn max_output_len(&self) -> usize {
+        OUTPUT_LEN
+    }
+
+    fn run(&mut self, _input: &[u8], out: &mut Vec<u8>) -> Result<(), TaskError> {
+        let secret_ptr = self.secret.get_or_insert_with(|| Secret::new(Self::SECRET)).as_ptr();
+        write_output(out, 0, self.env.platform().get_physical_address(secret_ptr as u64));
+        Ok(())
+    }
+}
+
+/// Which attack on the cache state [`AttackCache`] supports.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum AttackCacheMode {
+    /// The host evicts a single line with an eviction set it builds from the
+    /// physical address, the TEE reloads the line.
+    Evict,
+    /// The TEE flushes its own page with `clflush` and reloads it. A positive
+    /// control that needs no host.
+    Flush,
+    /// The TEE primes a buffer, the host occupies the cache, and the TEE probes
+    /// the buffer by timing every line.
+    PrimeProbe,
+    /// The host writes a line the TEE keeps writing, so that the line bounces
+    /// between the cores.
+    Contention,
+}
+
+impl AttackCacheMode {
+    /// Returns the expected [`PmcSignature`] of the attack.
+    pub fn signature(self) -> PmcSignature {
+        match self {
+            // Evicted from all caches, so the reload misses all of them
+            AttackCacheMode::Evict | AttackCacheMode::Flush => PmcSignature {
+                counted: &[Event::L2Miss, Event::L3Miss],
+                silent: &[Event::L3Hit],
+            },
+            // Whether evicted lines are still in the L3 depends on the host
+            AttackCacheMode::PrimeProbe => PmcSignature {
+                counted: &[Event::L2Miss],
+                silent: &[],
+            },
+            // The line is snooped from the other core via the L3
+            AttackCacheMode::Contention => PmcSignature {
+                counted: &[Event::L2Miss, Event::L3Hit],
+                silent: &[Event::L3Miss],
+            },
+        }
+    }
+
+    /// Returns the size of the buffer the attack works on.
+    fn buffer_len(self) -> usize {
+        match self {
+            AttackCacheMode::Evict | AttackCacheMode::Contention => CACHE_LINE,
+            AttackCacheMode::Flush => 0x1000,
+            AttackCacheMode::PrimeProbe => AttackCache::OCCUPANCY_LEN,
+        }
+    }
+}
+
+/// Allocates and writes a buffer on first invocation and accesses it according
+/// to its [`AttackCacheMode`] on all further invocations. Reports which PMCs
+/// moved during the access and whether they match the [`PmcSignature`].
+pub struct AttackCache {
+    env: TaskEnv,
+    mode: AttackCacheMode,
+    data: Option<SessionBuffer>,
+}
+
+impl AttackCache {
+    /// Size of the buffer of [`AttackCacheMode::PrimeProbe`].
+    pub const OCCUPANCY_LEN: usize = 0x8000;
+    /// Cycles above which a probed line counts as evicted. An L2 hit including
+    /// the `rdtsc` overhead takes well below it.
+    pub const PROBE_THRESHOLD: u64 = 150;
+    /// Number of writes of [`AttackCacheMode::Contention`] per invocation.
+    const CONTENTION_ROUNDS: usize = 0x1000;
+
+    pub const EVICT_DESCRIPTOR: TaskDescriptor = TaskDescriptor {
+        id: TaskId::ATTACK_EVICT,
+        name: "attack_evict",
+        create: &|env| Box::new(AttackCache::new(env, AttackCacheMode::Evict)),
+    };
+
+    pub const FLUSH_DESCRIPTOR: TaskDescriptor = TaskDescriptor {
+        id: TaskId::ATTACK_FLUSH,
+        name: "attack_flush",
+        create: &|env| Box::new(AttackCache::new(env, AttackCacheMode::Flush)),
+    };
+
+    pub const PRIME_PROBE_DESCRIPTOR: TaskDescriptor = TaskDescriptor {
+        id: TaskId::ATTACK_PRIME_PROBE,
+        name: "attack_prime_probe",
+        create: &|env| Box::new(AttackCache::new(env, AttackCacheMode::PrimeProbe)),
+    };
+
+    pub const CONTENTION_DESCRIPTOR: TaskDescriptor = TaskDescriptor {
+        id: TaskId::ATTACK_CONTENTION,
+        name: "attack_contention",
+        create: &|env| Box::new(AttackCache::new(env, AttackCacheMode::Contention)),
+    };
+
+    pub fn new(env: TaskEnv, mode: AttackCacheMode) -> Self {
+        Self { env, mode, data: None }
+    }
+
+    fn descriptor(&self) -> &'static TaskDescriptor {
+        match self.mode {
+            AttackCacheMode::Evict => &Self::EVICT_DESCRIPTOR,
+            AttackCacheMode::Flush => &Self::FLUSH_DESCRIPTOR,
+            AttackCacheMode::PrimeProbe => &Self::PRIME_PROBE_DESCRIPTOR,
+            AttackCacheMode::Contention => &Self::CONTENTION_DESCRIPTOR,
+        }
+    }
+
+    /// Accesses `data` according to `mode`. Returns the number of probed lines
+    /// that were slower than [`Self::PROBE_THRESHOLD`].
+    fn access(mode: AttackCacheMode, data: &SessionBuffer) -> u32 {
+        let lines = (0..data.len()).step_by(CACHE_LINE).map(|offset| unsafe { data.as_ptr().add(offset) });
+        let mut slow = 0;
+        match mode {
+            AttackCacheMode::Evict => unsafe {
+                ptr::read_volatile(data.as_ptr());
+            },
+            AttackCacheMode::Flush => unsafe {
+                lines.clone().for_each(|line| _mm_clflush(line));
+                _mm_mfence();
+                lines.for_each(|line| {
+                    ptr::read_volatile(line);
+                });
+            },
+            AttackCacheMode::PrimeProbe => unsafe {
+                for line in lines {
+                    _mm_lfence();
+                    let start = _rdtsc();
+                    ptr::read_volatile(line);
+                    _mm_lfence();
+                    if _rdtsc().wrapping_sub(start) > Self::PROBE_THRESHOLD {
+                        slow += 1;
+                    }
+                }
+            },
+            AttackCacheMode::Contention => unsafe {
+                let counter = data.as_ptr() as *mut u64;
+                for _ in 0..Self::CONTENTION_ROUNDS {
+                    ptr::write_volatile(counter, ptr::read_volatile(counter).wrapping_add(1));
+                }
+            },
+        }
+        slow
+    }
+}
+
+crate::claim_task_id!(ATTACK_EVICT);
+crate::claim_task_id!(ATTACK_FLUSH);
+crate::claim_task_id!(ATTACK_PRIME_PROBE);
+crate::claim_task_id!(ATTACK_CONTENTION);
+
+impl Task for AttackCache {
+    fn id(&self) -> TaskId {
+        self.descriptor().id
+    }
+
+    fn name(&self) -> &'static str {
+        self.descriptor().name
+    }
+
+    fn max_input_len(&self) -> usize {
+        0
+    }
+
+    fn max_output_len(&self) -> usize {
+        OUTPUT_LEN + 1 + 4
+    }
+
+    fn run(&mut self, _input: &[u8], out: &mut Vec<u8>) -> Result<(), TaskError> {
+        let platform = self.env.platform();
+        let mut moved_pmcs = 0;
+        let mut verdict = 0;
+        let mut slow = 0;
+        match self.data {
+            None => {
+                let mut data = SessionBuffer::new(self.mode.buffer_len(), 0x1000);
+                // Writing every line primes the cache
+                data.as_mut_slice().fill(0x1);
+                info!(
+                    "Initialized {:?} buffer: {:#016x?} -> {:#016x?}",
+                    self.mode,
+                    data.as_ptr() as u64,
+                    platform.get_physical_address(data.as_ptr() as u64)
+                );
+                self.data = Some(data);
+            }
+            Some(ref data) => {
+                let before = platform.read_pmcs();
+                slow = Self::access(self.mode, data);
+                moved_pmcs = moved(before, platform.read_pmcs());
+                if let Some(uarch) = Microarchitecture::detect() {
+                    verdict = self.mode.signature().verdict(uarch, &platform.pmc_config(), moved_pmcs);
+                }
+            }
+        }
+        let data_ptr = self.data.as_ref().unwrap().as_ptr();
+        write_output(out, moved_pmcs, platform.get_physical_address(data_ptr as u64));
+        out.push(verdict);
+        if AttackCacheMode::PrimeProbe == self.mode {
+            out.extend_from_slice(&slow.to_le_bytes());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared_mem_com::TeeCommand;
+    use crate::sim::{Simulator, SIM_PMC_CONFIG};
+
+    /// Configuration of the firmware: L1D replacements without interrupt, L2
+    /// misses, L3 hits and L3 misses with interrupt.
+    const CONFIG: [u64; COUNTER_NUM] = [0x430151, 0x5310d1, 0x5304d1, 0x5320d1];
+
+    const UARCH: Microarchitecture = Microarchitecture::IceLake;
+
+    #[test]
+    fn test_signature() {
+        let flush = AttackCacheMode::Flush.signature();
+        assert!(flush.matches(UARCH, &CONFIG, 0b1010));
+        assert!(flush.matches(UARCH, &CONFIG, 0b1011));
+        assert!(false == flush.matches(UARCH, &CONFIG, 0b0010));
+        assert!(false == flush.matches(UARCH, &CONFIG, 0b1110));
+        assert!(flush.is_detected(UARCH, &CONFIG));
+        assert_eq!(flush.verdict(UARCH, &CONFIG, 0b1010), SIGNATURE_EVALUATED | SIGNATURE_MATCHED | SIGNATURE_DETECTED);
+        assert_eq!(flush.verdict(UARCH, &CONFIG, 0b0010), SIGNATURE_EVALUATED | SIGNATURE_DETECTED);
+
+        let contention = AttackCacheMode::Contention.signature();
+        assert!(contention.matches(UARCH, &CONFIG, 0b0110));
+        assert!(false == contention.matches(UARCH, &CONFIG, 0b1110));
+
+        // Events no PMC counts are ignored
+        let config = [0x430151, 0, 0, 0];
+        assert!(AttackCacheMode::PrimeProbe.signature().matches(UARCH, &config, 0b0001));
+        assert!(false == AttackCacheMode::PrimeProbe.signature().is_detected(UARCH, &config));
+        assert!(false == flush.is_detected(UARCH, &SIM_PMC_CONFIG));
+        // The events are resolved for the microarchitecture
+        assert!(false == flush.is_detected(Microarchitecture::Zen3, &CONFIG));
+    }
+
+    #[test]
+    fn test_attack_cache() {
+        let mut sim = Simulator::default();
+        for task in [TaskId::ATTACK_EVICT, TaskId::ATTACK_FLUSH, TaskId::ATTACK_CONTENTION] {
+            assert!(matches!(sim.call(task, &[]), TeeCommand::TeeSend));
+            let first = sim.output();
+            assert_eq!(first.len(), OUTPUT_LEN + 1);
+            assert_eq!(first[..2], [1, 0]);
+            assert_eq!(first[OUTPUT_LEN], 0);
+            assert!(matches!(sim.call(task, &[]), TeeCommand::TeeSend));
+            // The simulated PMCs never move on their own
+            assert_eq!(sim.output()[..OUTPUT_LEN], first[..OUTPUT_LEN]);
+            let verdict = sim.output()[OUTPUT_LEN];
+            assert_eq!(0 != verdict & SIGNATURE_EVALUATED, Microarchitecture::detect().is_some());
+        }
+
+        assert!(matches!(sim.call(TaskId::ATTACK_PRIME_PROBE, &[]), TeeCommand::TeeSend));
+        assert_eq!(sim.output()[OUTPUT_LEN..], [0; 5]);
+        assert!(matches!(sim.call(TaskId::ATTACK_PRIME_PROBE, &[]), TeeCommand::TeeSend));
+        let slow = u32::from_le_bytes(sim.output()[OUTPUT_LEN + 1..].try_into().unwrap());
+        assert!(slow as usize <= AttackCache::OCCUPANCY_LEN / CACHE_LINE);
+    }
+}
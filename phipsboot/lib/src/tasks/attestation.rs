@@ -0,0 +1,50 @@
+//! Module for the [`get_report`] task.
+//!
+//! It fails with [`TaskError::ExecutionFailed`] if the TEE received no boot
+//! secret, as the attestation key is derived from it, see
+//! [`crate::attestation`].
+
+use crate::attestation::{self, Report, NONCE_LEN, SIGNED_REPORT_LEN};
+use crate::state_machine::task::{tee_task, TaskEnv, TaskError};
+use crate::state_machine::task_id::TaskId;
+
+/// Returns a signed attestation [`Report`] for the host-supplied `nonce`.
+#[tee_task(id = TaskId::GET_REPORT, name = "get_report")]
+pub fn get_report(env: &TaskEnv, nonce: [u8; NONCE_LEN]) -> Result<[u8; SIGNED_REPORT_LEN], TaskError> {
+    let platform = env.platform();
+    let boot_secret = platform.boot_secret().ok_or_else(|| {
+        log::info!("Cannot attest without a boot secret");
+        TaskError::ExecutionFailed
+    })?;
+    let key = attestation::derive_key(&platform.measurement(), boot_secret);
+    Ok(Report::new(platform, nonce).sign(&key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attestation::REPORT_LEN;
+    use crate::shared_mem_com::TeeCommand;
+    use crate::sim::{Simulator, SIM_BOOT_SECRET, SIM_CPU_INFO, SIM_MEASUREMENT};
+    use ed25519_dalek::{Signature, Verifier};
+
+    #[test]
+    fn test_get_report() {
+        let mut sim = Simulator::default();
+        assert!(matches!(sim.call(TaskId::GET_REPORT, &[7; NONCE_LEN]), TeeCommand::TeeSend));
+        let report = sim.output();
+        assert_eq!(report.len(), SIGNED_REPORT_LEN);
+        assert_eq!(report[1..33], SIM_MEASUREMENT);
+        assert_eq!(report[65..69], SIM_CPU_INFO.signature.to_le_bytes());
+        assert_eq!(report[REPORT_LEN - NONCE_LEN..REPORT_LEN], [7; NONCE_LEN]);
+
+        // The host knows the public key from the boot secret
+        let public_key = attestation::derive_key(&SIM_MEASUREMENT, &SIM_BOOT_SECRET).verifying_key();
+        assert_eq!(report[33..65], public_key.to_bytes());
+        let signature = Signature::from_bytes(report[REPORT_LEN..].try_into().unwrap());
+        assert!(public_key.verify(&report[..REPORT_LEN], &signature).is_ok());
+
+        assert!(matches!(sim.call(TaskId::GET_REPORT, &[7; NONCE_LEN - 1]), TeeCommand::TeeError));
+        assert_eq!(sim.output(), [TaskError::InvalidInput as u8]);
+    }
+}
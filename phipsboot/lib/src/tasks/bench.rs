@@ -0,0 +1,42 @@
+//! Module for the [`bench`] task.
+
+use alloc::vec::Vec;
+
+use crate::bench::{self, Benchmark};
+use crate::state_machine::task::{tee_task, TaskEnv, TaskError};
+use crate::state_machine::task_id::TaskId;
+
+/// Returns the encoded [`BenchResult`](crate::bench::BenchResult) of the
+/// [`Benchmark`] with the ID `benchmark`. Fails if it did not run at boot.
+#[tee_task(id = TaskId::BENCH, name = "bench", max_output = 514)]
+pub fn bench(_env: &TaskEnv, benchmark: u8) -> Result<Vec<u8>, TaskError> {
+    let benchmark = Benchmark::try_from(benchmark).map_err(|_| TaskError::InvalidInput)?;
+    bench::recorded(benchmark)
+        .map(|result| result.encode())
+        .ok_or(TaskError::ExecutionFailed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bench::DEFAULT_PASSES;
+    use crate::shared_mem_com::TeeCommand;
+    use crate::sim::{SimPlatform, Simulator};
+
+    #[test]
+    fn test_bench() {
+        let mut sim = Simulator::default();
+        let id = [Benchmark::HeapFootprint as u8];
+        assert!(matches!(sim.call(TaskId::BENCH, &id), TeeCommand::TeeError));
+        assert_eq!(sim.output(), [TaskError::ExecutionFailed as u8]);
+
+        bench::record(Benchmark::HeapFootprint.run(&SimPlatform::default(), 0x1000, DEFAULT_PASSES));
+        assert!(matches!(sim.call(TaskId::BENCH, &id), TeeCommand::TeeSend));
+        assert_eq!(sim.output()[..2], [Benchmark::HeapFootprint as u8, DEFAULT_PASSES]);
+        assert_eq!(sim.output().len(), 2 + 8 * usize::from(DEFAULT_PASSES));
+
+        assert!(matches!(sim.call(TaskId::BENCH, &[Benchmark::MemPasses as u8]), TeeCommand::TeeError));
+        assert!(matches!(sim.call(TaskId::BENCH, &[0]), TeeCommand::TeeError));
+        assert_eq!(sim.output(), [TaskError::InvalidInput as u8]);
+    }
+}
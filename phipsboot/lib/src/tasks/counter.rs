@@ -0,0 +1,275 @@
+//! Named monotonic counters for rollback protection.
+//!
+//! The [`Counters`] are shared by all sessions and live as long as the TEE.
+//! A counter can only be created, incremented and read, never decremented.
+//!
+//! The state can be exported as a sealed blob and imported again, e.g., after
+//! a reboot. The blob is sealed with its own key, so the host cannot forge one
+//! with the `seal` task. The blob carries the boot epoch, a random value drawn once per
+//! boot, and the generation, which grows with every change of the state:
+//!
+//! ```text
+//! 0         1       17           25      26
+//! | version | epoch | generation | count | name length | name | value | ...
+//! ```
+//!
+//! A blob of the current boot epoch that is older than the current state is a
+//! replay and rejected. A blob of an earlier boot is merged, with every counter
+//! taking the larger of both values, so no import can decrease a counter. The
+//! TEE cannot tell whether a blob of an earlier boot is the latest one; the
+//! host has to keep its blobs safe for that.
+
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::RefMut;
+
+use crate::seal::{SealPurpose, SealingKey};
+use crate::state_machine::task::{tee_task, TaskEnv, TaskError};
+use crate::state_machine::task_id::TaskId;
+use crate::tasks::seal::sealing_key;
+
+/// Version of the format described in the [module docs](self).
+pub const COUNTERS_VERSION: u8 = 1;
+/// Maximum number of counters.
+pub const MAX_COUNTERS: usize = 32;
+/// Maximum length of the name of a counter.
+pub const MAX_NAME_LEN: usize = 32;
+/// Length of the boot epoch.
+pub const EPOCH_LEN: usize = 16;
+const HEADER_LEN: usize = 1 + EPOCH_LEN + 8 + 1;
+
+/// Monotonic counters of the TEE. Use them via [`counters`].
+#[derive(Debug, Default)]
+pub struct Counters {
+    /// Drawn on first use in this boot.
+    epoch: Option<[u8; EPOCH_LEN]>,
+    /// Incremented by every change.
+    generation: u64,
+    entries: BTreeMap<Vec<u8>, u64>,
+}
+
+impl Counters {
+    /// Creates the counter `name` with the value 0.
+    pub fn create(&mut self, name: &[u8]) -> Result<(), TaskError> {
+        if name.is_empty() || name.len() > MAX_NAME_LEN || self.entries.contains_key(name) {
+            return Err(TaskError::InvalidInput);
+        }
+        if self.entries.len() >= MAX_COUNTERS {
+            return Err(TaskError::ExecutionFailed);
+        }
+        let generation = self.generation.checked_add(1).ok_or(TaskError::ExecutionFailed)?;
+        self.entries.insert(name.to_vec(), 0);
+        self.generation = generation;
+        Ok(())
+    }
+
+    /// Increments the counter `name` and returns its new value.
+    pub fn increment(&mut self, name: &[u8]) -> Result<u64, TaskError> {
+        let generation = self.generation.checked_add(1).ok_or(TaskError::ExecutionFailed)?;
+        let value = self.entries.get_mut(name).ok_or(TaskError::InvalidInput)?;
+        *value = value.checked_add(1).ok_or(TaskError::ExecutionFailed)?;
+        self.generation = generation;
+        Ok(*value)
+    }
+
+    /// Returns the value of the counter `name`.
+    pub fn read(&self, name: &[u8]) -> Result<u64, TaskError> {
+        self.entries.get(name).copied().ok_or(TaskError::InvalidInput)
+    }
+
+    /// Encodes the state as described in the [module docs](self).
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.push(COUNTERS_VERSION);
+        out.extend_from_slice(&self.epoch.unwrap_or_default());
+        out.extend_from_slice(&self.generation.to_le_bytes());
+        // There are at most MAX_COUNTERS entries
+        out.push(self.entries.len() as u8);
+        for (name, value) in &self.entries {
+            out.push(name.len() as u8);
+            out.extend_from_slice(name);
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+        out
+    }
+
+    /// Merges the encoded state `data` into this state. Fails without a change
+    /// if `data` is malformed, a replay of the current boot epoch or of the
+    /// last generation.
+    pub fn merge(&mut self, data: &[u8]) -> Result<(), TaskError> {
+        if data.len() < HEADER_LEN || COUNTERS_VERSION != data[0] {
+            return Err(TaskError::InvalidInput);
+        }
+        let epoch = &data[1..1 + EPOCH_LEN];
+        let generation = u64::from_le_bytes(data[1 + EPOCH_LEN..HEADER_LEN - 1].try_into().unwrap());
+        if Some(epoch) == self.epoch.as_ref().map(|epoch| &epoch[..]) && generation < self.generation {
+            log::info!("Rejecting counters of generation {}, current is {}", generation, self.generation);
+            return Err(TaskError::InvalidInput);
+        }
+        let generation = self.generation.max(generation).checked_add(1).ok_or(TaskError::InvalidInput)?;
+
+        let mut merged = self.entries.clone();
+        let mut rest = &data[HEADER_LEN..];
+        for _ in 0..data[HEADER_LEN - 1] {
+            let name_len = usize::from(*rest.first().ok_or(TaskError::InvalidInput)?);
+            if 0 == name_len || name_len > MAX_NAME_LEN || rest.len() < 1 + name_len + 8 {
+                return Err(TaskError::InvalidInput);
+            }
+            let name = &rest[1..1 + name_len];
+            let value = u64::from_le_bytes(rest[1 + name_len..1 + name_len + 8].try_into().unwrap());
+            let entry = merged.entry(name.to_vec()).or_default();
+            *entry = value.max(*entry);
+            rest = &rest[1 + name_len + 8..];
+        }
+        if false == rest.is_empty() || merged.len() > MAX_COUNTERS {
+            return Err(TaskError::InvalidInput);
+        }
+        self.entries = merged;
+        self.generation = generation;
+        Ok(())
+    }
+}
+
+/// Returns the counters of the TEE. Draws the boot epoch on first use.
+pub fn counters(env: &TaskEnv) -> Result<RefMut<'_, Counters>, TaskError> {
+    if env.global_state::<Counters>().epoch.is_none() {
+        let mut epoch = [0; EPOCH_LEN];
+        env.fill_random(&mut epoch)?;
+        env.global_state::<Counters>().epoch = Some(epoch);
+    }
+    Ok(env.global_state::<Counters>())
+}
+
+/// Creates the counter `name` with the value 0.
+#[tee_task(id = TaskId::COUNTER_CREATE, name = "counter_create")]
+pub fn counter_create(env: &TaskEnv, name: Vec<u8>) -> Result<(), TaskError> {
+    counters(env)?.create(&name)
+}
+
+/// Increments the counter `name` and returns its new value.
+#[tee_task(id = TaskId::COUNTER_INCREMENT, name = "counter_increment")]
+pub fn counter_increment(env: &TaskEnv, name: Vec<u8>) -> Result<u64, TaskError> {
+    counters(env)?.increment(&name)
+}
+
+/// Returns the value of the counter `name`.
+#[tee_task(id = TaskId::COUNTER_READ, name = "counter_read")]
+pub fn counter_read(env: &TaskEnv, name: Vec<u8>) -> Result<u64, TaskError> {
+    counters(env)?.read(&name)
+}
+
+/// Returns the state of all counters, sealed.
+#[tee_task(id = TaskId::COUNTER_EXPORT, name = "counter_export", max_output = 2048)]
+pub fn counter_export(env: &TaskEnv) -> Result<Vec<u8>, TaskError> {
+    let key = sealing_key(env, SealPurpose::Counters)?;
+    Ok(key.seal(&counters(env)?.encode()))
+}
+
+/// Unseals `blob` and merges the state into the counters.
+#[tee_task(id = TaskId::COUNTER_IMPORT, name = "counter_import")]
+pub fn counter_import(env: &TaskEnv, blob: Vec<u8>) -> Result<(), TaskError> {
+    let key = sealing_key(env, SealPurpose::Counters)?;
+    let mut data = vec![0; SealingKey::data_len(&blob)?];
+    key.unseal(&blob, &mut data)?;
+    counters(env)?.merge(&data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared_mem_com::TeeCommand;
+    use crate::sim::Simulator;
+
+    fn call(sim: &mut Simulator, task: TaskId, input: &[u8]) -> Result<Vec<u8>, u8> {
+        match sim.call(task, input) {
+            TeeCommand::TeeSend => Ok(sim.output()),
+            _ => Err(sim.output()[0]),
+        }
+    }
+
+    #[test]
+    fn test_counters() {
+        let mut counters = Counters::default();
+        assert_eq!(counters.create(b"a"), Ok(()));
+        assert_eq!(counters.create(b"a"), Err(TaskError::InvalidInput));
+        assert_eq!(counters.increment(b"a"), Ok(1));
+        assert_eq!(counters.increment(b"a"), Ok(2));
+        assert_eq!(counters.read(b"a"), Ok(2));
+        assert_eq!(counters.read(b"b"), Err(TaskError::InvalidInput));
+        assert_eq!(counters.increment(b"b"), Err(TaskError::InvalidInput));
+        assert_eq!(counters.create(&[]), Err(TaskError::InvalidInput));
+
+        for x in 1..MAX_COUNTERS {
+            counters.create(&[x as u8]).unwrap();
+        }
+        assert_eq!(counters.create(b"b"), Err(TaskError::ExecutionFailed));
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut counters = Counters { epoch: Some([1; EPOCH_LEN]), ..Default::default() };
+        counters.create(b"a").unwrap();
+        counters.increment(b"a").unwrap();
+        let old = counters.encode();
+        counters.increment(b"a").unwrap();
+        counters.create(b"b").unwrap();
+
+        // An older state of this boot is a replay
+        assert_eq!(counters.merge(&old), Err(TaskError::InvalidInput));
+        let current = counters.encode();
+        assert_eq!(counters.merge(&current), Ok(()));
+        assert_eq!(counters.read(b"a"), Ok(2));
+
+        // A state of another boot never decreases a counter
+        let mut rebooted = Counters { epoch: Some([2; EPOCH_LEN]), ..Default::default() };
+        rebooted.create(b"c").unwrap();
+        assert_eq!(rebooted.merge(&old), Ok(()));
+        assert_eq!(rebooted.merge(&current), Ok(()));
+        assert_eq!(rebooted.merge(&old), Ok(()));
+        assert_eq!(rebooted.read(b"a"), Ok(2));
+        assert_eq!(rebooted.read(b"b"), Ok(0));
+        assert_eq!(rebooted.read(b"c"), Ok(0));
+
+        // Malformed states change nothing
+        assert_eq!(rebooted.merge(&current[..current.len() - 1]), Err(TaskError::InvalidInput));
+        assert_eq!(rebooted.merge(&[current.as_slice(), &[0]].concat()), Err(TaskError::InvalidInput));
+        assert_eq!(rebooted.merge(&[]), Err(TaskError::InvalidInput));
+        assert_eq!(rebooted.read(b"a"), Ok(2));
+
+        // The generation cannot overflow
+        let mut last = Counters { epoch: Some([3; EPOCH_LEN]), generation: u64::MAX, ..Default::default() };
+        let blob = last.encode();
+        assert_eq!(rebooted.merge(&blob), Err(TaskError::InvalidInput));
+        assert_eq!(last.create(b"a"), Err(TaskError::ExecutionFailed));
+        assert_eq!(last.read(b"a"), Err(TaskError::InvalidInput));
+    }
+
+    #[test]
+    fn test_counter_tasks() {
+        let mut sim = Simulator::default();
+        assert_eq!(call(&mut sim, TaskId::COUNTER_CREATE, b"ctr"), Ok(Vec::new()));
+        assert_eq!(call(&mut sim, TaskId::COUNTER_INCREMENT, b"ctr"), Ok(1_u64.to_le_bytes().to_vec()));
+        let blob = call(&mut sim, TaskId::COUNTER_EXPORT, &[]).unwrap();
+        assert_eq!(call(&mut sim, TaskId::COUNTER_INCREMENT, b"ctr"), Ok(2_u64.to_le_bytes().to_vec()));
+
+        // Counters are shared by all sessions and survive them
+        let session = sim.open_session().unwrap();
+        assert!(matches!(sim.close_session(session), TeeCommand::TeeSend));
+        sim.set_session(0);
+        assert_eq!(call(&mut sim, TaskId::COUNTER_READ, b"ctr"), Ok(2_u64.to_le_bytes().to_vec()));
+
+        assert_eq!(call(&mut sim, TaskId::COUNTER_IMPORT, &blob), Err(TaskError::InvalidInput as u8));
+        let blob = call(&mut sim, TaskId::COUNTER_EXPORT, &[]).unwrap();
+        assert_eq!(call(&mut sim, TaskId::COUNTER_IMPORT, &blob), Ok(Vec::new()));
+        let mut tampered = blob.clone();
+        tampered[blob.len() - 1] ^= 1;
+        assert_eq!(call(&mut sim, TaskId::COUNTER_IMPORT, &tampered), Err(TaskError::InvalidInput as u8));
+
+        // Blobs of the seal task are not accepted
+        let mut forged = Counters { generation: u64::MAX - 1, ..Default::default() };
+        forged.entries.insert(b"ctr".to_vec(), 0);
+        let forged = call(&mut sim, TaskId::SEAL, &forged.encode()).unwrap();
+        assert_eq!(call(&mut sim, TaskId::COUNTER_IMPORT, &forged), Err(TaskError::InvalidInput as u8));
+    }
+}
@@ -0,0 +1,152 @@
+//! Cryptographic services: hashing, HMAC and Ed25519 signatures.
+//!
+//! Keys are [`Secrets`] of the session. They are generated inside the TEE or
+//! unsealed into it, and the host only refers to them by [`Handle`].
+
+use alloc::vec::Vec;
+use ed25519_dalek::{Signer, SigningKey};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256, Sha512};
+
+use crate::state_machine::session::SessionBuffer;
+use crate::state_machine::task::{tee_task, TaskEnv, TaskError};
+use crate::state_machine::task_id::TaskId;
+use crate::tasks::secrets::{Handle, SecretKind, Secrets};
+
+/// Length of a symmetric key if the host does not request another one.
+pub const DEFAULT_KEY_LEN: u8 = 32;
+
+/// Returns a new secret of `len` random bytes.
+fn random_secret(env: &TaskEnv, len: usize) -> Result<SessionBuffer, TaskError> {
+    let mut secret = SessionBuffer::new(len, 1);
+    env.fill_random(secret.as_mut_slice())?;
+    Ok(secret)
+}
+
+/// Returns the signing key `handle` refers to.
+fn signing_key(env: &TaskEnv, handle: Handle) -> Result<SigningKey, TaskError> {
+    let secrets = env.state::<Secrets>();
+    let seed = secrets.get(handle, SecretKind::Ed25519)?;
+    // Ed25519 secrets are always 32 bytes long
+    Ok(SigningKey::from_bytes(seed.try_into().unwrap()))
+}
+
+#[tee_task(id = TaskId::SHA256, name = "sha256")]
+pub fn sha256(data: Vec<u8>) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+#[tee_task(id = TaskId::SHA512, name = "sha512")]
+pub fn sha512(data: Vec<u8>) -> [u8; 64] {
+    Sha512::digest(data).into()
+}
+
+/// Computes the HMAC-SHA256 of `data` with the key `key` refers to.
+#[tee_task(id = TaskId::HMAC_SHA256, name = "hmac_sha256")]
+pub fn hmac_sha256(env: &TaskEnv, key: Handle, data: Vec<u8>) -> Result<[u8; 32], TaskError> {
+    let secrets = env.state::<Secrets>();
+    // HMAC accepts keys of any length
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(secrets.get(key, SecretKind::Data)?).unwrap();
+    mac.update(&data);
+    Ok(mac.finalize().into_bytes().into())
+}
+
+/// Generates a symmetric key of `len` bytes, [`DEFAULT_KEY_LEN`] by default.
+/// Returns its handle.
+#[tee_task(id = TaskId::GENERATE_KEY, name = "generate_key")]
+pub fn generate_key(env: &TaskEnv, len: Option<u8>) -> Result<Handle, TaskError> {
+    let len = len.unwrap_or(DEFAULT_KEY_LEN);
+    if 0 == len {
+        return Err(TaskError::InvalidInput);
+    }
+    let key = random_secret(env, len.into())?;
+    env.state::<Secrets>().insert(SecretKind::Data, key)
+}
+
+/// Generates an Ed25519 signing key. Returns its handle.
+#[tee_task(id = TaskId::ED25519_GENERATE, name = "ed25519_generate")]
+pub fn ed25519_generate(env: &TaskEnv) -> Result<Handle, TaskError> {
+    let seed = random_secret(env, ed25519_dalek::SECRET_KEY_LENGTH)?;
+    env.state::<Secrets>().insert(SecretKind::Ed25519, seed)
+}
+
+/// Returns the public key of the signing key `key` refers to.
+#[tee_task(id = TaskId::ED25519_PUBLIC_KEY, name = "ed25519_public_key")]
+pub fn ed25519_public_key(env: &TaskEnv, key: Handle) -> Result<[u8; 32], TaskError> {
+    Ok(signing_key(env, key)?.verifying_key().to_bytes())
+}
+
+/// Signs `message` with the signing key `key` refers to.
+#[tee_task(id = TaskId::ED25519_SIGN, name = "ed25519_sign")]
+pub fn ed25519_sign(env: &TaskEnv, key: Handle, message: Vec<u8>) -> Result<[u8; 64], TaskError> {
+    Ok(signing_key(env, key)?.sign(&message).to_bytes())
+}
+
+/// Zeroes and removes the secret `handle` refers to.
+#[tee_task(id = TaskId::DELETE_SECRET, name = "delete_secret")]
+pub fn delete_secret(env: &TaskEnv, handle: Handle) -> Result<(), TaskError> {
+    env.state::<Secrets>().remove(handle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared_mem_com::TeeCommand;
+    use crate::sim::Simulator;
+    use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+
+    fn call(sim: &mut Simulator, task: TaskId, input: &[u8]) -> Result<Vec<u8>, u8> {
+        match sim.call(task, input) {
+            TeeCommand::TeeSend => Ok(sim.output()),
+            _ => Err(sim.output()[0]),
+        }
+    }
+
+    #[test]
+    fn test_hashes() {
+        let mut sim = Simulator::default();
+        let digest = call(&mut sim, TaskId::SHA256, b"abc").unwrap();
+        assert_eq!(digest[..4], [0xba, 0x78, 0x16, 0xbf]);
+        assert_eq!(digest, Sha256::digest(b"abc")[..]);
+        let digest = call(&mut sim, TaskId::SHA512, b"abc").unwrap();
+        assert_eq!(digest[..4], [0xdd, 0xaf, 0x35, 0xa1]);
+        assert_eq!(digest.len(), 64);
+    }
+
+    #[test]
+    fn test_hmac() {
+        let mut sim = Simulator::default();
+        let key = call(&mut sim, TaskId::GENERATE_KEY, &[]).unwrap();
+        assert_eq!(key, [0, 0]);
+        let mac = call(&mut sim, TaskId::HMAC_SHA256, &[0, 0, b'x']).unwrap();
+        assert_eq!(mac.len(), 32);
+        assert_eq!(call(&mut sim, TaskId::HMAC_SHA256, &[0, 0, b'x']).unwrap(), mac);
+        assert_ne!(call(&mut sim, TaskId::HMAC_SHA256, &[0, 0, b'y']).unwrap(), mac);
+
+        // Keys are bound to the session
+        sim.open_session().unwrap();
+        assert_eq!(call(&mut sim, TaskId::HMAC_SHA256, &[0, 0, b'x']), Err(TaskError::InvalidInput as u8));
+        sim.set_session(0);
+        assert_eq!(call(&mut sim, TaskId::DELETE_SECRET, &[0, 0]), Ok(Vec::new()));
+        assert_eq!(call(&mut sim, TaskId::HMAC_SHA256, &[0, 0, b'x']), Err(TaskError::InvalidInput as u8));
+        assert_eq!(call(&mut sim, TaskId::GENERATE_KEY, &[0]), Err(TaskError::InvalidInput as u8));
+    }
+
+    #[test]
+    fn test_ed25519() {
+        let mut sim = Simulator::default();
+        let key = call(&mut sim, TaskId::ED25519_GENERATE, &[]).unwrap();
+        let public_key = call(&mut sim, TaskId::ED25519_PUBLIC_KEY, &key).unwrap();
+        let signature = call(&mut sim, TaskId::ED25519_SIGN, &[&key[..], b"message"].concat()).unwrap();
+
+        let public_key = VerifyingKey::from_bytes(public_key[..].try_into().unwrap()).unwrap();
+        let signature = Signature::from_bytes(signature[..].try_into().unwrap());
+        assert!(public_key.verify(b"message", &signature).is_ok());
+        assert!(public_key.verify(b"messagE", &signature).is_err());
+
+        // A signing key is not an HMAC key and vice versa
+        assert_eq!(call(&mut sim, TaskId::HMAC_SHA256, &key), Err(TaskError::InvalidInput as u8));
+        let hmac_key = call(&mut sim, TaskId::GENERATE_KEY, &[]).unwrap();
+        assert_eq!(call(&mut sim, TaskId::ED25519_SIGN, &hmac_key), Err(TaskError::InvalidInput as u8));
+    }
+}
@@ -0,0 +1,202 @@
+//! Key-value store that keeps small secrets in enclave memory.
+//!
+//! Every session has its own [`KvStore`]. The stores of all sessions together
+//! never occupy more than [`KV_BUDGET`] bytes of the heap, so that they stay
+//! resident in the cache next to the rest of the TEE. A full store rejects new
+//! values instead of growing.
+//!
+//! Values are zeroed when they are deleted or overwritten, when the session is
+//! closed, and when tampering is detected, as that closes all sessions.
+//!
+//! [`kv_list`] returns the keys in ascending order, each prefixed with its
+//! length:
+//!
+//! ```text
+//! | key length | key | key length | key | ...
+//! ```
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use crate::state_machine::session::{SessionBuffer, MAX_SESSIONS};
+use crate::state_machine::task::{tee_task, TaskEnv, TaskError};
+use crate::state_machine::task_id::TaskId;
+
+/// Bytes of the heap the stores of all sessions may occupy. The heap is
+/// preloaded into the cache, but only a part of it is left for tasks once the
+/// TEE itself is resident.
+pub const KV_BUDGET: usize = 0x8000;
+/// Bytes the store of a single session may occupy.
+pub const KV_CAPACITY: usize = KV_BUDGET / MAX_SESSIONS;
+/// Maximum length of a key.
+pub const MAX_KEY_LEN: usize = 32;
+/// Bookkeeping of the map that is charged to every entry.
+const ENTRY_OVERHEAD: usize = 32;
+
+/// Key-value store of a session. Use it via
+/// [`TaskEnv::state`](crate::state_machine::task::TaskEnv::state).
+#[derive(Debug, Default)]
+pub struct KvStore {
+    entries: BTreeMap<Vec<u8>, SessionBuffer>,
+    /// Bytes charged against [`KV_CAPACITY`].
+    used: usize,
+}
+
+impl KvStore {
+    /// Returns the number of bytes an entry is charged.
+    fn footprint(key: &[u8], value_len: usize) -> usize {
+        ENTRY_OVERHEAD + key.len() + value_len
+    }
+
+    /// Stores `value` under `key`, replacing and zeroing the previous value.
+    /// Fails if the store would exceed [`KV_CAPACITY`].
+    pub fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(), TaskError> {
+        if key.is_empty() || key.len() > MAX_KEY_LEN || value.is_empty() {
+            return Err(TaskError::InvalidInput);
+        }
+        let replaced = self.entries.get(key).map_or(0, |old| Self::footprint(key, old.len()));
+        let used = self.used - replaced + Self::footprint(key, value.len());
+        if used > KV_CAPACITY {
+            return Err(TaskError::ExecutionFailed);
+        }
+        let mut buffer = SessionBuffer::new(value.len(), 1);
+        buffer.as_mut_slice().copy_from_slice(value);
+        self.entries.insert(key.to_vec(), buffer);
+        self.used = used;
+        Ok(())
+    }
+
+    /// Returns the value stored under `key`.
+    pub fn get(&self, key: &[u8]) -> Result<&[u8], TaskError> {
+        self.entries
+            .get(key)
+            .map(SessionBuffer::as_slice)
+            .ok_or(TaskError::InvalidInput)
+    }
+
+    /// Zeroes and removes the value stored under `key`.
+    pub fn remove(&mut self, key: &[u8]) -> Result<(), TaskError> {
+        let value = self.entries.remove(key).ok_or(TaskError::InvalidInput)?;
+        self.used -= Self::footprint(key, value.len());
+        Ok(())
+    }
+
+    /// Returns all keys in ascending order.
+    pub fn keys(&self) -> impl Iterator<Item = &[u8]> {
+        self.entries.keys().map(Vec::as_slice)
+    }
+
+    /// Returns the number of bytes charged against [`KV_CAPACITY`].
+    pub fn used(&self) -> usize {
+        self.used
+    }
+}
+
+/// Stores a value. The input is the key followed by the value, `key_len` is
+/// the length of the key.
+#[tee_task(id = TaskId::KV_PUT, name = "kv_put")]
+pub fn kv_put(env: &TaskEnv, key_len: u8, data: Vec<u8>) -> Result<(), TaskError> {
+    if usize::from(key_len) > data.len() {
+        return Err(TaskError::InvalidInput);
+    }
+    let (key, value) = data.split_at(key_len.into());
+    env.state::<KvStore>().put(key, value)
+}
+
+/// Returns the value stored under `key`.
+#[tee_task(id = TaskId::KV_GET, name = "kv_get", max_output = 2048)]
+pub fn kv_get(env: &TaskEnv, key: Vec<u8>) -> Result<Vec<u8>, TaskError> {
+    Ok(env.state::<KvStore>().get(&key)?.to_vec())
+}
+
+/// Zeroes and removes the value stored under `key`.
+#[tee_task(id = TaskId::KV_DELETE, name = "kv_delete")]
+pub fn kv_delete(env: &TaskEnv, key: Vec<u8>) -> Result<(), TaskError> {
+    env.state::<KvStore>().remove(&key)
+}
+
+/// Returns the keys of the store as described in the [module docs](self).
+/// The list never exceeds [`KV_CAPACITY`], as every key is charged more than
+/// its entry in the list.
+#[tee_task(id = TaskId::KV_LIST, name = "kv_list", max_output = 2048)]
+pub fn kv_list(env: &TaskEnv) -> Vec<u8> {
+    let mut list = Vec::new();
+    for key in env.state::<KvStore>().keys() {
+        // Keys are at most MAX_KEY_LEN bytes long
+        list.push(key.len() as u8);
+        list.extend_from_slice(key);
+    }
+    list
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared_mem_com::TeeCommand;
+    use alloc::vec;
+    use crate::sim::Simulator;
+
+    fn call(sim: &mut Simulator, task: TaskId, input: &[u8]) -> Result<Vec<u8>, u8> {
+        match sim.call(task, input) {
+            TeeCommand::TeeSend => Ok(sim.output()),
+            _ => Err(sim.output()[0]),
+        }
+    }
+
+    #[test]
+    fn test_capacity() {
+        let mut store = KvStore::default();
+        let value = [1; 100];
+        let mut count = 0;
+        while store.put(&[count], &value).is_ok() {
+            count += 1;
+        }
+        assert_eq!(usize::from(count), KV_CAPACITY / KvStore::footprint(&[0], value.len()));
+        assert!(store.used() <= KV_CAPACITY);
+
+        // Overwriting only charges the difference
+        let spare = KV_CAPACITY - store.used();
+        assert_eq!(store.put(&[0], &vec![2; value.len() + spare + 1]), Err(TaskError::ExecutionFailed));
+        assert_eq!(store.put(&[0], &vec![2; value.len() + spare]), Ok(()));
+        assert_eq!(store.get(&[0]), Ok(&vec![2; value.len() + spare][..]));
+        assert_eq!(store.used(), KV_CAPACITY);
+        assert_eq!(store.remove(&[0]), Ok(()));
+        assert_eq!(store.put(&[count], &value), Ok(()));
+    }
+
+    #[test]
+    fn test_invalid_entries() {
+        let mut store = KvStore::default();
+        assert_eq!(store.put(&[], &[1]), Err(TaskError::InvalidInput));
+        assert_eq!(store.put(&[1; MAX_KEY_LEN + 1], &[1]), Err(TaskError::InvalidInput));
+        assert_eq!(store.put(&[1], &[]), Err(TaskError::InvalidInput));
+        assert_eq!(store.put(&[1], &[0; KV_CAPACITY]), Err(TaskError::ExecutionFailed));
+        assert_eq!(store.get(&[1]), Err(TaskError::InvalidInput));
+        assert_eq!(store.remove(&[1]), Err(TaskError::InvalidInput));
+        assert_eq!(store.used(), 0);
+    }
+
+    #[test]
+    fn test_kv_tasks() {
+        let mut sim = Simulator::default();
+        assert_eq!(call(&mut sim, TaskId::KV_PUT, b"\x03keyvalue"), Ok(Vec::new()));
+        assert_eq!(call(&mut sim, TaskId::KV_PUT, b"\x01kv"), Ok(Vec::new()));
+        assert_eq!(call(&mut sim, TaskId::KV_GET, b"key"), Ok(b"value".to_vec()));
+        assert_eq!(call(&mut sim, TaskId::KV_LIST, &[]), Ok(b"\x01k\x03key".to_vec()));
+        assert_eq!(call(&mut sim, TaskId::KV_PUT, b"\x04key"), Err(TaskError::InvalidInput as u8));
+
+        // Stores are bound to the session
+        sim.open_session().unwrap();
+        assert_eq!(call(&mut sim, TaskId::KV_LIST, &[]), Ok(Vec::new()));
+        assert_eq!(call(&mut sim, TaskId::KV_GET, b"key"), Err(TaskError::InvalidInput as u8));
+        sim.set_session(0);
+        assert_eq!(call(&mut sim, TaskId::KV_DELETE, b"key"), Ok(Vec::new()));
+        assert_eq!(call(&mut sim, TaskId::KV_GET, b"key"), Err(TaskError::InvalidInput as u8));
+        assert_eq!(call(&mut sim, TaskId::KV_LIST, &[]), Ok(b"\x01k".to_vec()));
+
+        // Tampering wipes the store
+        sim.platform().set_pmcs([0; 4]);
+        assert!(call(&mut sim, TaskId::KV_LIST, &[]).is_err());
+        assert_eq!(call(&mut sim, TaskId::KV_LIST, &[]), Ok(Vec::new()));
+    }
+}
@@ -0,0 +1,36 @@
+//! Tasks that are built into the TEE.
+//!
+//! Tasks declared with [`tee_task`](crate::state_machine::task::tee_task)
+//! register themselves. Only tasks that implement
+//! [`Task`](crate::state_machine::task::Task) by hand are listed in
+//! [`BUILTIN_TASKS`].
+
+pub mod attack;
+pub mod attestation;
+pub mod bench;
+pub mod counter;
+pub mod crypto;
+pub mod kv;
+pub mod module;
+pub mod ping;
+pub mod random;
+pub mod seal;
+pub mod secrets;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+use crate::state_machine::task::TaskDescriptor;
+
+/// Tasks that are registered by [`TaskRegistry::with_builtin_tasks`].
+///
+/// [`TaskRegistry::with_builtin_tasks`]: crate::state_machine::task::TaskRegistry::with_builtin_tasks
+pub const BUILTIN_TASKS: &[TaskDescriptor] = &[
+    attack::AttackMem::READ_DESCRIPTOR,
+    attack::AttackMem::WRITE_DESCRIPTOR,
+    attack::AttackMem::NOP_DESCRIPTOR,
+    attack::AttackIpi::DESCRIPTOR,
+    attack::AttackCache::EVICT_DESCRIPTOR,
+    attack::AttackCache::FLUSH_DESCRIPTOR,
+    attack::AttackCache::PRIME_PROBE_DESCRIPTOR,
+    attack::AttackCache::CONTENTION_DESCRIPTOR,
+];
@@ -0,0 +1,250 @@
+//! Tasks that are loaded at boot time instead of being compiled into the TEE.
+//!
+//! The firmware receives them as Multiboot2 modules. A module is a flat image:
+//! a header, the manifest, followed by position-independent code.
+//!
+//! ```text
+//! 0       8         10   12      16         20          24           28         32     64
+//! | magic | version | id | entry | code_len | max_input | max_output | reserved | name | code ...
+//! ```
+//!
+//! - `magic`: [`MODULE_MAGIC`]
+//! - `version`: [`MODULE_VERSION`]
+//! - `id`: [`TaskId`] the task is registered with.
+//! - `entry`: Offset of the entry point within the code.
+//! - `code_len`: Size of the code in bytes.
+//! - `max_input`/`max_output`: Limits as reported by [`Task`].
+//! - `name`: UTF-8 name of the task, padded with NUL bytes.
+//!
+//! All integers are little-endian. The entry point has the signature
+//! [`ModuleEntry`]. It runs in ring 3 and uses the services of
+//! [`crate::sandbox`] to read its input and to write its output. A negative
+//! return value signals failure.
+
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::state_machine::task::{Task, TaskDescriptor, TaskEnv, TaskError};
+use crate::state_machine::task_id::TaskId;
+
+/// Identifies a task module.
+pub const MODULE_MAGIC: [u8; 8] = *b"TEETASK\0";
+/// Version of the module format described in the [module docs](self).
+pub const MODULE_VERSION: u16 = 1;
+/// Size of the header in front of the code.
+pub const MODULE_HEADER_SIZE: usize = 64;
+/// Maximum length of the name of a module task.
+const NAME_LEN: usize = 32;
+
+/// Entry point of a module task.
+pub type ModuleEntry = extern "sysv64" fn() -> i64;
+
+/// Reason why a module was rejected.
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub enum ModuleError {
+    /// The module is smaller than the header.
+    TooSmall,
+    /// The module does not start with [`MODULE_MAGIC`]. It is probably not a
+    /// task.
+    BadMagic,
+    UnsupportedVersion(u16),
+    /// The task ID is reserved.
+    InvalidId(TaskId),
+    /// The code exceeds the module.
+    CodeOutOfBounds,
+    /// The entry point lies outside of the code.
+    EntryOutOfBounds,
+    /// The name is not valid UTF-8.
+    InvalidName,
+}
+
+/// Validated task module that is mapped for the lifetime of the TEE.
+#[derive(Debug)]
+pub struct ModuleImage {
+    id: TaskId,
+    name: &'static str,
+    entry: usize,
+    code: &'static [u8],
+    max_input: usize,
+    max_output: usize,
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> usize {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap()) as usize
+}
+
+impl ModuleImage {
+    /// Validates the module in `bytes`.
+    pub fn parse(bytes: &'static [u8]) -> Result<Self, ModuleError> {
+        if bytes.len() < MODULE_HEADER_SIZE {
+            return Err(ModuleError::TooSmall);
+        }
+        if MODULE_MAGIC != bytes[0..8] {
+            return Err(ModuleError::BadMagic);
+        }
+        let version = read_u16(bytes, 8);
+        if MODULE_VERSION != version {
+            return Err(ModuleError::UnsupportedVersion(version));
+        }
+        let id = TaskId(read_u16(bytes, 10));
+        if TaskId::UNKNOWN == id {
+            return Err(ModuleError::InvalidId(id));
+        }
+        let entry = read_u32(bytes, 12);
+        let code_len = read_u32(bytes, 16);
+        let code = bytes[MODULE_HEADER_SIZE..]
+            .get(..code_len)
+            .ok_or(ModuleError::CodeOutOfBounds)?;
+        if entry >= code_len {
+            return Err(ModuleError::EntryOutOfBounds);
+        }
+        let name = &bytes[32..32 + NAME_LEN];
+        let name_len = name.iter().position(|b| 0 == *b).unwrap_or(NAME_LEN);
+        let name = core::str::from_utf8(&name[..name_len]).map_err(|_| ModuleError::InvalidName)?;
+        Ok(Self {
+            id,
+            name,
+            entry,
+            code,
+            max_input: read_u32(bytes, 20),
+            max_output: read_u32(bytes, 24),
+        })
+    }
+
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Returns the code of the task.
+    pub fn code(&self) -> &'static [u8] {
+        self.code
+    }
+
+    /// Returns the address of the entry point.
+    pub fn entry(&self) -> u64 {
+        self.code[self.entry..].as_ptr() as u64
+    }
+
+    /// Returns a descriptor to register the task with. Modules are loaded
+    /// once at boot, so the image is leaked.
+    pub fn into_descriptor(self) -> TaskDescriptor {
+        let image: &'static ModuleImage = Box::leak(Box::new(self));
+        TaskDescriptor {
+            id: image.id,
+            name: image.name,
+            create: Box::leak(Box::new(move |env| {
+                Box::new(ModuleTask { env, image }) as Box<dyn Task>
+            })),
+        }
+    }
+}
+
+/// Instance of a loaded task for one session.
+pub struct ModuleTask {
+    env: TaskEnv,
+    image: &'static ModuleImage,
+}
+
+impl Task for ModuleTask {
+    fn id(&self) -> TaskId {
+        self.image.id
+    }
+
+    fn name(&self) -> &'static str {
+        self.image.name
+    }
+
+    fn max_input_len(&self) -> usize {
+        self.image.max_input
+    }
+
+    fn max_output_len(&self) -> usize {
+        self.image.max_output
+    }
+
+    fn run(&mut self, input: &[u8], out: &mut Vec<u8>) -> Result<(), TaskError> {
+        let mut output = vec![0; self.image.max_output];
+        let written = self.env.platform().run_module(self.image, input, &mut output)?;
+        out.extend_from_slice(&output[..written]);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Returns a leaked module with the given header fields.
+    pub(crate) fn module(id: u16, name: &str, entry: u32, code: &[u8]) -> &'static [u8] {
+        let mut bytes = vec![0_u8; MODULE_HEADER_SIZE];
+        bytes[0..8].copy_from_slice(&MODULE_MAGIC);
+        bytes[8..10].copy_from_slice(&MODULE_VERSION.to_le_bytes());
+        bytes[10..12].copy_from_slice(&id.to_le_bytes());
+        bytes[12..16].copy_from_slice(&entry.to_le_bytes());
+        bytes[16..20].copy_from_slice(&(code.len() as u32).to_le_bytes());
+        bytes[20..24].copy_from_slice(&16_u32.to_le_bytes());
+        bytes[24..28].copy_from_slice(&8_u32.to_le_bytes());
+        bytes[32..32 + name.len()].copy_from_slice(name.as_bytes());
+        bytes.extend_from_slice(code);
+        Box::leak(bytes.into_boxed_slice())
+    }
+
+    #[test]
+    fn test_parse() {
+        let bytes = module(0x100, "hash", 1, &[0x90, 0xc3]);
+        let image = ModuleImage::parse(bytes).unwrap();
+        assert_eq!(image.id(), TaskId(0x100));
+        assert_eq!(image.name(), "hash");
+        assert_eq!(image.code(), [0x90, 0xc3]);
+        assert_eq!(image.entry(), bytes.as_ptr() as u64 + MODULE_HEADER_SIZE as u64 + 1);
+        assert_eq!(image.max_input, 16);
+        assert_eq!(image.max_output, 8);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let code = [0xc3];
+        assert_eq!(ModuleImage::parse(&[0; 8]).err(), Some(ModuleError::TooSmall));
+        assert_eq!(
+            ModuleImage::parse(&[0; MODULE_HEADER_SIZE]).err(),
+            Some(ModuleError::BadMagic)
+        );
+        assert_eq!(
+            ModuleImage::parse(module(0xffff, "x", 0, &code)).err(),
+            Some(ModuleError::InvalidId(TaskId::UNKNOWN))
+        );
+        assert_eq!(
+            ModuleImage::parse(module(1, "x", 1, &code)).err(),
+            Some(ModuleError::EntryOutOfBounds)
+        );
+
+        let truncated = module(1, "x", 0, &code);
+        let truncated = &truncated[..truncated.len() - 1];
+        assert_eq!(ModuleImage::parse(truncated).err(), Some(ModuleError::CodeOutOfBounds));
+
+        let bad_version = module(1, "x", 0, &code);
+        let mut bad_version = bad_version.to_vec();
+        bad_version[8] = 2;
+        assert_eq!(
+            ModuleImage::parse(Box::leak(bad_version.into_boxed_slice())).err(),
+            Some(ModuleError::UnsupportedVersion(2))
+        );
+
+        let bad_name = module(1, "\u{00e4}", 0, &code);
+        let mut bad_name = bad_name.to_vec();
+        bad_name[32] = 0xff;
+        assert_eq!(
+            ModuleImage::parse(Box::leak(bad_name.into_boxed_slice())).err(),
+            Some(ModuleError::InvalidName)
+        );
+    }
+}
@@ -0,0 +1,13 @@
+//! Module for the [`ping`] task.
+
+use log::info;
+
+use crate::state_machine::task::tee_task;
+use crate::state_machine::task_id::TaskId;
+
+/// Returns the input byte incremented by one. A missing byte counts as zero.
+#[tee_task(id = TaskId::PING, name = "ping")]
+pub fn ping(value: Option<u8>) -> u8 {
+    info!("Ping");
+    value.unwrap_or(0).wrapping_add(1)
+}
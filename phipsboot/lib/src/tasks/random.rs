@@ -0,0 +1,44 @@
+//! Module for the [`get_random`] task.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::state_machine::task::{tee_task, TaskEnv, TaskError};
+use crate::state_machine::task_id::TaskId;
+
+/// Maximum number of bytes one [`get_random`] request returns.
+pub const MAX_RANDOM_LEN: u16 = 1024;
+
+/// Returns `len` random bytes from the [`Drbg`](crate::random::Drbg) of the
+/// TEE.
+#[tee_task(id = TaskId::GET_RANDOM, name = "get_random", max_output = 1024)]
+pub fn get_random(env: &TaskEnv, len: u16) -> Result<Vec<u8>, TaskError> {
+    if len > MAX_RANDOM_LEN {
+        return Err(TaskError::InvalidInput);
+    }
+    let mut random = vec![0; len.into()];
+    env.fill_random(&mut random)?;
+    Ok(random)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared_mem_com::TeeCommand;
+    use crate::sim::Simulator;
+
+    #[test]
+    fn test_get_random() {
+        let mut sim = Simulator::default();
+        assert!(matches!(sim.call(TaskId::GET_RANDOM, &32_u16.to_le_bytes()), TeeCommand::TeeSend));
+        let a = sim.output();
+        assert_eq!(a.len(), 32);
+        assert!(matches!(sim.call(TaskId::GET_RANDOM, &32_u16.to_le_bytes()), TeeCommand::TeeSend));
+        assert_ne!(sim.output(), a);
+
+        assert!(matches!(sim.call(TaskId::GET_RANDOM, &MAX_RANDOM_LEN.to_le_bytes()), TeeCommand::TeeSend));
+        assert_eq!(sim.output().len(), MAX_RANDOM_LEN.into());
+        assert!(matches!(sim.call(TaskId::GET_RANDOM, &(MAX_RANDOM_LEN + 1).to_le_bytes()), TeeCommand::TeeError));
+        assert_eq!(sim.output(), [TaskError::InvalidInput as u8]);
+    }
+}
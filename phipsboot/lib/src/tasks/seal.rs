@@ -0,0 +1,41 @@
+//! Module for the [`seal`] and [`unseal`] tasks.
+//!
+//! Both fail with [`TaskError::ExecutionFailed`] if the TEE received no boot
+//! secret, see [`crate::seal`].
+
+use alloc::vec::Vec;
+
+use crate::seal::{SealPurpose, SealingKey};
+use crate::state_machine::session::SessionBuffer;
+use crate::state_machine::task::{tee_task, TaskEnv, TaskError};
+use crate::state_machine::task_id::TaskId;
+use crate::tasks::secrets::{Handle, SecretKind, Secrets};
+
+/// Returns the sealing key of the TEE for `purpose`. Fails if there is no boot
+/// secret.
+pub(crate) fn sealing_key(env: &TaskEnv, purpose: SealPurpose) -> Result<SealingKey, TaskError> {
+    SealingKey::from_platform(purpose, env.platform()).ok_or_else(|| {
+        log::info!("Cannot seal without a boot secret");
+        TaskError::ExecutionFailed
+    })
+}
+
+/// Seals `data` and returns the blob to the host.
+#[tee_task(id = TaskId::SEAL, name = "seal")]
+pub fn seal(env: &TaskEnv, data: Vec<u8>) -> Result<Vec<u8>, TaskError> {
+    Ok(sealing_key(env, SealPurpose::Data)?.seal(&data))
+}
+
+/// Unseals `blob` into the [`Secrets`] of the session. Returns the handle of
+/// the data; the plaintext never leaves the TEE.
+#[tee_task(id = TaskId::UNSEAL, name = "unseal")]
+pub fn unseal(env: &TaskEnv, blob: Vec<u8>) -> Result<Handle, TaskError> {
+    let key = sealing_key(env, SealPurpose::Data)?;
+    let len = SealingKey::data_len(&blob)?;
+    if 0 == len {
+        return Err(TaskError::InvalidInput);
+    }
+    let mut data = SessionBuffer::new(len, 1);
+    key.unseal(&blob, data.as_mut_slice())?;
+    env.state::<Secrets>().insert(SecretKind::Data, data)
+}
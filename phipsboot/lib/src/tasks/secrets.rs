@@ -0,0 +1,99 @@
+//! Secrets that tasks keep in enclave memory on behalf of a session.
+//!
+//! The host never sees a secret, it only receives a [`Handle`] to refer to it
+//! in later requests. Secrets are zeroed when they are removed or when the
+//! session is closed.
+
+use alloc::collections::BTreeMap;
+
+use crate::state_machine::session::SessionBuffer;
+use crate::state_machine::task::TaskError;
+
+/// Refers to a secret of a session.
+pub type Handle = u16;
+
+/// Maximum number of secrets a session can hold.
+pub const MAX_SECRETS: usize = 16;
+
+/// What a secret may be used for. A secret can only be used as its kind, so
+/// that, e.g., a signing key cannot be used as HMAC key.
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub enum SecretKind {
+    /// Unsealed data or a symmetric key.
+    Data,
+    /// Seed of an Ed25519 signing key.
+    Ed25519,
+}
+
+/// Secrets of a session. Use it via
+/// [`TaskEnv::state`](crate::state_machine::task::TaskEnv::state).
+#[derive(Debug, Default)]
+pub struct Secrets {
+    entries: BTreeMap<Handle, (SecretKind, SessionBuffer)>,
+}
+
+impl Secrets {
+    /// Takes ownership of `secret` and returns its handle. Fails if the
+    /// session already holds [`MAX_SECRETS`].
+    pub fn insert(&mut self, kind: SecretKind, secret: SessionBuffer) -> Result<Handle, TaskError> {
+        if self.entries.len() >= MAX_SECRETS {
+            return Err(TaskError::ExecutionFailed);
+        }
+        // Reuse the lowest free handle
+        let handle = (0..=Handle::MAX)
+            .find(|handle| false == self.entries.contains_key(handle))
+            .unwrap();
+        self.entries.insert(handle, (kind, secret));
+        Ok(handle)
+    }
+
+    /// Returns the secret `handle` refers to if it is of kind `kind`.
+    pub fn get(&self, handle: Handle, kind: SecretKind) -> Result<&[u8], TaskError> {
+        match self.entries.get(&handle) {
+            Some((entry_kind, secret)) if kind == *entry_kind => Ok(secret.as_slice()),
+            _ => Err(TaskError::InvalidInput),
+        }
+    }
+
+    /// Zeroes and removes the secret `handle` refers to.
+    pub fn remove(&mut self, handle: Handle) -> Result<(), TaskError> {
+        self.entries.remove(&handle).map(drop).ok_or(TaskError::InvalidInput)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secret(value: u8) -> SessionBuffer {
+        let mut buffer = SessionBuffer::new(4, 1);
+        buffer.as_mut_slice().fill(value);
+        buffer
+    }
+
+    #[test]
+    fn test_secrets() {
+        let mut secrets = Secrets::default();
+        assert_eq!(secrets.insert(SecretKind::Data, secret(1)), Ok(0));
+        assert_eq!(secrets.insert(SecretKind::Ed25519, secret(2)), Ok(1));
+        assert_eq!(secrets.get(1, SecretKind::Ed25519), Ok(&[2; 4][..]));
+        assert_eq!(secrets.get(1, SecretKind::Data), Err(TaskError::InvalidInput));
+        assert_eq!(secrets.remove(0), Ok(()));
+        assert_eq!(secrets.get(0, SecretKind::Data), Err(TaskError::InvalidInput));
+        assert_eq!(secrets.remove(0), Err(TaskError::InvalidInput));
+        assert_eq!(secrets.insert(SecretKind::Data, secret(3)), Ok(0));
+
+        while secrets.len() < MAX_SECRETS {
+            secrets.insert(SecretKind::Data, secret(4)).unwrap();
+        }
+        assert_eq!(secrets.insert(SecretKind::Data, secret(5)), Err(TaskError::ExecutionFailed));
+    }
+}
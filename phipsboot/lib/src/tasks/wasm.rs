@@ -0,0 +1,243 @@
+//! Module for the [`wasm`] task.
+//!
+//! The task interprets a WebAssembly module that the host sends along with its
+//! arguments, so new workloads do not require a rebuild of the firmware. The
+//! interpreter, `wasmi`, keeps all of its state on the heap of the TEE.
+//!
+//! The module must export its `memory` and a function `run() -> i32`. A
+//! negative return value signals failure. The only imports are:
+//!
+//! - `env.read_input(ptr: i32, len: i32, offset: i32) -> i32`: Copies up to
+//!   `len` bytes of the arguments, starting at `offset`, to `ptr`. Returns the
+//!   number of bytes copied.
+//! - `env.write_output(ptr: i32, len: i32) -> i32`: Appends `len` bytes at
+//!   `ptr` to the output. Returns `len`.
+//!
+//! Both return -1 on failure. The execution is bounded by [`FUEL`]. The heap
+//! usage is bounded by [`MAX_MODULE`], [`MAX_MEMORY`], [`MAX_STACK_VALUES`]
+//! and [`MAX_TABLE_ELEMENTS`], so that a module fits the heap of the firmware.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use wasmi::{
+    Caller, Config, Engine, Extern, Linker, Memory, Module, StackLimits, Store, StoreLimits, StoreLimitsBuilder,
+};
+
+use crate::state_machine::task::{tee_task, TaskError};
+use crate::state_machine::task_id::TaskId;
+
+/// Units of fuel a module may consume. Most instructions consume one unit.
+pub const FUEL: u64 = 10_000_000;
+/// Maximum size of a module. wasmi keeps it and its translated code on the
+/// heap.
+pub const MAX_MODULE: usize = 0x1000;
+/// Maximum size of the linear memory, i.e., one Wasm page.
+pub const MAX_MEMORY: usize = 0x10000;
+/// Maximum number of values on the stack of the interpreter, 8 bytes each.
+pub const MAX_STACK_VALUES: usize = 0x400;
+/// Maximum number of nested calls.
+pub const MAX_CALL_DEPTH: usize = 64;
+/// Maximum number of elements of the table.
+pub const MAX_TABLE_ELEMENTS: u32 = 64;
+/// Maximum size of the output of a module.
+pub const MAX_OUTPUT: usize = 0x1000;
+
+/// State of the host functions.
+struct Guest {
+    args: Vec<u8>,
+    output: Vec<u8>,
+    limits: StoreLimits,
+}
+
+fn memory(caller: &Caller<'_, Guest>) -> Option<Memory> {
+    caller.get_export("memory").and_then(Extern::into_memory)
+}
+
+fn read_input(mut caller: Caller<'_, Guest>, ptr: i32, len: i32, offset: i32) -> Option<i32> {
+    let memory = memory(&caller)?;
+    let rest = caller.data().args.get(usize::try_from(offset).ok()?..)?;
+    let len = rest.len().min(usize::try_from(len).ok()?);
+    let chunk = rest[..len].to_vec();
+    memory.write(&mut caller, usize::try_from(ptr).ok()?, &chunk).ok()?;
+    Some(len as i32)
+}
+
+fn write_output(mut caller: Caller<'_, Guest>, ptr: i32, len: i32) -> Option<i32> {
+    let memory = memory(&caller)?;
+    let len_bytes = usize::try_from(len).ok()?;
+    // Check before allocating, the length is up to the module
+    if caller.data().output.len().checked_add(len_bytes)? > MAX_OUTPUT {
+        return None;
+    }
+    let mut chunk = vec![0; len_bytes];
+    memory.read(&caller, usize::try_from(ptr).ok()?, &mut chunk).ok()?;
+    caller.data_mut().output.extend_from_slice(&chunk);
+    Some(len)
+}
+
+/// Runs `run` of the Wasm module in `module`. Returns the output of the
+/// module.
+pub fn run(module: &[u8], args: Vec<u8>) -> Result<Vec<u8>, TaskError> {
+    if module.len() > MAX_MODULE {
+        return Err(TaskError::InvalidInput);
+    }
+    let mut config = Config::default();
+    config.consume_fuel(true);
+    // Only fails if the initial height exceeds the maximum
+    config.set_stack_limits(StackLimits::new(MAX_STACK_VALUES / 4, MAX_STACK_VALUES, MAX_CALL_DEPTH).unwrap());
+    config.set_cached_stacks(0);
+    let engine = Engine::new(&config);
+    let module = Module::new(&engine, module).map_err(|_| TaskError::InvalidInput)?;
+
+    let limits = StoreLimitsBuilder::new()
+        .memory_size(MAX_MEMORY)
+        .instances(1)
+        .memories(1)
+        .tables(1)
+        .table_elements(MAX_TABLE_ELEMENTS)
+        .build();
+    let mut store = Store::new(&engine, Guest { args, output: Vec::new(), limits });
+    store.limiter(|guest| &mut guest.limits);
+    // Only fails if fuel metering is disabled
+    store.add_fuel(FUEL).unwrap();
+
+    let mut linker = Linker::<Guest>::new(&engine);
+    linker
+        .func_wrap("env", "read_input", |caller: Caller<'_, Guest>, ptr: i32, len: i32, offset: i32| {
+            read_input(caller, ptr, len, offset).unwrap_or(-1)
+        })
+        .unwrap();
+    linker
+        .func_wrap("env", "write_output", |caller: Caller<'_, Guest>, ptr: i32, len: i32| {
+            write_output(caller, ptr, len).unwrap_or(-1)
+        })
+        .unwrap();
+
+    // Unknown imports, exceeded limits and a missing `run` are the fault of
+    // the module
+    let instance = linker
+        .instantiate(&mut store, &module)
+        .and_then(|instance| instance.start(&mut store))
+        .map_err(|_| TaskError::InvalidInput)?;
+    let run = instance
+        .get_typed_func::<(), i32>(&store, "run")
+        .map_err(|_| TaskError::InvalidInput)?;
+    match run.call(&mut store, ()) {
+        Ok(status) if status >= 0 => Ok(store.into_data().output),
+        Ok(status) => {
+            log::info!("Wasm module failed with status {}", status);
+            Err(TaskError::ExecutionFailed)
+        }
+        Err(e) => {
+            log::info!("Wasm module trapped: {:?}", e);
+            Err(TaskError::ExecutionFailed)
+        }
+    }
+}
+
+/// Runs the Wasm module in the first `module_len` bytes of `payload` with the
+/// rest of `payload` as arguments.
+// `max_output` is `MAX_OUTPUT`
+#[tee_task(id = TaskId::WASM, name = "wasm", max_output = 0x1000)]
+pub fn wasm(module_len: u32, mut payload: Vec<u8>) -> Result<Vec<u8>, TaskError> {
+    let module_len = module_len as usize;
+    if module_len > payload.len() {
+        return Err(TaskError::InvalidInput);
+    }
+    let args = payload.split_off(module_len);
+    run(&payload, args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes what it reads from the first 64 bytes of the arguments.
+    const ECHO: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // magic, version
+        // types: (i32 i32 i32) -> i32, (i32 i32) -> i32, () -> i32
+        0x01, 0x12, 0x03, 0x60, 0x03, 0x7f, 0x7f, 0x7f, 0x01, 0x7f, 0x60, 0x02, 0x7f, 0x7f, 0x01, 0x7f, 0x60, 0x00,
+        0x01, 0x7f,
+        // imports: env.read_input, env.write_output
+        0x02, 0x25, 0x02, 0x03, b'e', b'n', b'v', 0x0a, b'r', b'e', b'a', b'd', b'_', b'i', b'n', b'p', b'u', b't',
+        0x00, 0x00, 0x03, b'e', b'n', b'v', 0x0c, b'w', b'r', b'i', b't', b'e', b'_', b'o', b'u', b't', b'p', b'u',
+        b't', 0x00, 0x01,
+        // functions, memory of one page
+        0x03, 0x02, 0x01, 0x02, 0x05, 0x03, 0x01, 0x00, 0x01,
+        // exports: memory, run
+        0x07, 0x10, 0x02, 0x06, b'm', b'e', b'm', b'o', b'r', b'y', 0x02, 0x00, 0x03, b'r', b'u', b'n', 0x00, 0x02,
+        // write_output(0, read_input(0, 64, 0))
+        0x0a, 0x11, 0x01, 0x0f, 0x00, 0x41, 0x00, 0x41, 0x00, 0x41, 0xc0, 0x00, 0x41, 0x00, 0x10, 0x00, 0x10, 0x01,
+        0x0b,
+    ];
+
+    /// Loops forever.
+    const LOOP: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // magic, version
+        0x01, 0x05, 0x01, 0x60, 0x00, 0x01, 0x7f, // types: () -> i32
+        0x03, 0x02, 0x01, 0x00, 0x05, 0x03, 0x01, 0x00, 0x01, // functions, memory
+        0x07, 0x10, 0x02, 0x06, b'm', b'e', b'm', b'o', b'r', b'y', 0x02, 0x00, 0x03, b'r', b'u', b'n', 0x00, 0x00,
+        0x0a, 0x0b, 0x01, 0x09, 0x00, 0x03, 0x40, 0x0c, 0x00, 0x0b, 0x41, 0x00, 0x0b, // loop br 0 end, i32.const 0
+    ];
+
+    /// Returns `write_output(0, i32::MAX)`.
+    const HUGE_OUTPUT: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // magic, version
+        // types: (i32 i32) -> i32, () -> i32
+        0x01, 0x0b, 0x02, 0x60, 0x02, 0x7f, 0x7f, 0x01, 0x7f, 0x60, 0x00, 0x01, 0x7f,
+        // imports: env.write_output
+        0x02, 0x14, 0x01, 0x03, b'e', b'n', b'v', 0x0c, b'w', b'r', b'i', b't', b'e', b'_', b'o', b'u', b't', b'p',
+        b'u', b't', 0x00, 0x00,
+        // functions, memory of one page
+        0x03, 0x02, 0x01, 0x01, 0x05, 0x03, 0x01, 0x00, 0x01,
+        // exports: memory, run
+        0x07, 0x10, 0x02, 0x06, b'm', b'e', b'm', b'o', b'r', b'y', 0x02, 0x00, 0x03, b'r', b'u', b'n', 0x00, 0x01,
+        0x0a, 0x0e, 0x01, 0x0c, 0x00, 0x41, 0x00, 0x41, 0xff, 0xff, 0xff, 0xff, 0x07, 0x10, 0x00, 0x0b,
+    ];
+
+    fn payload(module: &[u8], args: &[u8]) -> (u32, Vec<u8>) {
+        (module.len() as u32, [module, args].concat())
+    }
+
+    #[test]
+    fn test_echo() {
+        let (len, payload) = payload(ECHO, b"hello");
+        assert_eq!(wasm(len, payload), Ok(b"hello".to_vec()));
+    }
+
+    #[test]
+    fn test_fuel() {
+        let (len, payload) = payload(LOOP, &[]);
+        assert_eq!(wasm(len, payload), Err(TaskError::ExecutionFailed));
+    }
+
+    #[test]
+    fn test_huge_output() {
+        // Rejected before the output is allocated
+        let (len, payload) = payload(HUGE_OUTPUT, &[]);
+        assert_eq!(wasm(len, payload), Err(TaskError::ExecutionFailed));
+    }
+
+    #[test]
+    fn test_module_too_large() {
+        let mut module = ECHO.to_vec();
+        // Custom section with an empty name that pads the module to one byte
+        // above the limit. Its size takes two bytes.
+        let size = MAX_MODULE + 1 - module.len() - 3;
+        module.extend_from_slice(&[0x00, 0x80 | (size as u8 & 0x7f), (size >> 7) as u8, 0x00]);
+        module.resize(MAX_MODULE + 1, 0);
+        let (len, input) = payload(&module, b"hello");
+        assert_eq!(wasm(len, input), Err(TaskError::InvalidInput));
+
+        module.truncate(MAX_MODULE);
+        module[ECHO.len() + 1] -= 1;
+        let (len, input) = payload(&module, b"hello");
+        assert_eq!(wasm(len, input), Ok(b"hello".to_vec()));
+    }
+
+    #[test]
+    fn test_invalid_module() {
+        assert_eq!(wasm(4, b"\0asm".to_vec()), Err(TaskError::InvalidInput));
+        assert_eq!(wasm(8, ECHO[..4].to_vec()), Err(TaskError::InvalidInput));
+    }
+}
@@ -0,0 +1,246 @@
+//! Procedural macros of the TEE.
+//!
+//! See [`macro@tee_task`].
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote, quote_spanned};
+use syn::spanned::Spanned;
+use syn::{parse_macro_input, Error, Expr, ExprLit, ExprPath, FnArg, ItemFn, Lit, LitInt, LitStr, ReturnType, Type};
+
+/// Task ID that is reserved for "no task".
+const UNKNOWN_TASK_ID: u16 = 0xffff;
+
+/// ID of a task as given to [`macro@tee_task`].
+enum TaskIdArg {
+    /// Integer literal, for tasks without a `TaskId` constant.
+    Literal(u16, Span),
+    /// Path to a `TaskId` constant, e.g. `TaskId::SHA256`.
+    Path(ExprPath),
+}
+
+/// Arguments of [`macro@tee_task`].
+#[derive(Default)]
+struct TaskArgs {
+    id: Option<TaskIdArg>,
+    name: Option<LitStr>,
+    max_input: Option<LitInt>,
+    max_output: Option<LitInt>,
+}
+
+impl TaskArgs {
+    fn parse(&mut self, meta: syn::meta::ParseNestedMeta) -> syn::Result<()> {
+        if meta.path.is_ident("id") {
+            self.id = Some(match meta.value()?.parse()? {
+                Expr::Lit(ExprLit { lit: Lit::Int(lit), .. }) => TaskIdArg::Literal(lit.base10_parse()?, lit.span()),
+                Expr::Path(path) => TaskIdArg::Path(path),
+                expr => return Err(Error::new(expr.span(), "expected an integer or a `TaskId` constant")),
+            });
+        } else if meta.path.is_ident("name") {
+            self.name = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("max_input") {
+            self.max_input = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("max_output") {
+            self.max_output = Some(meta.value()?.parse()?);
+        } else {
+            return Err(meta.error("expected `id`, `name`, `max_input` or `max_output`"));
+        }
+        Ok(())
+    }
+}
+
+/// Returns `true` if `ty` is `&TaskEnv`.
+fn is_env(ty: &Type) -> bool {
+    match ty {
+        Type::Reference(reference) => match &*reference.elem {
+            Type::Path(path) => path
+                .path
+                .segments
+                .last()
+                .is_some_and(|segment| segment.ident == "TaskEnv"),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Returns the `T` of a return type of the form `Result<T, _>`.
+fn result_ok_type(ty: &Type) -> Option<&Type> {
+    let Type::Path(path) = ty else { return None };
+    let segment = path.path.segments.last()?;
+    if segment.ident != "Result" {
+        return None;
+    }
+    let syn::PathArguments::AngleBracketed(args) = &segment.arguments else { return None };
+    match args.args.first()? {
+        syn::GenericArgument::Type(ty) => Some(ty),
+        _ => None,
+    }
+}
+
+fn expand(args: TaskArgs, function: ItemFn) -> syn::Result<TokenStream2> {
+    let lib = quote!(::lib::state_machine);
+    // Two tasks with the same ID export the same symbol. This fails to compile
+    // within a crate and to link across crates. IDs given as literals must
+    // not be those of built-in tasks, which are claimed by their name.
+    let (id, claim) = match args.id.ok_or_else(|| Error::new(Span::call_site(), "missing `id`"))? {
+        TaskIdArg::Literal(id, span) => {
+            if UNKNOWN_TASK_ID == id {
+                return Err(Error::new(span, "task ID 0xffff is reserved"));
+            }
+            let id_symbol = format!("__tee_task_id_{:04x}", id);
+            let builtin = quote_spanned! {span=>
+                const _: () = assert!(
+                    false == #lib::task_id::TaskId(#id).is_builtin(),
+                    "the ID belongs to a built-in task, use its `TaskId` constant",
+                );
+            };
+            let claim = quote! {
+                #builtin
+
+                #[used]
+                #[export_name = #id_symbol]
+                static ID_CLAIM: u8 = 0;
+            };
+            (quote!(#lib::task_id::TaskId(#id)), claim)
+        }
+        TaskIdArg::Path(path) => {
+            let name = &path.path.segments.last().expect("paths are never empty").ident;
+            (quote!(#path), quote!(::lib::claim_task_id!(#name);))
+        }
+    };
+    let name = args
+        .name
+        .ok_or_else(|| Error::new(Span::call_site(), "missing `name`"))?;
+    if let Some(generics) = function.sig.generics.lt_token {
+        return Err(Error::new(generics.span(), "tasks must not be generic"));
+    }
+
+    let function_name = &function.sig.ident;
+
+    // Decode all arguments except the environment from the input
+    let mut call_args = Vec::new();
+    let mut decode = Vec::new();
+    let mut input_types = Vec::new();
+    for (index, arg) in function.sig.inputs.iter().enumerate() {
+        let FnArg::Typed(arg) = arg else {
+            return Err(Error::new(arg.span(), "tasks must be free functions"));
+        };
+        if 0 == index && is_env(&arg.ty) {
+            call_args.push(quote!(&self.env));
+            continue;
+        }
+        let ty = &arg.ty;
+        let var = format_ident!("arg{}", index);
+        decode.push(quote! {
+            let #var = <#ty as #lib::task_codec::TaskInput>::decode(&mut input)?;
+        });
+        call_args.push(quote!(#var));
+        input_types.push(ty);
+    }
+
+    let (call, output_type) = match &function.sig.output {
+        ReturnType::Default => (quote!(#function_name(#(#call_args),*)), quote!(())),
+        ReturnType::Type(_, ty) => match result_ok_type(ty) {
+            Some(ok) => (quote!(#function_name(#(#call_args),*)?), quote!(#ok)),
+            None => (quote!(#function_name(#(#call_args),*)), quote!(#ty)),
+        },
+    };
+
+    let max_input = match args.max_input {
+        Some(max) => quote!(#max),
+        None => quote!(0_usize #(.saturating_add(<#input_types as #lib::task_codec::TaskInput>::MAX_LEN))*),
+    };
+    let max_output = match args.max_output {
+        Some(max) => quote!(#max),
+        None => quote!(<#output_type as #lib::task_codec::TaskOutput>::MAX_LEN),
+    };
+
+    Ok(quote! {
+        #function
+
+        const _: () = {
+            struct TeeTask {
+                #[allow(dead_code)]
+                env: #lib::task::TaskEnv,
+            }
+
+            impl #lib::task::Task for TeeTask {
+                fn id(&self) -> #lib::task_id::TaskId {
+                    #id
+                }
+
+                fn name(&self) -> &'static str {
+                    #name
+                }
+
+                fn max_input_len(&self) -> usize {
+                    #max_input
+                }
+
+                fn max_output_len(&self) -> usize {
+                    #max_output
+                }
+
+                fn run(
+                    &mut self,
+                    input: &[u8],
+                    out: &mut #lib::task::__private::Vec<u8>,
+                ) -> ::core::result::Result<(), #lib::task::TaskError> {
+                    let mut input = input;
+                    #(#decode)*
+                    if false == input.is_empty() {
+                        return Err(#lib::task::TaskError::InvalidInput);
+                    }
+                    let result: #output_type = #call;
+                    #lib::task_codec::TaskOutput::encode(result, out);
+                    Ok(())
+                }
+            }
+
+            #[used]
+            #[link_section = "tee_tasks"]
+            static DESCRIPTOR: #lib::task::TaskDescriptor = #lib::task::TaskDescriptor {
+                id: #id,
+                name: #name,
+                create: &|env| #lib::task::__private::Box::new(TeeTask { env }),
+            };
+
+            #claim
+        };
+    })
+}
+
+/// Declares a function as a task and registers it.
+///
+/// ```ignore
+/// #[tee_task(id = 0x7f00, name = "add")]
+/// fn add(a: u32, b: u32) -> Result<u32, TaskError> {
+///     a.checked_add(b).ok_or(TaskError::InvalidInput)
+/// }
+/// ```
+///
+/// The arguments are decoded from the input in declaration order via
+/// `TaskInput`; surplus input is rejected. The return value, or the `Ok` value
+/// of a `Result`, is encoded into the output via `TaskOutput`. A first argument
+/// of type `&TaskEnv` receives the environment of the task instead.
+///
+/// The input and output limits are derived from the types. They can be set
+/// explicitly with `max_input = ..` and `max_output = ..`.
+///
+/// The ID is either an integer or the path to a `TaskId` constant. The
+/// built-in tasks must use their constant, e.g. `id = TaskId::SHA256`.
+///
+/// The task is placed in the `tee_tasks` linker section, from where the
+/// `TaskRegistry` picks it up. Declaring two tasks with the same ID fails to
+/// compile or to link.
+#[proc_macro_attribute]
+pub fn tee_task(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let mut args = TaskArgs::default();
+    let parser = syn::meta::parser(|meta| args.parse(meta));
+    parse_macro_input!(attr with parser);
+    let function = parse_macro_input!(item as ItemFn);
+    expand(args, function)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}